@@ -1,16 +1,44 @@
 use std::collections::{vec_deque, HashMap, HashSet, VecDeque};
 
-use glam::{uvec3, IVec3, UVec3};
+use glam::{uvec3, IVec3, UVec3, Vec3};
 
-use crate::scene::MaterialId;
+use crate::scene::{MaterialId, Scene};
 use crate::tensor::SparseTensorChunk;
 
 pub struct Brain {
     pub position: UVec3,
     pub route: Vec<UVec3>,
+    /// The direction the brain is looking in, which must be normalized.
+    pub facing: Vec3,
 }
 
 impl Brain {
+    /// The center of the voxel the brain is in, which is where it sees from.
+    pub fn eye(&self) -> Vec3 {
+        self.position.as_vec3() + 0.5
+    }
+
+    /// Whether `target` is within `range` of the brain, inside the cone of
+    /// `cone_deg` degrees around the facing direction, and not hidden behind
+    /// terrain.
+    pub fn can_see(&self, target: Vec3, cone_deg: f32, range: f32, scene: &Scene) -> bool {
+        let to_target = target - self.eye();
+        let distance = to_target.length();
+        if distance > range {
+            return false;
+        }
+
+        // A target inside the eye is always seen.
+        if distance > 0.0 {
+            let cos_half_cone = (cone_deg.to_radians() / 2.0).cos();
+            if self.facing.dot(to_target / distance) < cos_half_cone {
+                return false;
+            }
+        }
+
+        scene.has_line_of_sight(self.eye(), target)
+    }
+
     // Dijkstra path finding (breadth first search)
     // The algorithm will spin forever, if there is no path.
     pub fn append_destination(&mut self, dest: UVec3, scene: &Vec<SparseTensorChunk>) {
@@ -88,6 +116,7 @@ fn straight() {
     let mut thing = Brain {
         position: UVec3 { x: 0, y: 0, z: 0 },
         route: vec![],
+        facing: Vec3::X,
     };
 
     let mut env = SparseTensorChunk::nothing(uvec3(4, 4, 4));
@@ -106,3 +135,22 @@ fn straight() {
         ]
     )
 }
+
+#[test]
+fn view_cone() {
+    let scene = Scene::empty();
+    let brain = Brain {
+        position: uvec3(10, 10, 0),
+        route: vec![],
+        facing: Vec3::X,
+    };
+
+    let ahead = brain.eye() + Vec3::new(5.0, 1.0, 0.0);
+    let behind = brain.eye() - Vec3::new(5.0, 1.0, 0.0);
+    assert!(brain.can_see(ahead, 90.0, 20.0, &scene));
+    assert!(scene.has_line_of_sight(brain.eye(), behind));
+    assert!(!brain.can_see(behind, 90.0, 20.0, &scene));
+
+    // Too far away, even straight ahead.
+    assert!(!brain.can_see(ahead, 90.0, 4.0, &scene));
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use crate::format::fnt::{self, FontFace};
+use crate::format::vox::{self, VoxMaterial, VoxModel};
+use crate::scene::Model;
+use crate::tensor::SparseTensorChunk;
+
+/// The models and palette of a parsed .vox file.
+pub type VoxFile = (Vec<VoxModel>, Box<[VoxMaterial; 256]>);
+
+/// Caches loaded assets by path, so every file is only parsed once and every
+/// object spawned from the same file shares one copy of its geometry.
+#[derive(Debug, Default)]
+pub struct AssetManager {
+    vox: HashMap<PathBuf, Rc<VoxFile>>,
+    models: HashMap<PathBuf, Vec<Rc<Model>>>,
+    chunks: HashMap<PathBuf, Rc<SparseTensorChunk>>,
+    fonts: HashMap<PathBuf, Rc<FontFace>>,
+}
+
+impl AssetManager {
+    /// Parses the .vox file at `path`, or returns the file parsed earlier from
+    /// the same path.
+    pub fn load_vox(&mut self, path: impl AsRef<Path>) -> Rc<VoxFile> {
+        let path = Self::normalize(path.as_ref());
+        let file = self
+            .vox
+            .entry(path)
+            .or_insert_with_key(|path| Rc::new(vox::open(path)));
+
+        Rc::clone(file)
+    }
+
+    /// Loads the first model of the .vox file at `path`, or returns the model
+    /// loaded earlier from the same path.
+    pub fn load_model(&mut self, path: impl AsRef<Path>) -> Rc<Model> {
+        Rc::clone(&self.load_frames(path)[0])
+    }
+
+    /// Loads every model of the .vox file at `path` in order, which are the
+    /// frames of an animation, or returns the models loaded earlier from the
+    /// same path.
+    pub fn load_frames(&mut self, path: impl AsRef<Path>) -> Vec<Rc<Model>> {
+        let path = Self::normalize(path.as_ref());
+        if let Some(frames) = self.models.get(&path) {
+            return frames.clone();
+        }
+
+        let (models, _) = &*self.load_vox(&path);
+        let frames: Vec<_> = models
+            .iter()
+            .map(|model| Rc::new(Model::from(model.clone())))
+            .collect();
+        self.models.insert(path, frames.clone());
+        frames
+    }
+
+    /// Builds a chunk from the first model of the .vox file at `path`, or
+    /// returns the chunk built earlier from the same path.
+    pub fn load_chunk(&mut self, path: impl AsRef<Path>) -> Rc<SparseTensorChunk> {
+        let path = Self::normalize(path.as_ref());
+        if let Some(chunk) = self.chunks.get(&path) {
+            return Rc::clone(chunk);
+        }
+
+        let model = self.load_model(&path);
+        let chunk = Rc::new(SparseTensorChunk::from(Model::clone(&model)));
+        self.chunks.insert(path, Rc::clone(&chunk));
+        chunk
+    }
+
+    /// Parses the .fnt file at `path`, or returns the font parsed earlier from
+    /// the same path.
+    pub fn load_font(&mut self, path: impl AsRef<Path>) -> Rc<FontFace> {
+        let path = Self::normalize(path.as_ref());
+        let font = self
+            .fonts
+            .entry(path)
+            .or_insert_with_key(|path| Rc::new(fnt::parse(&std::fs::read(path).unwrap())));
+
+        Rc::clone(font)
+    }
+
+    /// The number of distinct .vox files parsed so far.
+    #[cfg(test)]
+    pub fn parsed_vox_files(&self) -> usize {
+        self.vox.len()
+    }
+
+    /// Drops `.` components, so `./assets/a.vox` and `assets/a.vox` are cached
+    /// as the same file.
+    fn normalize(path: &Path) -> PathBuf {
+        path.components()
+            .filter(|component| *component != Component::CurDir)
+            .collect()
+    }
+}
+
+#[test]
+fn cached_by_path() {
+    let mut assets = AssetManager::default();
+
+    let a = assets.load_vox("./assets/knife.vox");
+    let b = assets.load_vox("assets/knife.vox");
+    assert!(Rc::ptr_eq(&a, &b));
+    assert_eq!(assets.vox.len(), 1);
+
+    // Models are built from the cached file instead of parsing it again.
+    let model = assets.load_model("assets/knife.vox");
+    assert!(Rc::ptr_eq(&model, &assets.load_model("./assets/knife.vox")));
+    assert!(Rc::ptr_eq(
+        &model,
+        &assets.load_frames("assets/knife.vox")[0]
+    ));
+    assert_eq!(assets.vox.len(), 1);
+
+    let font = assets.load_font("assets/fonts/sans-serif/sans-serif.fnt");
+    assert!(Rc::ptr_eq(
+        &font,
+        &assets.load_font("./assets/fonts/sans-serif/sans-serif.fnt")
+    ));
+}
@@ -0,0 +1,75 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// Measures the duration of frames and keeps a rolling average of the most
+/// recent ones, which is steadier than the duration of a single frame.
+pub struct Clock {
+    last_tick: Instant,
+    frame_times: VecDeque<f32>,
+    window: usize,
+}
+
+impl Clock {
+    /// Averages over the last `window` frames.
+    pub fn new(window: usize) -> Self {
+        assert!(window > 0);
+
+        Self {
+            last_tick: Instant::now(),
+            frame_times: VecDeque::with_capacity(window),
+            window,
+        }
+    }
+
+    /// Marks the end of a frame and returns its duration in seconds.
+    pub fn tick(&mut self) -> f32 {
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_tick).as_secs_f32();
+        self.last_tick = now;
+
+        self.record(dt);
+        dt
+    }
+
+    fn record(&mut self, dt: f32) {
+        if self.frame_times.len() == self.window {
+            self.frame_times.pop_front();
+        }
+
+        self.frame_times.push_back(dt);
+    }
+
+    /// The average duration of the recent frames in seconds.
+    pub fn average_frame_time(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+
+        self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32
+    }
+
+    /// The average frames per second over the recent frames.
+    pub fn fps(&self) -> f32 {
+        match self.average_frame_time() {
+            time if time > 0.0 => 1.0 / time,
+            _ => 0.0,
+        }
+    }
+}
+
+#[test]
+fn rolling_average() {
+    let mut clock = Clock::new(4);
+    assert_eq!(clock.fps(), 0.0);
+
+    for dt in [0.5, 0.01, 0.02, 0.03, 0.04] {
+        clock.record(dt);
+    }
+
+    // The first frame has fallen out of the window.
+    assert!((clock.average_frame_time() - 0.025).abs() < 1e-6);
+    assert!((clock.fps() - 40.0).abs() < 1e-3);
+
+    clock.record(0.01);
+    assert!((clock.average_frame_time() - 0.025).abs() < 1e-6);
+}
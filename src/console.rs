@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use glam::{uvec2, vec4, UVec2};
+
+use crate::scene::Text;
+
+/// A command line for debugging the game. A command is a name followed by
+/// arguments separated by whitespace, and runs the handler registered under
+/// the name.
+///
+/// The console doesn't know what its handlers operate on. It only finds the
+/// handler, which the caller of [`Console::submit`] then calls with whatever
+/// state it has at hand.
+pub struct Console<H> {
+    commands: HashMap<String, H>,
+    /// The line being typed.
+    pub input: String,
+    /// Whether the console is shown and takes the keyboard.
+    pub open: bool,
+    /// The commands that were run and their errors, oldest first.
+    log: Vec<String>,
+}
+
+impl<H: Copy> Console<H> {
+    /// The amount of lines of the log shown above the input line.
+    const SHOWN_LINES: usize = 8;
+    /// The height in pixels of a line of text.
+    const LINE_HEIGHT: u32 = 36;
+    const TEXT_SCALE: f32 = 0.5;
+
+    pub fn new() -> Self {
+        Self {
+            commands: HashMap::new(),
+            input: String::new(),
+            open: false,
+            log: Vec::new(),
+        }
+    }
+
+    pub fn register(&mut self, name: &str, handler: H) {
+        self.commands.insert(name.to_string(), handler);
+    }
+
+    /// Splits `line` into the handler registered for its first word and the
+    /// remaining words.
+    pub fn parse<'l>(&self, line: &'l str) -> Result<(H, Vec<&'l str>), String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().ok_or("no command given")?;
+        let handler = self
+            .commands
+            .get(name)
+            .ok_or_else(|| format!("unknown command `{name}`"))?;
+        Ok((*handler, words.collect()))
+    }
+
+    /// Runs the input line, where `run` calls the handler with the arguments,
+    /// and logs the line together with any error.
+    pub fn submit(&mut self, run: impl FnOnce(H, &[&str]) -> Result<(), String>) {
+        let line = std::mem::take(&mut self.input);
+        let result = self
+            .parse(&line)
+            .and_then(|(handler, args)| run(handler, &args));
+
+        self.log.push(format!("> {line}"));
+        if let Err(error) = result {
+            self.log.push(error);
+        }
+    }
+
+    pub fn log(&self) -> &[String] {
+        &self.log
+    }
+
+    /// The input line at `position` and the end of the log above it, ready to
+    /// be added to the text of the scene.
+    pub fn texts(&self, position: UVec2) -> Vec<Text> {
+        let shown = self.log.iter().rev().take(Self::SHOWN_LINES);
+        let lines = std::iter::once(format!("> {}_", self.input)).chain(shown.cloned());
+
+        lines
+            .enumerate()
+            .map(|(i, line)| Text {
+                scale: Self::TEXT_SCALE,
+                layer: 2,
+                outline: true,
+                ..Text::with_color(
+                    position + uvec2(0, i as u32 * Self::LINE_HEIGHT),
+                    line,
+                    vec4(1.0, 1.0, 1.0, 1.0),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Parses exactly `N` arguments of a command.
+pub fn parse_args<T: FromStr, const N: usize>(args: &[&str]) -> Result<[T; N], String> {
+    if args.len() != N {
+        return Err(format!("expected {N} arguments, got {}", args.len()));
+    }
+
+    let mut parsed = Vec::with_capacity(N);
+    for arg in args {
+        parsed.push(
+            arg.parse()
+                .map_err(|_| format!("invalid argument `{arg}`"))?,
+        );
+    }
+    Ok(parsed
+        .try_into()
+        .unwrap_or_else(|_| unreachable!("the amount of arguments was checked")))
+}
+
+#[test]
+fn dispatch_commands() {
+    type Spawn = fn(&mut Vec<[u32; 3]>, &[&str]) -> Result<(), String>;
+
+    let mut console: Console<Spawn> = Console::new();
+    console.register("spawn", |spawned, args| {
+        spawned.push(parse_args(args)?);
+        Ok(())
+    });
+
+    let mut spawned = Vec::new();
+    console.input = "spawn 1  2 3".to_string();
+    console.submit(|handler, args| handler(&mut spawned, args));
+    assert_eq!(spawned, [[1, 2, 3]]);
+    assert!(console.input.is_empty());
+
+    console.input = "spawn 1 2".to_string();
+    console.submit(|handler, args| handler(&mut spawned, args));
+    console.input = "teleport 1 2 3".to_string();
+    console.submit(|handler, args| handler(&mut spawned, args));
+    assert_eq!(spawned.len(), 1);
+    assert_eq!(
+        console.log(),
+        [
+            "> spawn 1  2 3",
+            "> spawn 1 2",
+            "expected 3 arguments, got 2",
+            "> teleport 1 2 3",
+            "unknown command `teleport`",
+        ]
+    );
+}
@@ -14,9 +14,36 @@ pub struct FontFace {
     pub height: usize,
     pub line_height: u32,
     pub base: u32,
+    /// The distance in pixels covered by a signed distance field atlas, or
+    /// `None` if the atlas is a plain bitmap.
+    pub distance_range: Option<u32>,
     pub glyphs: Vec<FontGlyph>,
 }
 
+impl FontFace {
+    /// The width of whitespace, since the font has no glyphs for it.
+    const WHITESPACE_ADVANCE: u32 = 38;
+
+    pub fn glyph(&self, c: char) -> Option<&FontGlyph> {
+        self.glyphs.iter().find(|glyph| glyph.id == c)
+    }
+
+    /// How far the next glyph is moved to the right after drawing `c`.
+    /// Characters missing from the font take up no space.
+    pub fn advance(&self, c: char) -> u32 {
+        if c.is_whitespace() {
+            Self::WHITESPACE_ADVANCE
+        } else {
+            self.glyph(c).map_or(0, |glyph| glyph.size.x)
+        }
+    }
+
+    /// The width in pixels of `text` drawn on a single line at scale 1.
+    pub fn measure(&self, text: &str) -> u32 {
+        text.chars().map(|c| self.advance(c)).sum()
+    }
+}
+
 pub fn parse(bytes: &[u8]) -> FontFace {
     let ident = |s: &str| {
         s.chars()
@@ -39,6 +66,7 @@ pub fn parse(bytes: &[u8]) -> FontFace {
     let mut height = None;
     let mut line_height = None;
     let mut base = None;
+    let mut distance_range = None;
 
     let mut glyphs = Vec::default();
 
@@ -56,6 +84,15 @@ pub fn parse(bytes: &[u8]) -> FontFace {
                     }
                 }
             }
+            line if line.starts_with("distanceField") => {
+                for (key, value) in line.split_whitespace().skip(1).map(kv) {
+                    match key.as_str() {
+                        "fieldType" => assert_eq!(value, "sdf", "only sdf atlases are supported"),
+                        "distanceRange" => distance_range = value.parse().ok(),
+                        _ => {}
+                    }
+                }
+            }
             line if line.starts_with("chars") => {
                 let (_, value) = line
                     .split_whitespace()
@@ -109,6 +146,7 @@ pub fn parse(bytes: &[u8]) -> FontFace {
         height: height.unwrap(),
         line_height: line_height.unwrap(),
         base: base.unwrap(),
+        distance_range,
         glyphs,
     }
 }
@@ -1,13 +1,21 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
 use glam::*;
-use sdl2::keyboard::{KeyboardState, Scancode};
+use sdl2::keyboard::Scancode;
 
 use crate::ai::Brain;
-use crate::format::vox;
-use crate::scene::{Camera, Entity, Light, Model, Object, Scene, SceneNode, SceneNodeId, Text};
+use crate::assets::AssetManager;
+use crate::renderer::Renderer;
+use crate::rng::Rng;
+use crate::scene::{
+    AnimatedModel, Camera, Entity, Light, Material, MaterialId, Model, Object, Scene, SceneNode,
+    SceneNodeId, TerrainId, Text, TextLayout, Transform,
+};
 use crate::tensor::{self, SparseTensorChunk};
-use crate::terrain;
+use crate::terrain::TerrainStreamer;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct MouseState {
     pub has_mouse_left_been_clicked: bool,
     pub has_mouse_right_been_clicked: bool,
@@ -15,11 +23,14 @@ pub struct MouseState {
     pub dy: i32,
 }
 
-pub struct GameSystems<'a> {
+pub struct GameSystems {
     pub window_size: UVec2,
-    pub keyboard: KeyboardState<'a>,
+    /// The keys held down during the frame.
+    pub keyboard: HashSet<Scancode>,
     pub mouse: MouseState,
     pub dt: f32,
+    /// The frame rate averaged over the recent frames.
+    pub fps: f32,
 }
 
 enum Weapon {
@@ -27,104 +38,352 @@ enum Weapon {
     Knife(SceneNodeId),
 }
 
+/// Spawns enemies in waves on a timer, with one more enemy in every wave.
+#[derive(Default)]
+struct Waves {
+    timer: f32,
+    wave: u32,
+}
+
+impl Waves {
+    /// Seconds between two waves.
+    const INTERVAL: f32 = 20.0;
+
+    /// Advances the timer and returns how many enemies to spawn.
+    fn tick(&mut self, dt: f32) -> u32 {
+        self.timer += dt;
+        if self.timer < Self::INTERVAL {
+            return 0;
+        }
+
+        self.timer -= Self::INTERVAL;
+        self.wave += 1;
+        self.wave
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EnemyState {
+    Idle,
+    Chase,
+    Attack,
+    Flee,
+}
+
+impl EnemyState {
+    /// How close in voxels an enemy must be to the player to attack.
+    const ATTACK_RANGE: f32 = 4.0;
+    /// Enemies with less health than this run away from the player.
+    const FLEE_HEALTH: u32 = 25;
+
+    /// The state an enemy in this state changes to, given whether it sees the
+    /// player, how far away the player is and how much health it has left.
+    fn next(self, sees_player: bool, distance: f32, health: u32) -> Self {
+        use EnemyState::*;
+
+        if health < Self::FLEE_HEALTH {
+            return Flee;
+        }
+
+        match self {
+            Flee => Flee,
+            _ if !sees_player => Idle,
+            _ if distance <= Self::ATTACK_RANGE => Attack,
+            _ => Chase,
+        }
+    }
+}
+
+/// A damage number floating up from where an enemy was hit, which fades out
+/// over its lifetime.
+struct DamagePopup {
+    position: Vec3,
+    damage: u32,
+    /// Seconds since the popup was spawned.
+    age: f32,
+}
+
+impl DamagePopup {
+    /// Seconds a popup is shown for.
+    const LIFETIME: f32 = 0.8;
+    /// Voxels per second the popup rises by.
+    const RISE_SPEED: f32 = 6.0;
+    const TEXT_SCALE: f32 = 0.5;
+
+    fn new(position: Vec3, damage: u32) -> Self {
+        Self {
+            position,
+            damage,
+            age: 0.0,
+        }
+    }
+
+    /// Advances the popup and returns whether it is still shown.
+    fn step(&mut self, dt: f32) -> bool {
+        self.age += dt;
+        self.position.y += Self::RISE_SPEED * dt;
+        self.age < Self::LIFETIME
+    }
+
+    /// The position in pixels of the popup on the screen, or `None` if it is
+    /// behind the camera or outside of the window.
+    fn screen_position(&self, camera: &Camera, window_size: UVec2) -> Option<UVec2> {
+        let ndc = camera.world_to_screen(self.position)?;
+        if ndc.abs().cmpgt(Vec2::ONE).any() {
+            return None;
+        }
+
+        Some(((ndc * 0.5 + 0.5) * window_size.as_vec2()).as_uvec2())
+    }
+
+    fn text(&self, camera: &Camera, window_size: UVec2) -> Option<Text> {
+        let alpha = 1.0 - self.age / Self::LIFETIME;
+        Some(Text {
+            scale: Self::TEXT_SCALE,
+            outline: true,
+            ..Text::with_color(
+                self.screen_position(camera, window_size)?,
+                self.damage.to_string(),
+                vec4(1.0, 0.2, 0.2, alpha),
+            )
+        })
+    }
+}
+
 struct Enemy {
     id: SceneNodeId,
     health: u32,
+    state: EnemyState,
+    /// Seconds until the enemy can attack again.
+    attack_cooldown: f32,
+}
+
+/// How the player moves through the air.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GameSettings {
+    /// The downwards acceleration in voxels per second squared.
+    pub gravity: f32,
+    /// The upwards speed in voxels per second the player jumps off with.
+    pub jump_velocity: f32,
+    /// The height of the floor wherever there is no terrain below the player.
+    pub ground_height: f32,
+    /// How far above the floor the camera is while the player stands on it.
+    pub eye_height: f32,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        Self {
+            gravity: 1350.0,
+            jump_velocity: 180.0,
+            ground_height: 6.0,
+            eye_height: 10.0,
+        }
+    }
 }
 
 pub struct Game {
+    settings: GameSettings,
     yaw: f32,
     pitch: f32,
 
     // Player state
     health: u32,
     weapon: Weapon,
+    /// The material of the voxels the player places.
+    block: MaterialId,
 
     // Enemy state
     enemies: Vec<(Brain, Enemy)>,
-
-    // Animation state
-    nframes_since_spawn: usize,
-    nframes_since_jump: Option<usize>,
-    nframes_since_shoot: Option<usize>,
-    nframes_since_reload: Option<usize>,
-    nframes_since_attack: Option<usize>,
+    waves: Waves,
+    popups: Vec<DamagePopup>,
+
+    // HUD, as indices into the texts of the scene.
+    fps_text: usize,
+    ammo_text: usize,
+    /// The number of texts up to the end of the HUD, after which the damage
+    /// popups are placed.
+    hud_texts: usize,
+
+    assets: AssetManager,
+    terrain: TerrainStreamer,
+    rng: Rng,
+
+    // Animation state, in seconds since the animation started.
+    /// The upwards speed of the player while in the air.
+    vertical_velocity: Option<f32>,
+    time_since_shoot: Option<f32>,
+    time_since_reload: Option<f32>,
 }
 
 impl Game {
     const SPEED: f32 = 1.0;
     const CAPACITY: u32 = 9;
+    /// The angle in degrees of the cone in which enemies notice the player.
+    const VIEW_CONE: f32 = 120.0;
+    const VIEW_RANGE: f32 = 100.0;
+    /// Voxels per second.
+    const ENEMY_SPEED: f32 = 4.0;
+    /// Frames per second of the walk cycle of the enemies.
+    const ENEMY_ANIMATION_FPS: f32 = 8.0;
+    const ENEMY_DAMAGE: u32 = 10;
+    /// The damage a bullet does to an enemy.
+    const SHOT_DAMAGE: u32 = 10;
+    /// Seconds between two attacks of the same enemy.
+    const ENEMY_ATTACK_INTERVAL: f32 = 1.0;
+    /// Seeds every random decision of the game, so a session can be replayed.
+    const SEED: u64 = 0x0ff1ce;
+    /// Seconds the gun spends recoiling and the same again moving back.
+    const RECOIL: f32 = 2.0 / 60.0;
+    const RECOIL_DISTANCE: f32 = 4.0;
+    const RELOAD_DURATION: f32 = 1.0;
+    /// How far away from the player blocks can be placed.
+    const REACH: f32 = 8.0;
+    /// Seconds it takes the red flash of a hit enemy to fade.
+    const FLASH_DURATION: f32 = 0.15;
+    /// The angles in radians the camera leans by while strafing and when hit.
+    const STRAFE_LEAN: f32 = 0.03;
+    const DAMAGE_LEAN: f32 = 0.08;
+    /// How quickly the lean settles, per second.
+    const LEAN_SPEED: f32 = 10.0;
+    /// How high a ledge the player walks up onto without jumping.
+    const STEP_HEIGHT: f32 = 1.0;
+    /// How much a hit shakes the camera.
+    const DAMAGE_TRAUMA: f32 = 0.4;
+    /// The objects of the gun and its magazine, which are drawn as chunks
+    /// like the enemies.
+    const WEAPON_CHUNKS: usize = 2;
 
     pub fn new(scene: &mut Scene) -> Self {
+        Self::with_settings(scene, GameSettings::default())
+    }
+
+    pub fn with_settings(scene: &mut Scene, settings: GameSettings) -> Self {
+        let mut assets = AssetManager::default();
+
         // Terrain
         {
-            let (models, _) = vox::open("./assets/floor.vox");
+            let (models, _) = &*assets.load_vox("./assets/floor.vox");
             let mut floor = SparseTensorChunk::from(Model::from(models[0].clone()));
             floor.transform *= Mat4::from_translation(vec3(-200.0, -5.0, 0.0));
             floor.transform *= Mat4::from_scale(vec3(10.0, 10.0, 0.1));
 
-            scene.terrain.push(floor);
-
-            let player_block = terrain::closest_block(scene.camera().position);
-            let map_block = terrain::MapBlock::from_scratch(player_block);
-            let terrain = map_block.gen_terrain(terrain::EMPTY_MASK);
-            for n in &terrain{
-                // println!("{n:?}");
-            }
-            scene.terrain.push(terrain);
+            scene.add_terrain(floor);
         }
+        let terrain = TerrainStreamer::new(scene, &mut assets);
 
         // FPS
+        let fps_text = scene.text.len();
         scene.text.push(Text {
             position: uvec2(0, 0),
             text: "FPS".to_string(),
             color: vec4(0.0, 1.0, 0.0, 1.0),
             scale: 0.5,
+            layout: TextLayout::default(),
+            layer: 1,
+            outline: true,
         });
 
-        scene.camera_mut().translate(vec3(0.0, 16.0, 0.0));
+        // Ammo counter, placed below the FPS counter.
+        let ammo_text = scene.text.len();
+        scene.text.push(Text::with_color(
+            uvec2(0, 48),
+            String::new(),
+            vec4(0.0, 1.0, 0.0, 1.0),
+        ));
+        let hud_texts = scene.text.len();
+
+        scene
+            .camera_mut()
+            .translate(vec3(0.0, settings.ground_height + settings.eye_height, 0.0));
 
-        let gun = Self::spawn_gun(scene);
+        let gun = Self::spawn_gun(scene, &mut assets);
 
-        let enemy = Self::spawn_enemy(scene);
+        let mut rng = Rng::new(Self::SEED);
+        let position = Self::spawn_point(scene, &mut rng).unwrap_or(UVec3::ZERO);
+        let enemy = Self::spawn_enemy(scene, &mut assets, position);
 
         Self {
+            settings,
             yaw: -90.0,
             pitch: 0.0,
 
             health: 100,
             weapon: Weapon::Gun(gun, Self::CAPACITY),
+            block: MaterialId(1),
             enemies: vec![enemy],
+            waves: Waves::default(),
+            popups: Vec::new(),
 
-            nframes_since_spawn: 0,
-            nframes_since_jump: None,
-            nframes_since_reload: None,
-            nframes_since_shoot: None,
-            nframes_since_attack: None,
+            fps_text,
+            ammo_text,
+            hud_texts,
+
+            assets,
+            terrain,
+            rng,
+
+            vertical_velocity: None,
+            time_since_reload: None,
+            time_since_shoot: None,
         }
     }
 
+    /// The routes the enemies are following, in world space voxels, starting
+    /// at the voxel the enemy is in.
+    pub fn enemy_routes(&self) -> impl Iterator<Item = Vec<UVec3>> + '_ {
+        self.enemies.iter().map(|(brain, _)| {
+            let route = brain.route.iter().copied();
+            std::iter::once(brain.position).chain(route).collect()
+        })
+    }
+
+    /// The enemy the player is aiming at.
+    pub fn target(&self, scene: &Scene) -> Option<SceneNodeId> {
+        let camera = scene.camera();
+        let ray = Ray::with_len(camera.translation(), camera.direction(), Self::VIEW_RANGE);
+        ray.cast_object(scene, "enemy").map(|hit| hit.node)
+    }
+
+    /// Whether the player has died. The game stops updating once it is over.
+    pub fn is_game_over(&self) -> bool {
+        self.health == 0
+    }
+
     pub fn run(&mut self, systems: &mut GameSystems, scene: &mut Scene) {
+        if self.is_game_over() {
+            return;
+        }
+
         let keyboard = &systems.keyboard;
         let mouse = &systems.mouse;
         let dt = systems.dt;
 
+        scene.advance_time_of_day(dt);
+
         self.handle_movement(systems, scene);
-        self.handle_shoot(scene);
+        scene.camera_mut().update_shake(dt);
+        self.terrain.update(scene);
+        self.update_waves(scene, dt);
+        self.update_enemies(scene, dt);
+        self.handle_shoot(scene, dt);
+        self.handle_reload(dt);
+        self.update_popups(scene, systems.window_size, dt);
 
         // self.shoot_animation(scene);
-        self.jump_animation(scene);
+        self.jump_animation(scene, dt);
 
         // Weapon switch
-        if keyboard.is_scancode_pressed(Scancode::Num1) {
+        if keyboard.contains(&Scancode::Num1) {
             if let Weapon::Knife(knife_id) = &self.weapon {
                 scene.scene_graph.remove_entity(knife_id);
-                self.weapon = Weapon::Gun(Self::spawn_gun(scene), Self::CAPACITY);
+                let gun = Self::spawn_gun(scene, &mut self.assets);
+                self.weapon = Weapon::Gun(gun, Self::CAPACITY);
             }
-        } else if keyboard.is_scancode_pressed(Scancode::Num2) {
+        } else if keyboard.contains(&Scancode::Num2) {
             if let Weapon::Gun(gun_id, _) = &self.weapon {
                 scene.scene_graph.remove_entity(gun_id);
-                self.weapon = Weapon::Knife(Self::spawn_knife(scene));
+                self.weapon = Weapon::Knife(Self::spawn_knife(scene, &mut self.assets));
             }
         }
 
@@ -135,31 +394,40 @@ impl Game {
                 let position = scene.camera().translation();
                 let direction = scene.camera().direction();
                 let gun = scene.scene_graph.object_mut(id).unwrap();
-                gun.transform = Mat4::from_translation(position);
-                gun.transform *= Mat4::from_translation(vec3(-1.0, 0.0, 2.5));
-                gun.transform *= Mat4::from_scale(vec3(0.05, 0.05, 0.05));
-                gun.transform *= Mat4::from_rotation_y(-std::f32::consts::FRAC_PI_2 + 0.1);
-                gun.transform *= Mat4::from_rotation_y(direction.x);
+                gun.transform = Transform {
+                    translation: position + vec3(-1.0, 0.0, 2.5),
+                    rotation: Quat::from_rotation_y(
+                        -std::f32::consts::FRAC_PI_2 + 0.1 + direction.x,
+                    ),
+                    scale: vec3(0.05, 0.05, 0.05),
+                };
 
                 // Shoot
                 if mouse.has_mouse_left_been_clicked && *ammo != 0 {
-                    self.nframes_since_shoot = Some(0);
+                    self.time_since_shoot = Some(0.0);
+                }
+
+                // Place a block on the terrain the player is looking at
+                if mouse.has_mouse_right_been_clicked {
+                    let ray = Ray::with_len(position, direction, Self::REACH);
+                    if let Some(hit) = ray.cast_terrain(scene) {
+                        Self::place_block(scene, &hit, self.block);
+                    }
                 }
 
                 // Reload
-                if keyboard.is_scancode_pressed(Scancode::R) && *ammo != Self::CAPACITY {
+                if keyboard.contains(&Scancode::R) && *ammo != Self::CAPACITY {
                     *ammo = Self::CAPACITY;
 
-                    self.nframes_since_reload = Some(0);
+                    self.time_since_reload = Some(0.0);
                 }
 
                 // Ammo Counter
-                scene.text.push(Text::black(
-                    uvec2(0, 0),
-                    format!("{ammo}/{}", Self::CAPACITY),
-                ));
+                scene.text[self.ammo_text].text = format!("{ammo}/{}", Self::CAPACITY);
             }
             Weapon::Knife(knife_id) => {
+                scene.text[self.ammo_text].text.clear();
+
                 // Attack
                 if mouse.has_mouse_right_been_clicked {
                     // TODO
@@ -167,70 +435,174 @@ impl Game {
             }
         }
 
-        // Update the fps counter with the latest delta time.
-        scene.text[0].text = format!("FPS {:05.1}", 1.0 / dt);
+        scene.text[self.fps_text].text = format!("FPS {:05.1}", systems.fps);
     }
 
-    fn update_enemies(&mut self, scene: &mut Scene) {
+    /// Runs a frame like [`Game::run`] and then waits for the terrain it
+    /// requested, so the outcome of every frame is the same from run to run.
+    #[cfg(test)]
+    fn step(&mut self, mut systems: GameSystems, scene: &mut Scene) {
+        self.run(&mut systems, scene);
+        self.terrain.finish(scene);
+    }
+
+    fn update_enemies(&mut self, scene: &mut Scene, dt: f32) {
+        let player = scene.camera().translation();
+
         for (brain, enemy) in &mut self.enemies {
+            let sees_player = brain.can_see(player, Self::VIEW_CONE, Self::VIEW_RANGE, scene);
+            let distance = brain.eye().distance(player);
+            enemy.state = enemy.state.next(sees_player, distance, enemy.health);
+            enemy.attack_cooldown = (enemy.attack_cooldown - dt).max(0.0);
+
             brain.route.clear();
-            // brain.append_destination(scene.camera().translation(), scene);
+            let direction = match enemy.state {
+                EnemyState::Idle => Vec3::ZERO,
+                // A chasing enemy sees the player, so the straight line to the
+                // player is free and no path has to be found.
+                EnemyState::Chase => {
+                    // Keep looking at the player, so they aren't lost again right away.
+                    brain.facing = (player - brain.eye()).normalize_or_zero();
+                    brain.route.push(player.max(Vec3::ZERO).as_uvec3());
+                    brain.facing
+                }
+                EnemyState::Attack => {
+                    if enemy.attack_cooldown == 0.0 {
+                        self.health = self.health.saturating_sub(Self::ENEMY_DAMAGE);
+                        scene.camera_mut().set_roll(Self::DAMAGE_LEAN);
+                        scene.camera_mut().add_trauma(Self::DAMAGE_TRAUMA);
+                        enemy.attack_cooldown = Self::ENEMY_ATTACK_INTERVAL;
+                    }
+                    Vec3::ZERO
+                }
+                EnemyState::Flee => (brain.eye() - player).normalize_or_zero(),
+            };
+
+            if let Some(object) = scene.scene_graph.object_mut(&enemy.id) {
+                object.transform.translation += direction * Self::ENEMY_SPEED * dt;
+                // Enemies only walk while they move.
+                if direction != Vec3::ZERO {
+                    object.animate(dt);
+                }
+                brain.position = object.transform.translation.max(Vec3::ZERO).as_uvec3();
+                object.flash = object
+                    .flash
+                    .map(|(color, strength)| (color, strength - dt / Self::FLASH_DURATION))
+                    .filter(|&(_, strength)| strength > 0.0);
+            }
         }
     }
 
-    fn spawn_enemy(scene: &mut Scene) -> (Brain, Enemy) {
-        let Scene { scene_graph, .. } = scene;
+    fn update_popups(&mut self, scene: &mut Scene, window_size: UVec2, dt: f32) {
+        self.popups.retain_mut(|popup| popup.step(dt));
+
+        let camera = scene.camera();
+        let texts: Vec<_> = self
+            .popups
+            .iter()
+            .filter_map(|popup| popup.text(camera, window_size))
+            .collect();
+        scene.text.truncate(self.hud_texts);
+        scene.text.extend(texts);
+    }
+
+    /// How many enemies can be alive at once, so that together with the
+    /// terrain cells, the floor and the weapon they fit into the chunks the
+    /// renderer can draw.
+    fn enemy_budget(&self) -> usize {
+        let terrain = self.terrain.fov().pow(2) + 1;
+        Renderer::MAX_CHUNKS.saturating_sub(terrain + Self::WEAPON_CHUNKS)
+    }
+
+    fn update_waves(&mut self, scene: &mut Scene, dt: f32) {
+        for _ in 0..self.waves.tick(dt) {
+            // Enemies of waves that come while too many are alive are dropped.
+            if self.enemies.len() >= self.enemy_budget() {
+                break;
+            }
+            // Enemies that can't find room to spawn are skipped.
+            if let Some(position) = Self::spawn_point(scene, &mut self.rng) {
+                let enemy = Self::spawn_enemy(scene, &mut self.assets, position);
+                self.enemies.push(enemy);
+            }
+        }
+    }
+
+    /// Spawns an enemy at `position` outside of the waves, e.g. from the
+    /// console.
+    pub fn add_enemy(&mut self, scene: &mut Scene, position: UVec3) {
+        let enemy = Self::spawn_enemy(scene, &mut self.assets, position);
+        self.enemies.push(enemy);
+    }
+
+    /// Picks a random position for an enemy that is not inside solid terrain.
+    fn spawn_point(scene: &Scene, rng: &mut Rng) -> Option<UVec3> {
+        const ATTEMPTS: usize = 64;
 
-        // This should be cached...
-        let (models, _) = vox::open("./assets/zombie.vox");
-        let zombie = Model::from(models[0].clone());
+        (0..ATTEMPTS)
+            .map(|_| uvec3(rng.range(0..40), rng.range(0..40), 0))
+            .find(|position| !scene.is_solid(position.as_vec3() + 0.5))
+    }
 
-        // Determine zombie spawn location
-        let position = uvec3(0, 0, 0);
-        let transform = Mat4::from_translation(position.as_vec3());
+    fn spawn_enemy(
+        scene: &mut Scene,
+        assets: &mut AssetManager,
+        position: UVec3,
+    ) -> (Brain, Enemy) {
+        let Scene { scene_graph, .. } = scene;
 
-        let id = scene_graph.insert_entity(
-            Object::with_tag(transform, zombie, "enemy".to_string()),
-            &scene_graph.root(),
+        // Every model of the file is a frame of the walk cycle.
+        let zombie = Rc::new(AnimatedModel::new(
+            assets.load_frames("./assets/zombie.vox"),
+            Self::ENEMY_ANIMATION_FPS,
+        ));
+
+        let transform = Transform::from_translation(position.as_vec3());
+        let mut enemy = Object::with_tag(
+            transform,
+            Rc::clone(&zombie.frames()[0]),
+            "enemy".to_string(),
         );
+        enemy.animation = Some((zombie, 0.0));
+
+        let id = scene_graph.insert_entity(enemy, &scene_graph.root());
         (
             Brain {
                 position,
                 route: vec![],
+                facing: Vec3::X,
+            },
+            Enemy {
+                id,
+                health: 100,
+                state: EnemyState::Idle,
+                attack_cooldown: 0.0,
             },
-            Enemy { id, health: 100 },
         )
     }
 
-    fn spawn_gun(scene: &mut Scene) -> SceneNodeId {
+    fn spawn_gun(scene: &mut Scene, assets: &mut AssetManager) -> SceneNodeId {
         let (gun, magazine) = {
-            let (models, materials) = vox::open("./assets/gun.vox");
+            let (models, materials) = &*assets.load_vox("./assets/gun.vox");
             if !scene.has_materials() {
-                let materials = Box::new(materials.map(Into::into));
-                scene.set_materials(materials);
+                scene.set_materials(Material::palette(materials));
             }
 
-            // To have decent rotations we must map the coordinates from 0..40 to -20..20
-            // which moves the origin to the center of the chunk.
-            let mut model = models[3].clone();
-            model.positions.iter_mut().for_each(|(position, _)| {
-                *position -= vec3(40.0, 40.0, 40.0);
-            });
-
-            let gun_model = Model::from(model);
-            let mut gun = Object::new(Mat4::IDENTITY, gun_model);
+            // To have decent rotations the origin is moved to the center of the gun. The
+            // magazine is moved along with it, so it stays in place relative to the gun.
+            let mut gun_model = Model::from(models[3].clone());
+            let offset = gun_model.center_origin();
+            let mut gun = Object::new(Transform::IDENTITY, gun_model);
 
-            let mut magazine_model = models[2].clone();
+            let mut magazine_model = Model::from(models[2].clone());
             magazine_model
                 .positions
                 .iter_mut()
-                .for_each(|(position, _)| {
-                    *position -= vec3(40.0, 40.0, 40.0);
-                });
+                .for_each(|(position, _)| *position += offset);
 
             let magazine = Object::new(
-                Mat4::from_translation(vec3(-37.0, -2.0, 20.0)),
-                Model::from(magazine_model),
+                Transform::from_translation(vec3(-37.0, -2.0, 20.0)),
+                magazine_model,
             );
 
             (gun, magazine)
@@ -243,14 +615,18 @@ impl Game {
         gun_id
     }
 
-    fn spawn_knife(scene: &mut Scene) -> SceneNodeId {
-        let (models, _) = vox::open("./assets/knife.vox");
-        let mut knife = Object::new(Mat4::IDENTITY, Model::from(models[0].clone()));
-
-        knife.transform *= Mat4::from_translation(vec3(3.0, -16.0, 10.0));
-        knife.transform *= Mat4::from_scale(vec3(0.25, 0.25, 0.25));
-        knife.transform *= Mat4::from_rotation_x(1.1);
-        knife.transform *= Mat4::from_rotation_y(-1.6);
+    fn spawn_knife(scene: &mut Scene, assets: &mut AssetManager) -> SceneNodeId {
+        let knife_model = assets.load_model("./assets/knife.vox");
+        // The knife is placed in the space of the camera, which looks down its negative
+        // z axis, so it stays in view when the player looks around.
+        let transform = Transform {
+            translation: vec3(-3.0, -16.0, -10.0),
+            rotation: Quat::from_rotation_y(std::f32::consts::PI)
+                * Quat::from_rotation_x(1.1)
+                * Quat::from_rotation_y(-1.6),
+            scale: vec3(0.25, 0.25, 0.25),
+        };
+        let knife = Object::new(transform, knife_model);
 
         scene.scene_graph.insert_entity(knife, &scene.camera)
     }
@@ -264,26 +640,26 @@ impl Game {
         // Walk around with WASD keys
         // TODO fix that we are moving slower when pointing upwards
         let speed = vec3(Self::SPEED, 0.0, Self::SPEED);
-        if keyboard.is_scancode_pressed(Scancode::W) {
+        if keyboard.contains(&Scancode::W) {
             scene.camera_mut().translate(camera.direction() * speed);
         }
 
-        if keyboard.is_scancode_pressed(Scancode::A) {
+        if keyboard.contains(&Scancode::A) {
             scene.camera_mut().translate(-camera.right() * speed);
         }
 
-        if keyboard.is_scancode_pressed(Scancode::S) {
+        if keyboard.contains(&Scancode::S) {
             scene.camera_mut().translate(-camera.direction() * speed);
         }
 
-        if keyboard.is_scancode_pressed(Scancode::D) {
+        if keyboard.contains(&Scancode::D) {
             scene.camera_mut().translate(camera.right() * speed);
         }
 
         // Like in real life we can only jump if we are grounded.
-        let is_grounded = camera.translation().y == 16.0;
-        if keyboard.is_scancode_pressed(Scancode::Space) && is_grounded {
-            self.nframes_since_jump = Some(0);
+        let is_grounded = self.vertical_velocity.is_none();
+        if keyboard.contains(&Scancode::Space) && is_grounded {
+            self.vertical_velocity = Some(self.settings.jump_velocity);
         }
 
         // Look around using the mouse
@@ -306,64 +682,183 @@ impl Game {
             yaw.sin() * pitch.cos(),
         );
         scene.camera_mut().set_direction(direction);
+
+        // Lean towards the side we are strafing to. The lean eases in and out, which
+        // also lets the kick of a hit wear off.
+        let strafe =
+            keyboard.contains(&Scancode::D) as i32 - keyboard.contains(&Scancode::A) as i32;
+        let target = strafe as f32 * Self::STRAFE_LEAN;
+        let roll = scene.camera().roll();
+        let t = (dt * Self::LEAN_SPEED).min(1.0);
+        scene.camera_mut().set_roll(roll + (target - roll) * t);
+    }
+
+    /// The height above the ground and the upwards speed of the player `dt`
+    /// seconds later, with semi-implicit Euler integration.
+    fn fall(settings: &GameSettings, height: f32, velocity: f32, dt: f32) -> (f32, f32) {
+        let velocity = velocity - settings.gravity * dt;
+        (height + velocity * dt, velocity)
+    }
+
+    /// How far back the gun is pushed `t` seconds into a shot.
+    fn recoil(t: f32) -> f32 {
+        match t {
+            t if t < Self::RECOIL => Self::RECOIL_DISTANCE * t / Self::RECOIL,
+            t => Self::RECOIL_DISTANCE * (2.0 - t / Self::RECOIL).max(0.0),
+        }
+    }
+
+    /// The height the camera at `eye` would have while standing on the floor
+    /// below it.
+    fn standing_height(&self, scene: &Scene, eye: Vec3) -> f32 {
+        let feet = eye.y - self.settings.eye_height;
+        let ground = scene.ground_height(eye.xz(), feet + Self::STEP_HEIGHT);
+        ground.unwrap_or(self.settings.ground_height) + self.settings.eye_height
     }
 
-    fn jump_animation(&mut self, scene: &mut Scene) {
+    fn jump_animation(&mut self, scene: &mut Scene, dt: f32) {
+        let ground = self.standing_height(scene, scene.camera().translation());
         let camera = scene.camera_mut();
 
-        if let Some(n) = &mut self.nframes_since_jump {
-            *n += 1;
+        // Follow the floor while walking on it.
+        let Some(velocity) = &mut self.vertical_velocity else {
+            camera.translate(vec3(0.0, ground - camera.translation().y, 0.0));
+            return;
+        };
 
-            match *n - 1 {
-                n if n < 8 => camera.translate(vec3(0.0, 1.5, 0.0)),
-                n if n >= 8 && n < 10 => {}
-                n if n >= 10 && n < 14 => camera.translate(vec3(0.0, -(12.0 / 4.0), 0.0)),
-                _ => self.nframes_since_jump = None,
-            }
+        let height = camera.translation().y - ground;
+        let (height, new_velocity) = Self::fall(&self.settings, height, *velocity, dt);
+        *velocity = new_velocity;
 
-            if self.nframes_since_jump.is_none() {
-                camera.translate(vec3(0.0, 16.0 - camera.translation().y, 0.0))
-            }
-        }
+        // Land on the ground instead of falling through it.
+        let height = if height <= 0.0 {
+            self.vertical_velocity = None;
+            0.0
+        } else {
+            height
+        };
+        camera.translate(vec3(0.0, ground + height - camera.translation().y, 0.0));
     }
 
-    fn handle_shoot(&mut self, scene: &mut Scene) {
+    fn handle_shoot(&mut self, scene: &mut Scene, dt: f32) {
         let camera = *scene.camera();
         // let Scene { scene_graph, .. } = scene;
 
-        if let Weapon::Gun(gun_id, ammo) = &self.weapon && let Some(n) = &mut self.nframes_since_shoot {
+        if let Weapon::Gun(gun_id, ammo) = &self.weapon && let Some(t) = &mut self.time_since_shoot {
             
-            if *n == 0 {
+            if *t == 0.0 {
                 let ray = Ray::with_len(vec3(0.0, 0.0, 0.0), camera.direction(), 100.0);
-                if let Some(id) = ray.cast_object(1000.0, scene, "enemy") {
+                if let Some(Hit { node: id, .. }) = ray.cast_object(scene, "enemy") {
                     println!("hit");
                     // let enemy = scene.scene_graph.object_mut(&id).unwrap();
                     let (i, enemy) = self.enemies.iter_mut().enumerate().find_map(|(i, (_, enemy))| (enemy.id == id).then_some((i, enemy))).unwrap();
-                    enemy.health -= 10;
+                    enemy.health -= Self::SHOT_DAMAGE;
+
+                    if let Some(object) = scene.scene_graph.object(&id) {
+                        self.popups.push(DamagePopup::new(
+                            object.transform.translation,
+                            Self::SHOT_DAMAGE,
+                        ));
+                    }
 
                     if enemy.health == 0 {
                         self.enemies.remove(i);
                         scene.scene_graph.remove_entity(&id);
+                    } else if let Some(object) = scene.scene_graph.object_mut(&id) {
+                        object.flash = Some((vec3(1.0, 0.0, 0.0), 1.0));
                     }
                 }
             }
             
             let gun = scene.scene_graph.object_mut(gun_id).unwrap();
             
-            *n += 1;
-            match *n - 1 {
-                n if n < 2 => gun.transform *= Mat4::from_translation(vec3(-2.0, 0.0, 0.0)),
-                n if (2..4).contains(&n) => gun.transform *= Mat4::from_translation(vec3(2.0, 0.0, 0.0)),
-                _ => self.nframes_since_shoot = None
+            let recoil = Self::recoil(*t);
+            *t += dt;
+            gun.transform *= Transform::from_translation(vec3(recoil - Self::recoil(*t), 0.0, 0.0));
+
+            if *t >= 2.0 * Self::RECOIL {
+                self.time_since_shoot = None;
             }
 
 
         }
     }
 
-    fn handle_reload(&mut self) {}
+    /// Inserts a voxel of `material` next to the face of the terrain that was
+    /// hit. The voxel goes into the chunk that was hit, or into the one next to
+    /// it when the face is on the border of the chunk. Returns whether there
+    /// was room for it, which there isn't if the voxel is taken or outside of
+    /// every chunk.
+    fn place_block(scene: &mut Scene, hit: &Hit<TerrainId>, material: MaterialId) -> bool {
+        let voxel = hit.voxel.as_ivec3() + hit.normal;
+        if voxel.cmplt(IVec3::ZERO).any() || scene.is_solid(voxel.as_vec3() + 0.5) {
+            return false;
+        }
 
-    fn handle_attack(&mut self) {}
+        let point = voxel.as_vec3() + 0.5;
+        let target = scene
+            .terrain()
+            .filter(|(id, _)| id == &hit.node)
+            .chain(scene.terrain())
+            .find_map(|(id, chunk)| Some((id, chunk.world_to_local(point)?)));
+        let Some((id, i)) = target else {
+            return false;
+        };
+
+        scene.terrain_mut(&id).unwrap().insert(i, Some(material));
+        true
+    }
+
+    fn handle_reload(&mut self, dt: f32) {
+        if let Some(t) = &mut self.time_since_reload {
+            *t += dt;
+            if *t >= Self::RELOAD_DURATION {
+                self.time_since_reload = None;
+            }
+        }
+    }
+}
+
+/// Input to play back into a game frame by frame, at a fixed frame rate.
+#[cfg(test)]
+#[derive(Default)]
+struct InputScript {
+    /// The keys held down and the mouse input of every frame.
+    frames: Vec<(HashSet<Scancode>, MouseState)>,
+}
+
+#[cfg(test)]
+impl InputScript {
+    const DT: f32 = 1.0 / 60.0;
+    const WINDOW_SIZE: UVec2 = uvec2(640, 480);
+
+    /// Holds down `keys` for `frames` frames.
+    fn hold(mut self, keys: &[Scancode], frames: usize) -> Self {
+        let keys: HashSet<_> = keys.iter().copied().collect();
+        let frame = (keys, MouseState::default());
+        self.frames.extend(std::iter::repeat_n(frame, frames));
+        self
+    }
+
+    /// Plays a single frame of mouse input without any keys held down.
+    fn mouse(mut self, mouse: MouseState) -> Self {
+        self.frames.push((HashSet::new(), mouse));
+        self
+    }
+
+    /// Steps `game` once for every frame of the script.
+    fn play(&self, game: &mut Game, scene: &mut Scene) {
+        for (keyboard, mouse) in &self.frames {
+            let systems = GameSystems {
+                window_size: Self::WINDOW_SIZE,
+                keyboard: keyboard.clone(),
+                mouse: mouse.clone(),
+                dt: Self::DT,
+                fps: 1.0 / Self::DT,
+            };
+            game.step(systems, scene);
+        }
+    }
 }
 
 struct Ray {
@@ -389,47 +884,482 @@ impl Ray {
         }
     }
 
-    /// Checks if a ray intersects with an object in the scene
-    pub fn cast_object(&self, steps: f32, scene: &mut Scene, tag: &str) -> Option<SceneNodeId> {
+    /// Finds the first voxel of an object tagged with `tag` along the ray.
+    pub fn cast_object(&self, scene: &Scene, tag: &str) -> Option<Hit<SceneNodeId>> {
         let Scene { scene_graph, .. } = scene;
 
         let mut objects = Vec::new();
 
-        for (id, entity) in scene_graph.mutated_entities() {
-            if let Entity::Object(o) = entity && o.tag.contains(&tag) {
+        for id in scene_graph.find_by_tag(tag) {
+            if let Some(Entity::Object(o)) = scene_graph.mutated_entity(&id) {
                 objects.push((id, SparseTensorChunk::from(o.clone())));
             }
         }
 
-        let mut t = 0.0;
-        while t <= self.len {
-            // The location in world-space.
-            let v = self.origin + self.direction * t;
+        // Every object lies inside of `0..bounds`, so the march can stop once the
+        // ray has left it.
+        let bounds = objects
+            .iter()
+            .flat_map(|(_, chunk)| chunk.data.keys())
+            .fold(UVec3::ZERO, |bounds, &i| bounds.max(i + 1));
+
+        let max_distance = self.len.min(self.exit_distance(bounds.as_vec3()));
+        self.march(max_distance, |voxel| {
+            objects
+                .iter()
+                .find(|(_, chunk)| chunk.voxel(voxel).is_some())
+                .map(|(id, _)| id.clone())
+        })
+    }
 
-            // We convert the vector to a UVec3 eg. voxel-space.
+    /// Finds the first voxel of the terrain along the ray.
+    pub fn cast_terrain(&self, scene: &Scene) -> Option<Hit<TerrainId>> {
+        // The terrain lies inside of `0..bounds`, so the march can stop once the
+        // ray has left it.
+        let bounds = scene
+            .terrain()
+            .map(|(_, chunk)| chunk.local_to_world(chunk.dim))
+            .fold(Vec3::ZERO, Vec3::max);
+
+        let max_distance = self.len.min(self.exit_distance(bounds));
+        self.march(max_distance, |voxel| {
+            let center = voxel.as_vec3() + 0.5;
+            if !scene.is_solid(center) {
+                return None;
+            }
 
-            let v_voxel = uvec3(v.x as _, v.y as _, v.z as _);
+            // Only the chunks of the terrain know which of them was hit.
+            scene
+                .terrain()
+                .find(|(_, chunk)| {
+                    let local = chunk.world_to_local(center);
+                    local.is_some_and(|i| chunk.voxel(i).is_some())
+                })
+                .map(|(id, _)| id)
+        })
+    }
 
-            // First we find all chunks where the ray intersects the scene terrain.
-            // let mut terrain_hit = None;
-            // // let mut object_hit = None;
+    /// The distance at which the ray leaves the box from the origin to
+    /// `bounds`, which is negative if the ray never enters it.
+    fn exit_distance(&self, bounds: Vec3) -> f32 {
+        let direction = self.direction.normalize_or_zero();
+        (0..3)
+            .map(|axis| match direction[axis] {
+                d if d > 0.0 => (bounds[axis] - self.origin[axis]) / d,
+                d if d < 0.0 => -self.origin[axis] / d,
+                _ if (0.0..bounds[axis]).contains(&self.origin[axis]) => f32::INFINITY,
+                _ => -1.0,
+            })
+            .fold(f32::INFINITY, f32::min)
+    }
 
-            // for chunk in &scene.terrain {
-            //     if chunk.voxel(v_voxel).is_some() {
-            //         // HIT!
-            //         terrain_hit = Some(t);
-            //     }
-            // }
+    /// Walks the voxels of the world grid along the ray, starting with the
+    /// voxel of the origin, until `hit` finds something or the ray is longer
+    /// than `max_distance`. Voxels with negative coordinates are skipped.
+    fn march<Id>(
+        &self,
+        max_distance: f32,
+        mut hit: impl FnMut(UVec3) -> Option<Id>,
+    ) -> Option<Hit<Id>> {
+        let direction = self.direction.normalize_or_zero();
+        let mut voxel = self.origin.floor().as_ivec3();
+
+        let mut step = IVec3::ZERO;
+        // How far along the ray the next voxel border and the distance between
+        // two borders are on every axis.
+        let mut next_border = Vec3::splat(f32::INFINITY);
+        let mut border_distance = Vec3::splat(f32::INFINITY);
+        for axis in 0..3 {
+            if direction[axis] > 0.0 {
+                step[axis] = 1;
+                next_border[axis] =
+                    (voxel[axis] as f32 + 1.0 - self.origin[axis]) / direction[axis];
+            } else if direction[axis] < 0.0 {
+                step[axis] = -1;
+                next_border[axis] = (voxel[axis] as f32 - self.origin[axis]) / direction[axis];
+            } else {
+                continue;
+            }
+            border_distance[axis] = 1.0 / direction[axis].abs();
+        }
 
-            for (id, chunk) in &objects {
-                if chunk.voxel(v_voxel).is_some() {
-                    return Some(id.clone());
-                }
+        // The origin isn't entered through a face, so it has no normal.
+        let mut normal = IVec3::ZERO;
+        let mut distance = 0.0;
+        while distance <= max_distance {
+            if voxel.cmpge(IVec3::ZERO).all()
+                && let Some(node) = hit(voxel.as_uvec3())
+            {
+                return Some(Hit {
+                    node,
+                    voxel: voxel.as_uvec3(),
+                    normal,
+                    distance,
+                });
             }
 
-            t += self.len / steps;
+            let axis = (0..3)
+                .min_by(|&i, &j| next_border[i].total_cmp(&next_border[j]))
+                .unwrap();
+            distance = next_border[axis];
+            voxel[axis] += step[axis];
+            normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+            next_border[axis] += border_distance[axis];
         }
 
         None
     }
 }
+
+/// Where a [`Ray`] hit something.
+#[derive(Debug, Clone, PartialEq)]
+struct Hit<Id> {
+    /// What was hit.
+    node: Id,
+    /// The voxel that was hit, in world space.
+    voxel: UVec3,
+    /// The normal of the face the ray entered the voxel through, which is zero
+    /// if the ray started inside of the voxel.
+    normal: IVec3,
+    /// The distance from the origin of the ray to the face.
+    distance: f32,
+}
+
+#[test]
+fn enemies_share_model() {
+    let mut scene = Scene::empty();
+    let mut assets = AssetManager::default();
+
+    let (_, a) = Game::spawn_enemy(&mut scene, &mut assets, UVec3::ZERO);
+    let (_, b) = Game::spawn_enemy(&mut scene, &mut assets, uvec3(1, 0, 0));
+
+    let a = &scene.scene_graph.object(&a.id).unwrap().model;
+    let b = &scene.scene_graph.object(&b.id).unwrap().model;
+    assert!(std::rc::Rc::ptr_eq(a, b));
+}
+
+#[test]
+fn enemy_waves() {
+    let mut scene = Scene::empty();
+    let mut game = Game::new(&mut scene);
+
+    // Fill half of the spawn area with a solid slab.
+    let mut slab = SparseTensorChunk::nothing(uvec3(20, 40, 1));
+    for x in 0..20 {
+        for y in 0..40 {
+            slab.insert(uvec3(x, y, 0), Some(MaterialId(1)));
+        }
+    }
+    scene.add_terrain(slab);
+
+    // Three waves of one, two and three enemies on top of the first enemy.
+    for _ in 0..(3.5 * Waves::INTERVAL) as usize {
+        game.update_waves(&mut scene, 1.0);
+    }
+    assert_eq!(game.enemies.len(), 1 + 1 + 2 + 3);
+
+    for (Brain { position, .. }, _) in &game.enemies[1..] {
+        assert!(position.x >= 20, "{position:?} spawned inside terrain");
+    }
+}
+
+#[test]
+fn enemy_budget() {
+    let mut scene = Scene::empty();
+    let mut game = Game::new(&mut scene);
+
+    // Nobody fights back, so every wave adds to the enemies that are alive.
+    for _ in 0..30 {
+        game.update_waves(&mut scene, Waves::INTERVAL);
+    }
+
+    let budget = game.enemy_budget();
+    assert_eq!(game.enemies.len(), budget);
+    let chunks = scene.terrain().count() + Game::WEAPON_CHUNKS + game.enemies.len();
+    assert!(chunks <= Renderer::MAX_CHUNKS, "{chunks} chunks");
+}
+
+#[test]
+fn enemy_state_transitions() {
+    use EnemyState::*;
+
+    let far = EnemyState::ATTACK_RANGE * 4.0;
+    let near = EnemyState::ATTACK_RANGE / 2.0;
+
+    let mut state = Idle;
+    let script = [
+        ((false, far, 100), Idle),
+        ((true, far, 100), Chase),
+        ((true, near, 100), Attack),
+        ((true, far, 100), Chase),
+        ((false, far, 100), Idle),
+        ((true, near, 100), Attack),
+        ((true, near, 10), Flee),
+        // Fleeing enemies never come back.
+        ((true, near, 100), Flee),
+    ];
+
+    for ((sees_player, distance, health), expected) in script {
+        state = state.next(sees_player, distance, health);
+        assert_eq!(state, expected);
+    }
+}
+
+#[test]
+fn game_over() {
+    let mut scene = Scene::empty();
+    let mut game = Game::new(&mut scene);
+
+    let terrain: Vec<_> = scene.terrain().map(|(id, _)| id).collect();
+    for id in &terrain {
+        scene.remove_terrain(id);
+    }
+
+    // Place the enemy right next to the player, looking at them.
+    let player = scene.camera().translation();
+    let (brain, enemy) = &mut game.enemies[0];
+    brain.position = (player + vec3(1.0, 0.0, 0.0)).as_uvec3();
+    brain.facing = (player - brain.eye()).normalize();
+    let object = scene.scene_graph.object_mut(&enemy.id).unwrap();
+    object.transform.translation = brain.position.as_vec3();
+
+    assert!(!game.is_game_over());
+    for _ in 0..100 {
+        game.update_enemies(&mut scene, 0.5);
+    }
+
+    assert_eq!(game.enemies[0].1.state, EnemyState::Attack);
+    assert_eq!(game.health, 0);
+    assert!(game.is_game_over());
+}
+
+#[test]
+fn chase_route() {
+    let mut scene = Scene::empty();
+    let mut game = Game::new(&mut scene);
+
+    let terrain: Vec<_> = scene.terrain().map(|(id, _)| id).collect();
+    for id in &terrain {
+        scene.remove_terrain(id);
+    }
+
+    // Place the enemy out of reach of the player, looking at them.
+    let player = scene.camera().translation();
+    let (brain, enemy) = &mut game.enemies[0];
+    brain.position = (player + vec3(10.0, 0.0, 0.0)).as_uvec3();
+    brain.facing = (player - brain.eye()).normalize();
+    let object = scene.scene_graph.object_mut(&enemy.id).unwrap();
+    object.transform.translation = brain.position.as_vec3();
+
+    game.update_enemies(&mut scene, 0.0);
+    assert_eq!(game.enemies[0].1.state, EnemyState::Chase);
+
+    // The route leads from the enemy to the player, which is a line to draw.
+    let route = game.enemy_routes().next().unwrap();
+    assert_eq!(route, [game.enemies[0].0.position, player.as_uvec3()]);
+    assert_eq!(route.windows(2).count(), 1);
+}
+
+#[test]
+fn shoot_animation_is_framerate_independent() {
+    let mut scene = Scene::empty();
+    let mut game = Game::new(&mut scene);
+    let gun = match &game.weapon {
+        Weapon::Gun(gun, _) => gun.clone(),
+        Weapon::Knife(_) => unreachable!("the game starts with a gun"),
+    };
+    let translation = |scene: &mut Scene| {
+        scene
+            .scene_graph
+            .object_mut(&gun)
+            .unwrap()
+            .transform
+            .translation
+    };
+    let start = translation(&mut scene);
+
+    for fps in [30.0, 60.0, 144.0, 240.0] {
+        let dt = 1.0 / fps;
+        let mut elapsed = 0.0;
+
+        game.time_since_shoot = Some(0.0);
+        while game.time_since_shoot.is_some() {
+            game.handle_shoot(&mut scene, dt);
+            elapsed += dt;
+        }
+
+        assert!(elapsed >= 2.0 * Game::RECOIL, "{fps} FPS took {elapsed}s");
+        assert!(
+            elapsed < 2.0 * Game::RECOIL + dt,
+            "{fps} FPS took {elapsed}s"
+        );
+
+        let end = translation(&mut scene);
+        assert!(start.distance(end) < 1e-3, "the gun moved at {fps} FPS");
+    }
+}
+
+#[test]
+fn ray_hit_normal() {
+    let mut scene = Scene::empty();
+    let root = scene.scene_graph.root();
+    let mut model = Model::default();
+    model.positions.push((vec3(3.0, 0.0, 0.0), MaterialId(1)));
+    let object = Object::with_tag(Transform::IDENTITY, model, "enemy".to_string());
+    let id = scene.scene_graph.insert_entity(object, &root);
+    scene.scene_graph.evaluate_all();
+
+    // A ray going along +x enters the voxel through its -x face.
+    let hit = Ray::with_len(vec3(0.5, 0.5, 0.5), Vec3::X, 10.0).cast_object(&scene, "enemy");
+    assert_eq!(
+        hit,
+        Some(Hit {
+            node: id,
+            voxel: uvec3(3, 0, 0),
+            normal: ivec3(-1, 0, 0),
+            distance: 2.5,
+        })
+    );
+
+    let hit = Ray::with_len(vec3(6.5, 0.5, 0.5), -Vec3::X, 10.0).cast_object(&scene, "enemy");
+    assert_eq!(hit.map(|hit| hit.normal), Some(ivec3(1, 0, 0)));
+
+    // Rays stop at their length, and rays going away from every object stop at
+    // once.
+    let short = Ray::with_len(vec3(0.5, 0.5, 0.5), Vec3::X, 2.0);
+    assert_eq!(short.cast_object(&scene, "enemy"), None);
+    let away = Ray::new(vec3(0.5, 0.5, 0.5), -Vec3::X);
+    assert_eq!(away.cast_object(&scene, "enemy"), None);
+}
+
+#[test]
+fn place_block() {
+    let mut scene = Scene::empty();
+    let mut chunk = SparseTensorChunk::nothing(uvec3(3, 3, 3));
+    chunk.insert(uvec3(1, 1, 1), Some(MaterialId(1)));
+    let id = scene.add_terrain(chunk);
+
+    let ray = Ray::with_len(vec3(1.5, 1.5, 5.5), -Vec3::Z, 10.0);
+    let hit = ray.cast_terrain(&scene).unwrap();
+    assert_eq!((hit.voxel, hit.normal), (uvec3(1, 1, 1), ivec3(0, 0, 1)));
+
+    assert!(Game::place_block(&mut scene, &hit, MaterialId(2)));
+    let chunk = scene.terrain().next().unwrap().1;
+    assert_eq!(chunk.voxel(uvec3(1, 1, 2)), Some(&MaterialId(2)));
+
+    // The next block would be outside of the chunk.
+    let hit = ray.cast_terrain(&scene).unwrap();
+    assert_eq!((&hit.node, hit.voxel), (&id, uvec3(1, 1, 2)));
+    assert!(!Game::place_block(&mut scene, &hit, MaterialId(2)));
+}
+
+#[test]
+fn place_block_across_chunks() {
+    let mut scene = Scene::empty();
+    let mut chunk = SparseTensorChunk::nothing(uvec3(1, 1, 1));
+    chunk.insert(UVec3::ZERO, Some(MaterialId(1)));
+    let hit_id = scene.add_terrain(chunk);
+
+    // An empty chunk right in front of the face that is hit.
+    let mut next = SparseTensorChunk::nothing(uvec3(1, 1, 1));
+    next.transform = Mat4::from_translation(Vec3::Z);
+    let next_id = scene.add_terrain(next);
+
+    let ray = Ray::with_len(vec3(0.5, 0.5, 5.5), -Vec3::Z, 10.0);
+    let hit = ray.cast_terrain(&scene).unwrap();
+    assert_eq!((&hit.node, hit.normal), (&hit_id, ivec3(0, 0, 1)));
+
+    assert!(Game::place_block(&mut scene, &hit, MaterialId(2)));
+    let next = scene.terrain_mut(&next_id).unwrap();
+    assert_eq!(next.voxel(UVec3::ZERO), Some(&MaterialId(2)));
+}
+
+#[test]
+fn damage_popup() {
+    let window_size = uvec2(640, 480);
+    let mut camera = Camera::new(Vec3::ZERO, 4.0 / 3.0);
+    camera.set_direction(vec3(0.0, 0.0, -1.0));
+
+    let mut popup = DamagePopup::new(vec3(0.0, 0.0, -20.0), 10);
+    let start = popup.screen_position(&camera, window_size).unwrap();
+    assert_eq!(start, window_size / 2);
+    assert_eq!(popup.text(&camera, window_size).unwrap().text, "10");
+
+    // The popup stays where it is in the world when the camera turns.
+    camera.set_direction(vec3(0.2, 0.0, -1.0));
+    let turned = popup.screen_position(&camera, window_size).unwrap();
+    assert!(turned.x < start.x);
+
+    assert!(popup.step(DamagePopup::LIFETIME / 2.0));
+    let risen = popup.screen_position(&camera, window_size).unwrap();
+    assert!(risen.y > turned.y);
+
+    assert!(!popup.step(DamagePopup::LIFETIME / 2.0));
+}
+
+#[test]
+fn jump_apex() {
+    let apex = |jump_velocity| {
+        let settings = GameSettings {
+            jump_velocity,
+            ..Default::default()
+        };
+        let (mut height, mut velocity) = (0.0, jump_velocity);
+        let mut apex: f32 = 0.0;
+        while height >= 0.0 {
+            (height, velocity) = Game::fall(&settings, height, velocity, 1.0 / 600.0);
+            apex = apex.max(height);
+        }
+        apex
+    };
+
+    // The apex is at v² / 2g, so twice the speed jumps four times as high.
+    let settings = GameSettings::default();
+    let expected = settings.jump_velocity.powi(2) / (2.0 * settings.gravity);
+    let low = apex(settings.jump_velocity);
+    let high = apex(2.0 * settings.jump_velocity);
+    assert!((low / expected - 1.0).abs() < 0.02, "{low}");
+    assert!((high / low - 4.0).abs() < 0.05, "{high} {low}");
+}
+
+#[test]
+fn scripted_game() {
+    let script = InputScript::default()
+        .hold(&[], 1)
+        .hold(&[Scancode::W], 30)
+        .hold(&[Scancode::D], 10)
+        .mouse(MouseState {
+            dx: 90,
+            ..Default::default()
+        })
+        .hold(&[Scancode::W], 5);
+
+    let play = || {
+        let mut scene = Scene::empty();
+        let mut game = Game::new(&mut scene);
+        script.play(&mut game, &mut scene);
+        (scene, game)
+    };
+    let (scene, game) = play();
+
+    // Forward is along -z until the mouse turns the player to face +x.
+    let player = scene.camera().translation();
+    assert!(
+        player.abs_diff_eq(vec3(15.0, 16.0, -30.0), 1e-2),
+        "{player}"
+    );
+    assert_eq!(game.enemies.len(), 1);
+    assert_eq!(game.health, 100);
+
+    // Every run of the script ends the same way.
+    let (again_scene, again) = play();
+    assert_eq!(again_scene.camera().translation(), player);
+    let positions = |game: &Game| -> Vec<_> {
+        game.enemies
+            .iter()
+            .map(|(brain, _)| brain.position)
+            .collect()
+    };
+    assert_eq!(positions(&again), positions(&game));
+}
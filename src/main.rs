@@ -1,6 +1,8 @@
 #![feature(iter_array_chunks, let_chains, slice_as_chunks, array_chunks, test)]
 #![feature(option_result_contains)]
 
+use std::collections::HashSet;
+
 use glam::*;
 use sdl2::event::*;
 use sdl2::keyboard::Scancode;
@@ -8,15 +10,21 @@ use sdl2::mouse::MouseButton;
 use sdl2::video::*;
 use sdl2::*;
 
+use crate::clock::*;
+use crate::console::*;
 use crate::game::*;
 use crate::renderer::*;
 use crate::scene::*;
 
 mod ai;
+mod assets;
+mod clock;
+mod console;
 mod format;
 mod game;
 mod renderer;
 mod rhi;
+mod rng;
 mod scene;
 mod tensor;
 mod terrain;
@@ -24,10 +32,23 @@ mod terrain;
 const WIDTH: u32 = 640;
 const HEIGHT: u32 = 480;
 const ASPECT_RATIO: f32 = WIDTH as f32 / HEIGHT as f32;
-
-fn setup_window(video_subsystem: &VideoSubsystem) -> Window {
+/// The seconds between two updates of the game. Frames are rendered in between
+/// with the transforms interpolated, so the frame rate doesn't depend on it.
+const UPDATE_INTERVAL: f32 = 1.0 / 60.0;
+/// The most time the game catches up on at once, so a long frame doesn't lead
+/// to ever longer frames of updates.
+const MAX_LAG: f32 = 0.25;
+
+fn setup_window(video_subsystem: &VideoSubsystem, msaa_samples: u8) -> Window {
     video_subsystem.gl_attr().set_context_version(4, 6);
-    // video_subsystem.gl_attr().set_multisample_samples(4);
+    // The outline of the highlighted object is masked with the stencil buffer.
+    video_subsystem.gl_attr().set_stencil_size(8);
+    if msaa_samples > 0 {
+        video_subsystem.gl_attr().set_multisample_buffers(1);
+        video_subsystem
+            .gl_attr()
+            .set_multisample_samples(msaa_samples);
+    }
     video_subsystem
         .gl_attr()
         .set_context_profile(GLProfile::Core);
@@ -43,15 +64,44 @@ fn setup_window(video_subsystem: &VideoSubsystem) -> Window {
     window
 }
 
+/// A console command, which is given its arguments and the state of the game.
+type Command = fn(&[&str], &mut Scene, &mut Game, &mut Renderer) -> Result<(), String>;
+
+fn setup_console() -> Console<Command> {
+    let mut console: Console<Command> = Console::new();
+    console.register("spawn", |args, scene, game, _| {
+        game.add_enemy(scene, UVec3::from_array(parse_args(args)?));
+        Ok(())
+    });
+    console.register("teleport", |args, scene, _, _| {
+        scene
+            .camera_mut()
+            .set_translation(Vec3::from_array(parse_args(args)?));
+        Ok(())
+    });
+    console.register("time", |args, scene, _, _| {
+        let [time] = parse_args::<f32, 1>(args)?;
+        scene.time_of_day = time.rem_euclid(1.0);
+        Ok(())
+    });
+    console.register("gbuffer", |args, _, _, renderer| {
+        parse_args::<String, 0>(args)?;
+        renderer.set_debug_view(GBufferTarget::cycle(renderer.debug_view()));
+        Ok(())
+    });
+    console
+}
+
 fn main() -> Result<(), String> {
     let sdl = sdl2::init()?;
     let video_subsystem = sdl.video()?;
     let audio_subsystem = sdl.audio()?;
     let mut event_pump = sdl.event_pump()?;
 
-    let mut window = setup_window(&video_subsystem);
+    let config = RendererConfig::default();
+    let mut window = setup_window(&video_subsystem, config.msaa_samples);
     let mut window_size = uvec2(WIDTH, HEIGHT);
-    let mut renderer = Renderer::new(&window, true);
+    let mut renderer = Renderer::new(&window, config).map_err(|e| e.to_string())?;
 
     let camera = Camera::new(Vec3::new(0.0, 0.0, -2.0), ASPECT_RATIO);
     let mut scene = Scene::new(camera);
@@ -64,7 +114,12 @@ fn main() -> Result<(), String> {
         dy: 0,
     };
 
-    let mut dt = 1.0;
+    // The FPS counter averages over about a second of frames.
+    let mut clock = Clock::new(60);
+    let mut show_routes = false;
+    let mut console = setup_console();
+    // The time the game is behind the clock, which the next updates catch up on.
+    let mut lag = 0.0;
     'running: loop {
         for event in event_pump.poll_iter() {
             #[allow(clippy::collapsible_match, clippy::single_match)]
@@ -78,9 +133,10 @@ fn main() -> Result<(), String> {
                     WindowEvent::Close => break 'running,
                     _ => {}
                 },
+                // Frames without an update keep the motion for the next one.
                 Event::MouseMotion { xrel, yrel, .. } => {
-                    mouse_state.dx = xrel;
-                    mouse_state.dy = yrel;
+                    mouse_state.dx += xrel;
+                    mouse_state.dy += yrel;
                 }
                 Event::MouseButtonDown { mouse_btn, .. } => match mouse_btn {
                     MouseButton::Left => mouse_state.has_mouse_left_been_clicked = true,
@@ -102,25 +158,93 @@ fn main() -> Result<(), String> {
 
                     window.set_fullscreen(fullscreen).unwrap();
                 }
+                Event::KeyDown { scancode, .. } if scancode == Some(Scancode::F3) => {
+                    renderer.set_debug_view(GBufferTarget::cycle(renderer.debug_view()));
+                }
+                Event::KeyDown { scancode, .. } if scancode == Some(Scancode::F4) => {
+                    let overlay = match renderer.occupancy_overlay() {
+                        Some(_) => None,
+                        None => scene.terrain().next().map(|(id, _)| id),
+                    };
+                    renderer.set_occupancy_overlay(overlay);
+                }
+                Event::KeyDown { scancode, .. } if scancode == Some(Scancode::F5) => {
+                    show_routes = !show_routes;
+                }
+                Event::KeyDown { scancode, .. } if scancode == Some(Scancode::F12) => {
+                    renderer.screenshot("screenshot.png");
+                }
+                Event::KeyDown { scancode, .. } if scancode == Some(Scancode::Grave) => {
+                    console.open = !console.open;
+                }
+                Event::TextInput { text, .. } if console.open => {
+                    // The key opening the console is typed as well.
+                    console.input.extend(text.chars().filter(|&c| c != '`'));
+                }
+                Event::KeyDown { scancode, .. } if console.open => match scancode {
+                    Some(Scancode::Backspace) => {
+                        console.input.pop();
+                    }
+                    Some(Scancode::Return) => console.submit(|command, args| {
+                        command(args, &mut scene, &mut game, &mut renderer)
+                    }),
+                    _ => {}
+                },
                 Event::Quit { .. } => break 'running,
                 _ => {}
             }
         }
 
-        if let Some(time) = renderer.render(&mut scene) {
-            dt = (time / 1000.0) as _;
+        // The enemy in the crosshair is outlined as the target.
+        renderer.set_highlight(game.target(&scene));
+
+        if show_routes {
+            for route in game.enemy_routes() {
+                renderer.draw_route(&route, Mat4::IDENTITY, vec4(1.0, 0.0, 0.0, 1.0));
+            }
         }
 
-        let mut systems = GameSystems {
-            window_size,
-            keyboard: event_pump.keyboard_state(),
-            mouse: mouse_state,
-            dt,
-        };
+        // The console is drawn on top of the text of the game while it is open.
+        let text_len = scene.text.len();
+        if console.open {
+            scene
+                .text
+                .extend(console.texts(uvec2(0, window_size.y / 2)));
+        }
 
-        game.run(&mut systems, &mut scene);
+        // The frame lies between the last two updates, as far past the previous one
+        // as the game is behind the clock.
+        renderer.render(&mut scene, lag / UPDATE_INTERVAL);
+        scene.text.truncate(text_len);
+        lag = (lag + clock.tick()).min(MAX_LAG);
+
+        let keyboard: HashSet<_> = event_pump.keyboard_state().pressed_scancodes().collect();
+        while lag >= UPDATE_INTERVAL {
+            lag -= UPDATE_INTERVAL;
+
+            // Clicks and mouse motion are only handled by the first update after them.
+            let mut systems = GameSystems {
+                window_size,
+                keyboard: keyboard.clone(),
+                mouse: std::mem::take(&mut mouse_state),
+                dt: UPDATE_INTERVAL,
+                fps: clock.fps(),
+            };
+
+            // The game is paused while the console takes the keyboard.
+            scene.scene_graph.store_previous_transforms();
+            if !console.open {
+                game.run(&mut systems, &mut scene);
+            }
 
-        mouse_state = MouseState::default();
+            if game.is_game_over() {
+                // Start over in a fresh scene.
+                let mut camera = Camera::new(Vec3::new(0.0, 0.0, -2.0), ASPECT_RATIO);
+                camera.resize(window_size.x as f32, window_size.y as f32);
+                scene = Scene::new(camera);
+                game = Game::new(&mut scene);
+            }
+        }
     }
 
     Ok(())
@@ -1,18 +1,60 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
 use glam::*;
 
+use super::profiler::Profiler;
 use super::{CubeVertex, QuadVertex, CUBE, QUAD};
 use crate::rhi::*;
 use crate::scene::*;
+use crate::tensor::{SparseTensorChunk, FACES};
+
+/// The per-instance data of a voxel, interleaved in a single instance buffer.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(super) struct Voxel {
+    pub(super) position: Vec3,
+    // Since we only allow 256 chunks in a drawcall a u16 saves us some bandwidth.
+    pub(super) chunk_id: u16,
+    // Since we only allow 256 materials a u16 saves us some bandwidth.
+    pub(super) material_id: u16,
+    /// The share of ambient light at the corners of the faces, see
+    /// [`DeferredRenderer::voxel_occlusion`].
+    pub(super) occlusion: [u32; 2],
+}
+
+impl Voxel {
+    /// The occlusion of a voxel whose faces are lit at every corner.
+    const UNOCCLUDED: [u32; 2] = [u32::MAX, 0xFFFF];
+}
 
-struct Voxel {
-    position: Vec3,
-    chunk_id: u16, // Since we only allow 256 chunks in a drawcall a u16 saves us some bandwidth.
-    material_id: u16, // Since we only allow 256 materials a u16 saves us some bandwidth.
+/// The voxels of a model shared by several objects, which are drawn together
+/// with one instanced draw call. The objects take consecutive chunks, starting
+/// at `chunk_id`, which is also the chunk id of the voxels.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct InstancedModel {
+    pub(super) chunk_id: u16,
+    pub(super) voxels: Vec<Voxel>,
+    pub(super) instances: usize,
+}
+
+#[cfg(test)]
+impl InstancedModel {
+    /// The voxels of every object drawn with the model, each pointed at the
+    /// chunk of its object.
+    pub(super) fn expand(&self) -> impl Iterator<Item = Voxel> + '_ {
+        (0..self.instances as u16).flat_map(move |instance| {
+            self.voxels.iter().map(move |voxel| Voxel {
+                chunk_id: voxel.chunk_id + instance,
+                ..*voxel
+            })
+        })
+    }
 }
 
 unsafe impl BufferLayout for Voxel {
-    const LAYOUT: &'static [Format] = &[Format::Vec3, Format::U16, Format::U16];
-    const PADDING: &'static [usize] = &[0, 0, 0];
+    const LAYOUT: &'static [Format] = &[Format::Vec3, Format::U16, Format::U16, Format::UVec2];
+    const PADDING: &'static [usize] = &[0, 0, 0, 0];
     const COPYABLE: bool = true;
 
     fn to_bytes(items: &[Self]) -> Vec<u8> {
@@ -20,6 +62,31 @@ unsafe impl BufferLayout for Voxel {
     }
 }
 
+/// The occlusion query of the bounds of a terrain chunk. The GPU runs a frame
+/// or more behind, so the query is only issued again once its result is back,
+/// and until then the last result is used.
+struct OcclusionQuery {
+    query: Query,
+    /// Whether the query was issued and its result isn't back yet.
+    pending: bool,
+    /// Whether no sample of the bounds passed as of the last result.
+    hidden: bool,
+}
+
+/// A world space position and color of one end of a debug line.
+#[repr(C)]
+struct LineVertex(Vec4, Vec4);
+
+unsafe impl BufferLayout for LineVertex {
+    const LAYOUT: &'static [Format] = &[Format::Vec4, Format::Vec4];
+    const PADDING: &'static [usize] = &[0, 0];
+    const COPYABLE: bool = true;
+
+    fn to_bytes(_items: &[Self]) -> Vec<u8> {
+        unimplemented!()
+    }
+}
+
 unsafe impl BufferLayout for [Mat4; 2] {
     const LAYOUT: &'static [Format] = &[Format::Mat4, Format::Mat4];
     const PADDING: &'static [usize] = &[0, 0];
@@ -81,16 +148,99 @@ unsafe impl BufferLayout for Light {
     }
 }
 
+/// The render targets of the g-buffer, in the order they are attached to the
+/// framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GBufferTarget {
+    Position,
+    Normal,
+    Albedo,
+    RoughnessAndMetalness,
+    /// The tangent frame for normal-mapped materials. No material has a
+    /// normal map yet, so the lighting pass doesn't sample it and it is only
+    /// shown by the debug view.
+    Tangent,
+}
+
+impl GBufferTarget {
+    const ALL: [Self; 5] = [
+        Self::Position,
+        Self::Normal,
+        Self::Albedo,
+        Self::RoughnessAndMetalness,
+        Self::Tangent,
+    ];
+
+    /// The index of the color attachment holding the target.
+    pub fn attachment(&self) -> usize {
+        Self::ALL.iter().position(|target| target == self).unwrap()
+    }
+
+    /// Steps through every target and then back to the lit scene (`None`).
+    pub fn cycle(current: Option<Self>) -> Option<Self> {
+        match current {
+            None => Some(Self::ALL[0]),
+            Some(target) => Self::ALL.get(target.attachment() + 1).copied(),
+        }
+    }
+}
+
+unsafe impl BufferLayout for DirectionalLight {
+    const LAYOUT: &'static [Format] = &[Format::Vec4, Format::Vec4];
+    const PADDING: &'static [usize] = &[0, 0];
+    const COPYABLE: bool = false;
+
+    fn to_bytes(items: &[Self]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(items.len() * Self::stride());
+
+        for item in items {
+            let direction: [u8; 12] = unsafe { std::mem::transmute(item.direction) };
+            bytes.extend(direction);
+            bytes.extend(0.0f32.to_ne_bytes());
+
+            let color: [u8; 12] = unsafe { std::mem::transmute(item.color) };
+            bytes.extend(color);
+            bytes.extend(1.0f32.to_ne_bytes());
+        }
+
+        bytes
+    }
+}
+
+// The amount of frames the GPU may still be working on while the next one is
+// written, which is how many copies there are of the buffers written every
+// frame.
+const FRAMES_IN_FLIGHT: usize = 3;
+
 pub struct DeferredRenderer<'a> {
     device: Device<'a>,
     cube_buffer: Buffer<CubeVertex, false, false>,
     quad_buffer: Buffer<QuadVertex, false, false>,
-    matrix_buffer: Buffer<[Mat4; 2], false, true>,
-    material_buffer: Buffer<Material, false, true>,
-    light_buffer: Buffer<Light, false, true>,
-    camera_buffer: Buffer<Vec4, false, true>,
+    matrix_buffer: RingBuffer<[Mat4; 2], FRAMES_IN_FLIGHT>,
+    tint_buffer: RingBuffer<Vec4, FRAMES_IN_FLIGHT>,
+    material_buffer: RingBuffer<Material, FRAMES_IN_FLIGHT>,
+    light_buffer: UniformBuffer<Light>,
+    camera_buffer: UniformBuffer<Vec4>,
+    sun_buffer: UniformBuffer<DirectionalLight>,
     program: ShaderProgram,
     lighting_program: ShaderProgram,
+    debug_program: ShaderProgram,
+    debug_view: Option<GBufferTarget>,
+    skybox_program: ShaderProgram,
+    skybox_buffer: UniformBuffer<Mat4>,
+    environment: Option<TextureCube>,
+    black_environment: TextureCube,
+    occlusion_program: ShaderProgram,
+    bounds_buffer: UniformBuffer<Mat4>,
+    occlusion_buffer: RingBuffer<Mat4, FRAMES_IN_FLIGHT>,
+    occlusion_queries: HashMap<TerrainId, OcclusionQuery>,
+    occupancy_program: ShaderProgram,
+    occupancy_overlay: Option<TerrainId>,
+    outline_program: ShaderProgram,
+    highlight: Option<SceneNodeId>,
+    line_program: ShaderProgram,
+    lines: Vec<LineVertex>,
+    cull_program: ShaderProgram,
     framebuffer: Framebuffer,
 }
 
@@ -99,12 +249,26 @@ impl<'a> DeferredRenderer<'a> {
     const DS_PIXEL_SHADER_SRC: &str = include_str!("./shaders/ds.frag");
     const DS_LIGHTING_VERTEX_SHADER_SRC: &str = include_str!("./shaders/ds_lighting.vert");
     const DS_LIGHTING_PIXEL_SHADER_SRC: &str = include_str!("./shaders/ds_lighting.frag");
+    const DS_DEBUG_PIXEL_SHADER_SRC: &'static str = include_str!("./shaders/ds_debug.frag");
+    const SKYBOX_VERTEX_SHADER_SRC: &'static str = include_str!("./shaders/skybox.vert");
+    const SKYBOX_PIXEL_SHADER_SRC: &'static str = include_str!("./shaders/skybox.frag");
+    const OCCLUSION_VERTEX_SHADER_SRC: &'static str = include_str!("./shaders/occlusion.vert");
+    const OCCLUSION_PIXEL_SHADER_SRC: &'static str = include_str!("./shaders/occlusion.frag");
+    const OCCUPANCY_VERTEX_SHADER_SRC: &'static str = include_str!("./shaders/occupancy.vert");
+    const OCCUPANCY_PIXEL_SHADER_SRC: &'static str = include_str!("./shaders/occupancy.frag");
+    const LINE_VERTEX_SHADER_SRC: &'static str = include_str!("./shaders/line.vert");
+    const LINE_PIXEL_SHADER_SRC: &'static str = include_str!("./shaders/line.frag");
+    const OUTLINE_PIXEL_SHADER_SRC: &'static str = include_str!("./shaders/outline.frag");
+    const CULL_COMPUTE_SHADER_SRC: &'static str = include_str!("./shaders/cull.comp");
+
+    // The amount of voxels tested by a single work group of the culling shader.
+    const CULL_GROUP_SIZE: usize = 64;
 
     // The maximum amount of chunks that can be grouped into a single drawcall.
     //
     // OpenGL is required to support at least 16384 bytes for uniform buffers.
     // MAX_CHUNKS * (2 * std::mem::size_of::<Mat4>()) < 16384
-    const MAX_CHUNKS: usize = 170;
+    pub const MAX_CHUNKS: usize = 170;
 
     // The maximum amount of materials that can be used at any given time.
     const MAX_MATERIALS: usize = 256;
@@ -112,92 +276,376 @@ impl<'a> DeferredRenderer<'a> {
     // The maximum amount of lights that can be used at any given time.
     const MAX_LIGHTS: usize = 256;
 
-    pub fn new(device: Device<'a>, window_size: UVec2) -> Self {
+    // How close in voxels of a chunk the camera may get to its bounds before it
+    // counts as inside of them, so the near plane never cuts into them.
+    const CAMERA_MARGIN: f32 = 1.0;
+
+    // How many voxels of the highlighted object its outline reaches out by.
+    const OUTLINE_WIDTH: f32 = 0.5;
+
+    pub fn new(device: Device<'a>, window_size: UVec2) -> Result<Self, RhiError> {
         // The cube buffer is static since we use instanced rendering, so it is uploaded
         // once at the creation of the renderer.
         let cube_buffer = device.new_buffer(BufferInit::Data(&CUBE));
         let quad_buffer = device.new_buffer(BufferInit::Data(&QUAD));
 
         // We preallocate space for the various kinds of uniform buffers.
-        let matrix_buffer = device.new_buffer(BufferInit::Capacity(Self::MAX_CHUNKS));
-        let material_buffer = device.new_buffer(BufferInit::Capacity(Self::MAX_MATERIALS));
-        let light_buffer = device.new_buffer(BufferInit::Capacity(Self::MAX_LIGHTS));
-        let camera_buffer = device.new_buffer(BufferInit::Capacity(1));
+        let matrix_buffer = device.new_ring_buffer("Chunks", 0, Self::MAX_CHUNKS);
+        let tint_buffer = device.new_ring_buffer("Tints", 2, Self::MAX_CHUNKS);
+        let material_buffer = device.new_ring_buffer("Materials", 1, Self::MAX_MATERIALS);
+        let light_buffer = device.new_uniform_buffer("Lights", 0, Self::MAX_LIGHTS);
+        let camera_buffer = device.new_uniform_buffer("Camera", 1, 1);
+        let sun_buffer = device.new_uniform_buffer("Sun", 2, 1);
 
         let program = {
-            let vertex_shader = device.new_shader(VertexStage, Self::DS_VERTEX_SHADER_SRC);
-            let pixel_shader = device.new_shader(PixelStage, Self::DS_PIXEL_SHADER_SRC);
-            device.new_shader_program(&vertex_shader, &pixel_shader)
+            let vertex_shader = device.new_shader(VertexStage, Self::DS_VERTEX_SHADER_SRC)?;
+            let pixel_shader = device.new_shader(PixelStage, Self::DS_PIXEL_SHADER_SRC)?;
+            device.new_shader_program(&vertex_shader, &pixel_shader)?
         };
 
         let lighting_program = {
-            let vertex_shader = device.new_shader(VertexStage, Self::DS_LIGHTING_VERTEX_SHADER_SRC);
-            let pixel_shader = device.new_shader(PixelStage, Self::DS_LIGHTING_PIXEL_SHADER_SRC);
-            device.new_shader_program(&vertex_shader, &pixel_shader)
+            let vertex_shader =
+                device.new_shader(VertexStage, Self::DS_LIGHTING_VERTEX_SHADER_SRC)?;
+            let pixel_shader = device.new_shader(PixelStage, Self::DS_LIGHTING_PIXEL_SHADER_SRC)?;
+            device.new_shader_program(&vertex_shader, &pixel_shader)?
+        };
+
+        let debug_program = {
+            let vertex_shader =
+                device.new_shader(VertexStage, Self::DS_LIGHTING_VERTEX_SHADER_SRC)?;
+            let pixel_shader = device.new_shader(PixelStage, Self::DS_DEBUG_PIXEL_SHADER_SRC)?;
+            device.new_shader_program(&vertex_shader, &pixel_shader)?
+        };
+
+        let skybox_program = {
+            let vertex_shader = device.new_shader(VertexStage, Self::SKYBOX_VERTEX_SHADER_SRC)?;
+            let pixel_shader = device.new_shader(PixelStage, Self::SKYBOX_PIXEL_SHADER_SRC)?;
+            device.new_shader_program(&vertex_shader, &pixel_shader)?
+        };
+        let skybox_buffer = device.new_uniform_buffer("Matrices", 0, 1);
+
+        // The lighting pass always samples an environment, so without one we bind a
+        // black cubemap which contributes no ambient light.
+        let mut black_environment = device.new_texture_cube(1, Format::R8G8B8A8)?;
+        for face in 0..6 {
+            black_environment.write_face(face, &[0, 0, 0, 255]);
+        }
+
+        let occlusion_program = {
+            let vertex_shader =
+                device.new_shader(VertexStage, Self::OCCLUSION_VERTEX_SHADER_SRC)?;
+            let pixel_shader = device.new_shader(PixelStage, Self::OCCLUSION_PIXEL_SHADER_SRC)?;
+            device.new_shader_program(&vertex_shader, &pixel_shader)?
+        };
+        let bounds_buffer = device.new_uniform_buffer("Bounds", 0, 1);
+        let occlusion_buffer = device.new_ring_buffer("Bounds", 0, Self::MAX_CHUNKS);
+
+        let occupancy_program = {
+            let vertex_shader =
+                device.new_shader(VertexStage, Self::OCCUPANCY_VERTEX_SHADER_SRC)?;
+            let pixel_shader = device.new_shader(PixelStage, Self::OCCUPANCY_PIXEL_SHADER_SRC)?;
+            device.new_shader_program(&vertex_shader, &pixel_shader)?
+        };
+
+        // The outline is made of the voxels of the highlighted object, which are
+        // placed the same way as those of the occupancy overlay.
+        let outline_program = {
+            let vertex_shader =
+                device.new_shader(VertexStage, Self::OCCUPANCY_VERTEX_SHADER_SRC)?;
+            let pixel_shader = device.new_shader(PixelStage, Self::OUTLINE_PIXEL_SHADER_SRC)?;
+            device.new_shader_program(&vertex_shader, &pixel_shader)?
+        };
+
+        let line_program = {
+            let vertex_shader = device.new_shader(VertexStage, Self::LINE_VERTEX_SHADER_SRC)?;
+            let pixel_shader = device.new_shader(PixelStage, Self::LINE_PIXEL_SHADER_SRC)?;
+            device.new_shader_program(&vertex_shader, &pixel_shader)?
+        };
+
+        let cull_program = {
+            let compute_shader = device.new_shader(ComputeStage, Self::CULL_COMPUTE_SHADER_SRC)?;
+            device.new_compute_program(&compute_shader)?
         };
 
-        let framebuffer = Self::setup_framebuffer(&device, window_size);
+        let framebuffer = Self::setup_framebuffer(&device, window_size)?;
 
-        Self {
+        Ok(Self {
             device,
             cube_buffer,
             quad_buffer,
             matrix_buffer,
+            tint_buffer,
             material_buffer,
             light_buffer,
             camera_buffer,
+            sun_buffer,
             program,
             lighting_program,
+            debug_program,
+            debug_view: None,
+            skybox_program,
+            skybox_buffer,
+            environment: None,
+            black_environment,
+            occlusion_program,
+            bounds_buffer,
+            occlusion_buffer,
+            occlusion_queries: HashMap::new(),
+            occupancy_program,
+            occupancy_overlay: None,
+            outline_program,
+            highlight: None,
+            line_program,
+            lines: Vec::new(),
+            cull_program,
             framebuffer,
-        }
+        })
     }
 
-    pub fn render(&mut self, scene: &mut Scene) {
+    /// Draws the scene, timing the geometry, lighting and post passes with
+    /// `profiler` when it's given.
+    pub fn render(&mut self, scene: &mut Scene, mut profiler: Option<&mut Profiler<'a>>) {
         let Self {
             device,
             cube_buffer,
             quad_buffer,
             matrix_buffer,
+            tint_buffer,
             material_buffer,
             light_buffer,
             camera_buffer,
+            sun_buffer,
             program,
             lighting_program,
+            debug_program,
+            debug_view,
+            skybox_program,
+            skybox_buffer,
+            environment,
+            black_environment,
+            occlusion_program,
+            bounds_buffer,
+            occlusion_buffer,
+            occlusion_queries,
+            occupancy_program,
+            occupancy_overlay,
+            outline_program,
+            highlight,
+            line_program,
+            lines,
+            cull_program,
             framebuffer,
         } = self;
 
-        framebuffer.clear(vec4(0.0, 0.0, 0.0, 0.0), true);
+        if let Some(profiler) = profiler.as_deref_mut() {
+            profiler.begin_profile("geometry");
+        }
+
+        // The g-buffer is cleared to zero rather than to the clear color of the
+        // renderer, as a zero albedo alpha is what tells the lighting pass that no
+        // geometry covers a pixel. It then discards the pixel, which leaves the clear
+        // color of the default framebuffer behind the terrain.
+        framebuffer.clear(Vec4::ZERO, true);
 
-        // Write matrices and upload voxels
-        let (matrices, voxels) = Self::extract_matrices_and_voxels(scene);
-        matrix_buffer.map_write().write(&matrices);
-        let voxel_buffer: Buffer<_, false, false> = device.new_buffer(BufferInit::Data(&voxels));
+        // Chunks whose bounding box was completely hidden at the last result of
+        // their query are skipped. The queries of removed chunks go with them.
+        let terrain: HashSet<TerrainId> = scene.terrain().map(|(id, _)| id).collect();
+        occlusion_queries.retain(|id, _| terrain.contains(id));
+        for occlusion in occlusion_queries.values_mut().filter(|o| o.pending) {
+            if let Some(samples) = device.query_result(&occlusion.query) {
+                occlusion.pending = false;
+                occlusion.hidden = samples == 0;
+            }
+        }
+
+        // From inside of its bounds a chunk is always drawn, see below.
+        let camera_position = scene.camera().translation();
+        let occluded: HashSet<TerrainId> = scene
+            .terrain()
+            .filter(|(id, chunk)| {
+                occlusion_queries.get(id).is_some_and(|o| o.hidden)
+                    && !Self::contains_camera(chunk, camera_position)
+            })
+            .map(|(id, _)| id)
+            .collect();
+
+        // Write matrices and tints and upload voxels
+        let (matrices, tints, mut models, mut voxels) =
+            Self::extract_matrices_and_voxels(scene, &occluded);
+
+        // Only distinct materials are uploaded, so the voxels are pointed at the
+        // compacted palette.
+        let (materials, remap) = Self::compact_materials(scene.materials());
+        let model_voxels = models.iter_mut().flat_map(|model| &mut model.voxels);
+        for voxel in voxels.iter_mut().chain(model_voxels) {
+            voxel.material_id = remap[voxel.material_id as usize];
+        }
+
+        matrix_buffer.next().map_write().write(&matrices);
+        tint_buffer.next().map_write().write(&tints);
+        // Buffers can't be empty, and without any terrain voxels nothing is culled
+        // from the one voxel the buffer has room for.
+        let voxel_buffer: Buffer<_, false, false> = match voxels.is_empty() {
+            true => device.new_buffer(BufferInit::Capacity(1)),
+            false => device.new_buffer(BufferInit::Data(&voxels)),
+        };
 
         // Write materials
-        material_buffer.map_write().write(scene.materials());
+        material_buffer.next().map_write().write(&materials);
+
+        // Voxels outside of the view frustum are culled on the GPU, which leaves the
+        // visible voxels packed at the start of their own buffer and the amount of
+        // them in the draw command.
+        let visible_buffer: Buffer<_, false, false> =
+            device.new_buffer(BufferInit::Capacity(voxels.len().max(1)));
+        let command_buffer: Buffer<_, false, false> =
+            device.new_buffer(BufferInit::Data(&[DrawCommand {
+                count: cube_buffer.len() as _,
+                ..Default::default()
+            }]));
+
+        matrix_buffer.current().bind();
+        Self::cull(
+            device,
+            cull_program,
+            &voxel_buffer,
+            &visible_buffer,
+            &command_buffer,
+        );
 
         device.bind_shader_program(program);
 
         device.bind_vertex_buffer(BindProps {
             binding: 0,
-            attributes: &["a_position", "a_normal"],
+            attributes: &["a_position", "a_normal", "a_tangent"],
             buffer: cube_buffer,
             instanced: false,
         });
 
         device.bind_vertex_buffer(BindProps {
             binding: 1,
-            attributes: &["a_offset", "a_chunkId", "a_materialId"],
-            buffer: &voxel_buffer,
+            attributes: &["a_offset", "a_chunkId", "a_materialId", "a_occlusion"],
+            buffer: &visible_buffer,
             instanced: true,
         });
 
         device.bind_framebuffer(framebuffer);
 
-        device.bind_uniform_buffer(matrix_buffer, 0);
-        device.bind_uniform_buffer(material_buffer, 1);
+        matrix_buffer.current().bind();
+        material_buffer.current().bind();
+        tint_buffer.current().bind();
+
+        // The g-buffer holds positions and normals, which must be overwritten by the
+        // closest voxel rather than blended with the voxels behind it.
+        device.set_blend(None);
+        device.set_depth_test(Some(DepthFunc::LessEqual));
+
+        // Every terrain voxel is drawn once with the chunk it was given.
+        device.set_uniform_i32("objects", 1);
+        device.multi_draw_indirect(&command_buffer, 1);
+
+        // The voxels of a model are drawn once for every object using it. Each
+        // voxel is repeated for as many instances as there are objects, and the
+        // shader picks the chunk of the object from the instance index.
+        for model in models.iter().filter(|model| !model.voxels.is_empty()) {
+            let model_buffer: Buffer<_, false, false> =
+                device.new_buffer(BufferInit::Data(&model.voxels));
+
+            device.bind_vertex_buffer(BindProps {
+                binding: 1,
+                attributes: &["a_offset", "a_chunkId", "a_materialId", "a_occlusion"],
+                buffer: &model_buffer,
+                instanced: true,
+            });
+            device.set_instance_divisor(1, model.instances);
+
+            device.set_uniform_i32("objects", model.instances as _);
+            device.draw_instanced(cube_buffer.len(), model.voxels.len() * model.instances);
+        }
+
+        // The bounding box of every chunk is tested against the depth buffer, which
+        // decides whether the chunk is drawn next frame. Skipped chunks are tested
+        // as well, so they reappear once they are no longer hidden.
+        device.bind_shader_program(occlusion_program);
+
+        device.bind_vertex_buffer(BindProps {
+            binding: 0,
+            attributes: &["a_position"],
+            buffer: cube_buffer,
+            instanced: false,
+        });
+
+        device.set_write_mask(false, false);
 
-        device.draw_instanced(cube_buffer.len(), voxel_buffer.len());
+        // The bounds of all chunks are written at once, and every query draws the
+        // bounds of its chunk by picking them with the base instance.
+        let offset = matrices.len() - scene.terrain().count();
+        let bounds: Vec<Mat4> = scene
+            .terrain()
+            .enumerate()
+            .map(|(i, (_, chunk))| matrices[offset + i][1] * Self::chunk_bounds(chunk))
+            .collect();
+        occlusion_buffer.next().map_write().write(&bounds);
+        occlusion_buffer.current().bind();
+
+        for (i, (id, chunk)) in scene.terrain().enumerate() {
+            // From inside of the bounds the near plane cuts them open and the faces
+            // left may be behind the terrain around the camera, which would hide a
+            // chunk the camera is in. Such chunks are drawn without asking.
+            if Self::contains_camera(chunk, camera_position) {
+                continue;
+            }
+
+            match occlusion_queries.get_mut(&id) {
+                Some(occlusion) if occlusion.pending => continue,
+                Some(occlusion) => {
+                    device.restart_query(&occlusion.query);
+                    device.draw_instanced_from(cube_buffer.len(), 1, i);
+                    device.end_query(&occlusion.query);
+                    occlusion.pending = true;
+                }
+                None => {
+                    let query = device.begin_query(QueryKind::SamplesPassed);
+                    device.draw_instanced_from(cube_buffer.len(), 1, i);
+                    device.end_query(&query);
+
+                    let occlusion = OcclusionQuery {
+                        query,
+                        pending: true,
+                        hidden: false,
+                    };
+                    occlusion_queries.insert(id, occlusion);
+                }
+            }
+        }
+
+        device.set_write_mask(true, true);
+
+        if let Some(profiler) = profiler.as_deref_mut() {
+            profiler.end_profile("geometry");
+        }
+
+        // When debugging we show the raw g-buffer target instead of the lit scene.
+        if let Some(target) = debug_view {
+            device.bind_shader_program(debug_program);
+
+            device.bind_vertex_buffer(BindProps {
+                binding: 0,
+                attributes: &["a_position", "a_texcoord"],
+                buffer: quad_buffer,
+                instanced: false,
+            });
+
+            device.bind_framebuffer(&mut device.default_framebuffer());
+            device.set_depth_test(None);
+            device.bind_texture_2d(framebuffer.color(target.attachment()), "gTarget", 0);
+            device.draw(quad_buffer.len());
+            return;
+        }
+
+        if let Some(profiler) = profiler.as_deref_mut() {
+            profiler.begin_profile("lighting");
+        }
 
         // Write lights
         let lights = Self::extract_lights(scene);
@@ -206,8 +654,40 @@ impl<'a> DeferredRenderer<'a> {
         let position = scene.camera().translation();
         let position = vec4(position.x, position.y, position.z, 1.0);
         camera_buffer.map_write().write(&[position]);
+        sun_buffer.map_write().write(&[scene.sun()]);
+
+        // The remaining passes cover the whole screen and must not be rejected by
+        // the depth of whatever was drawn before them.
+        device.bind_framebuffer(&mut device.default_framebuffer());
+        device.set_depth_test(None);
+
+        // The skybox is drawn first, so the lighting pass covers it everywhere except
+        // for the pixels without geometry, which it discards.
+        let environment = environment.as_ref();
+        if let Some(environment) = environment {
+            let camera = scene.camera();
+            let view = Mat4::from_mat3(Mat3::from_mat4(*camera.view()));
+            skybox_buffer
+                .map_write()
+                .write(&[*camera.projection() * view]);
+
+            device.bind_shader_program(skybox_program);
+
+            device.bind_vertex_buffer(BindProps {
+                binding: 0,
+                attributes: &["a_position"],
+                buffer: cube_buffer,
+                instanced: false,
+            });
+
+            skybox_buffer.bind();
+            device.bind_texture_cube(environment, "environment", 0);
+
+            device.draw(cube_buffer.len());
+        }
 
         device.bind_shader_program(&lighting_program);
+        device.set_blend(Some(BlendMode::Alpha));
 
         device.bind_vertex_buffer(BindProps {
             binding: 0,
@@ -216,65 +696,443 @@ impl<'a> DeferredRenderer<'a> {
             instanced: false,
         });
 
-        device.bind_framebuffer(&mut device.default_framebuffer());
-
-        device.bind_uniform_buffer(light_buffer, 0);
-        device.bind_uniform_buffer(camera_buffer, 1);
+        light_buffer.bind();
+        camera_buffer.bind();
+        sun_buffer.bind();
         device.bind_texture_2d(framebuffer.color(0), "gWorldPosition", 0);
         device.bind_texture_2d(framebuffer.color(1), "gNormal", 1);
         device.bind_texture_2d(framebuffer.color(2), "gAlbedo", 2);
         device.bind_texture_2d(framebuffer.color(3), "gRoughnessAndMetalness", 3);
+        device.bind_texture_cube(environment.unwrap_or(black_environment), "environment", 4);
 
         device.draw(quad_buffer.len());
+
+        if let Some(profiler) = profiler.as_deref_mut() {
+            profiler.end_profile("lighting");
+            profiler.begin_profile("post");
+        }
+
+        // The highlighted object is only drawn into the stencil buffer, and then drawn
+        // again grown by the width of the outline where the stencil is still unset,
+        // which leaves its outline on top of everything.
+        let chunk = highlight
+            .as_ref()
+            .and_then(|id| Self::highlighted_chunk(scene, id));
+        let offsets: Vec<Vec3> = chunk.map_or(Vec::new(), |chunk| {
+            models
+                .iter()
+                .find(|model| (model.chunk_id..).take(model.instances).any(|c| c == chunk))
+                .map_or(Vec::new(), |model| {
+                    model.voxels.iter().map(|voxel| voxel.position).collect()
+                })
+        });
+        if let Some(chunk) = chunk.filter(|_| !offsets.is_empty()) {
+            let offset_buffer: Buffer<_, false, false> =
+                device.new_buffer(BufferInit::Data(&offsets));
+            let [_, mvp] = matrices[chunk as usize];
+
+            device.bind_shader_program(outline_program);
+
+            device.bind_vertex_buffer(BindProps {
+                binding: 0,
+                attributes: &["a_position"],
+                buffer: cube_buffer,
+                instanced: false,
+            });
+
+            device.bind_vertex_buffer(BindProps {
+                binding: 1,
+                attributes: &["a_offset"],
+                buffer: &offset_buffer,
+                instanced: true,
+            });
+
+            device.default_framebuffer().clear_stencil();
+            bounds_buffer.map_write().write(&[mvp]);
+            bounds_buffer.bind();
+
+            device.set_write_mask(false, false);
+            device.set_stencil_test(Some(StencilTest::Write(1)));
+            device.draw_instanced(cube_buffer.len(), offset_buffer.len());
+            device.set_write_mask(true, true);
+
+            bounds_buffer
+                .map_write()
+                .write(&[mvp * Self::outline_growth(&offsets)]);
+            bounds_buffer.bind();
+
+            device.set_stencil_test(Some(StencilTest::NotEqual(1)));
+            device.draw_instanced(cube_buffer.len(), offset_buffer.len());
+            device.set_stencil_test(None);
+        }
+
+        // The occupied voxels of the chosen chunk are drawn on top of everything, so
+        // they can be seen through the terrain in front of them.
+        let chunk = occupancy_overlay.as_ref().and_then(|overlay| {
+            scene
+                .terrain()
+                .find(|(id, chunk)| id == overlay && !chunk.data.is_empty())
+        });
+        if let Some((_, chunk)) = chunk {
+            let offsets: Vec<Vec3> = chunk
+                .data
+                .keys()
+                .map(|position| position.as_vec3())
+                .collect();
+            let offset_buffer: Buffer<_, false, false> =
+                device.new_buffer(BufferInit::Data(&offsets));
+
+            device.bind_shader_program(occupancy_program);
+
+            device.bind_vertex_buffer(BindProps {
+                binding: 0,
+                attributes: &["a_position"],
+                buffer: cube_buffer,
+                instanced: false,
+            });
+
+            device.bind_vertex_buffer(BindProps {
+                binding: 1,
+                attributes: &["a_offset"],
+                buffer: &offset_buffer,
+                instanced: true,
+            });
+
+            bounds_buffer
+                .map_write()
+                .write(&[scene.camera().view_projection() * chunk.transform]);
+            bounds_buffer.bind();
+
+            device.draw_instanced(cube_buffer.len(), offset_buffer.len());
+        }
+
+        // Debug lines are drawn on top of everything as well, and only for the
+        // frame they were queued for.
+        if !lines.is_empty() {
+            let line_buffer: Buffer<_, false, false> = device.new_buffer(BufferInit::Data(lines));
+
+            device.bind_shader_program(line_program);
+
+            device.bind_vertex_buffer(BindProps {
+                binding: 0,
+                attributes: &["a_position", "a_color"],
+                buffer: &line_buffer,
+                instanced: false,
+            });
+
+            bounds_buffer
+                .map_write()
+                .write(&[scene.camera().view_projection()]);
+            bounds_buffer.bind();
+
+            device.draw_lines(line_buffer.len());
+            lines.clear();
+        }
+
+        if let Some(profiler) = profiler {
+            profiler.end_profile("post");
+        }
+    }
+
+    /// Sets the cubemap drawn behind the geometry and used for ambient
+    /// lighting. Without an environment the clear color is visible instead.
+    pub fn set_environment(&mut self, environment: Option<TextureCube>) {
+        self.environment = environment;
+    }
+
+    pub fn debug_view(&self) -> Option<GBufferTarget> {
+        self.debug_view
+    }
+
+    /// Shows the chosen g-buffer target full-screen instead of running the
+    /// lighting pass. `None` restores the lit scene.
+    pub fn set_debug_view(&mut self, target: Option<GBufferTarget>) {
+        self.debug_view = target;
+    }
+
+    pub fn occupancy_overlay(&self) -> Option<&TerrainId> {
+        self.occupancy_overlay.as_ref()
+    }
+
+    /// Draws the occupied voxels of the terrain chunk `id` as translucent
+    /// cubes on top of the scene. `None` hides the overlay again.
+    pub fn set_occupancy_overlay(&mut self, id: Option<TerrainId>) {
+        self.occupancy_overlay = id;
+    }
+
+    /// Outlines the object `id` on top of the scene. `None` removes the
+    /// outline again.
+    pub fn set_highlight(&mut self, id: Option<SceneNodeId>) {
+        self.highlight = id;
+    }
+
+    /// Draws `route` through the voxels of a chunk placed by `chunk_transform`
+    /// as connected lines on top of the next frame. Routes have to be drawn
+    /// again every frame to stay visible.
+    pub fn draw_route(&mut self, route: &[UVec3], chunk_transform: Mat4, color: Vec4) {
+        for [a, b] in Self::route_segments(route, chunk_transform) {
+            self.lines.push(LineVertex(a.extend(1.0), color));
+            self.lines.push(LineVertex(b.extend(1.0), color));
+        }
+    }
+
+    /// The world space lines between the centers of consecutive voxels of
+    /// `route`.
+    fn route_segments(route: &[UVec3], chunk_transform: Mat4) -> Vec<[Vec3; 2]> {
+        let centers: Vec<_> = route
+            .iter()
+            .map(|voxel| chunk_transform.transform_point3(voxel.as_vec3() + 0.5))
+            .collect();
+        centers.windows(2).map(|pair| [pair[0], pair[1]]).collect()
     }
 
     pub fn resize(&mut self, window_size: UVec2) {
-        self.framebuffer = Self::setup_framebuffer(&self.device, window_size);
+        self.framebuffer = Self::setup_framebuffer(&self.device, window_size)
+            .expect("failed to recreate the G-buffer");
     }
 
-    fn extract_matrices_and_voxels(scene: &mut Scene) -> (Vec<[Mat4; 2]>, Vec<Voxel>) {
-        let entities = scene.scene_graph.mutated_entities();
+    /// Maps the unit cube to the box enclosing every voxel of `chunk`.
+    fn chunk_bounds(chunk: &SparseTensorChunk) -> Mat4 {
+        let size = chunk.dim.as_vec3();
+        Mat4::from_translation(size / 2.0 - 0.5) * Mat4::from_scale(size)
+    }
+
+    /// Whether `camera` is inside of the bounds of `chunk`, or so close to them
+    /// that the near plane might cut into them.
+    fn contains_camera(chunk: &SparseTensorChunk, camera: Vec3) -> bool {
+        let local = chunk.transform.inverse().transform_point3(camera);
+        let min = Vec3::splat(-0.5 - Self::CAMERA_MARGIN);
+        let max = chunk.dim.as_vec3() - 0.5 + Self::CAMERA_MARGIN;
+        local.cmpge(min).all() && local.cmple(max).all()
+    }
+
+    /// Scales the voxels at `offsets` about their center, so the box enclosing
+    /// them grows by the width of the outline on every side. The cube of a
+    /// voxel is centered on its offset.
+    fn outline_growth(offsets: &[Vec3]) -> Mat4 {
+        let (min, max) = offsets.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), &offset| (min.min(offset - 0.5), max.max(offset + 0.5)),
+        );
+
+        let size = max - min;
+        let center = (min + max) / 2.0;
+        let scale = (size + 2.0 * Self::OUTLINE_WIDTH) / size;
+        Mat4::from_translation(center) * Mat4::from_scale(scale) * Mat4::from_translation(-center)
+    }
+
+    /// Copies the voxels inside of the view frustum of their chunk from
+    /// `voxels` to the start of `visible`, and adds them to the instance
+    /// count of `command`. The chunk matrices must be bound to uniform
+    /// binding 0.
+    fn cull<'b, const R: bool>(
+        device: &Device<'b>,
+        program: &'b ShaderProgram,
+        voxels: &Buffer<Voxel>,
+        visible: &Buffer<Voxel, R>,
+        command: &Buffer<DrawCommand, R>,
+    ) {
+        device.bind_shader_program(program);
+        device.bind_storage_buffer(voxels, 0);
+        device.bind_storage_buffer(visible, 1);
+        device.bind_storage_buffer(command, 2);
+
+        let groups = voxels.len().div_ceil(Self::CULL_GROUP_SIZE);
+        device.dispatch_compute(uvec3(groups as _, 1, 1));
+    }
+
+    /// The matrices and tints of every chunk, the models of the objects and
+    /// the voxels of the terrain. Objects come first, and the objects sharing
+    /// a model take consecutive chunks, see [`Self::grouped_objects`].
+    pub(super) fn extract_matrices_and_voxels(
+        scene: &mut Scene,
+        occluded: &HashSet<TerrainId>,
+    ) -> (Vec<[Mat4; 2]>, Vec<Vec4>, Vec<InstancedModel>, Vec<Voxel>) {
         let camera = scene.camera();
 
-        let objects = |entity: (SceneNodeId, &Entity)| {
-            if let Entity::Object(o) = entity.1.clone() {
-                Some(o)
-            } else {
-                None
+        let mut matrices = Vec::with_capacity(Self::MAX_CHUNKS);
+        // The color of a flash goes in rgb and its strength in alpha.
+        let mut tints = Vec::with_capacity(Self::MAX_CHUNKS);
+        let mut models = Vec::new();
+        for group in Self::grouped_objects(scene) {
+            let chunk_id = matrices.len() as u16;
+            for (_, object) in &group {
+                let model = object.transform.to_mat4() * object.model.transform;
+                matrices.push([model, camera.view_projection() * model]);
+                tints.push(
+                    object
+                        .flash
+                        .map_or(Vec4::ZERO, |(color, strength)| color.extend(strength)),
+                );
             }
-        };
 
-        let mut matrices = Vec::with_capacity(Self::MAX_CHUNKS);
+            let voxels = group[0]
+                .1
+                .model
+                .positions
+                .iter()
+                .filter(|(_, material_id)| !material_id.is_air())
+                .map(|&(position, material_id)| Voxel {
+                    position,
+                    chunk_id,
+                    material_id: material_id.0 as _,
+                    occlusion: Voxel::UNOCCLUDED,
+                })
+                .collect();
+            models.push(InstancedModel {
+                chunk_id,
+                voxels,
+                instances: group.len(),
+            });
+        }
+
+        // We handle the terrain geometry here. Occluded chunks keep their matrices,
+        // so the chunk ids stay the same from frame to frame.
+        let view_projection = camera.view_projection();
+        let terrain: Vec<(TerrainId, Mat4)> = scene
+            .terrain()
+            .map(|(id, chunk)| (id, chunk.transform))
+            .collect();
         let mut voxels = Vec::with_capacity(256 * 256 * 256); // 16 Mib
-        for (i, object) in entities.filter_map(objects).enumerate() {
-            let model = object.transform * object.model.transform;
-            matrices.push([model, camera.view_projection() * model]);
+        let offset = matrices.len();
+        for (i, (id, transform)) in terrain.into_iter().enumerate() {
+            matrices.push([transform, view_projection * transform]);
+            tints.push(Vec4::ZERO);
+            if occluded.contains(&id) {
+                continue;
+            }
+
+            let drawn = scene.drawn_voxels(&id, Self::drawn_voxels).unwrap();
             voxels.extend(
-                object
-                    .model
-                    .positions
+                drawn
                     .iter()
-                    .map(|&(position, material_id)| Voxel {
-                        position,
-                        chunk_id: i as _,
+                    .map(|&(position, material_id, occlusion)| Voxel {
+                        position: position.as_vec3(),
+                        chunk_id: (i + offset) as _,
                         material_id: material_id.0 as _,
+                        occlusion,
                     }),
             );
         }
 
-        // We handle the terrain geometry here
-        let offset = matrices.len();
-        for (i, chunk) in scene.terrain.iter().enumerate() {
-            matrices.push([chunk.transform, camera.view_projection() * chunk.transform]);
-            voxels.extend(chunk.into_iter().map(|(position, material_id)| Voxel {
-                position: position.as_vec3(),
-                chunk_id: (i + offset) as _,
-                material_id: material_id.0 as _,
-            }));
+        assert!(matrices.len() <= Self::MAX_CHUNKS);
+        (matrices, tints, models, voxels)
+    }
+
+    /// The voxels of the terrain chunk `chunk` with the ambient occlusion of
+    /// their faces, which is computed once and kept by the scene until the
+    /// chunk or one of `neighbors` changes, see [`Scene::drawn_voxels`].
+    /// Voxels buried on every side, by the chunk or by `neighbors`, can't be
+    /// seen and are left out.
+    fn drawn_voxels(
+        chunk: &SparseTensorChunk,
+        neighbors: &[&SparseTensorChunk],
+    ) -> Vec<DrawnVoxel> {
+        // The exposed faces of a voxel come one after another.
+        let mut exposed: Vec<UVec3> = chunk
+            .exposed_faces_between(neighbors)
+            .into_iter()
+            .map(|(i, _)| i)
+            .collect();
+        exposed.dedup();
+
+        exposed
+            .into_iter()
+            .map(|i| (i, *chunk.voxel(i).unwrap()))
+            .filter(|(_, material_id)| !material_id.is_air())
+            .map(|(i, material_id)| (i, material_id, Self::voxel_occlusion(chunk, neighbors, i)))
+            .collect()
+    }
+
+    /// The share of ambient light at the corners of the faces of the voxel `i`,
+    /// see [`SparseTensorChunk::face_occlusion`]. The shares come in steps of a
+    /// third, so every corner takes two bits, with four corners per face and
+    /// the faces in the order of [`FACES`]. Voxels outside of the chunk occlude
+    /// if a voxel of `neighbors` covers them, and faces covered by another
+    /// voxel can't be seen, so they are left lit instead of being looked at.
+    pub(super) fn voxel_occlusion(
+        chunk: &SparseTensorChunk,
+        neighbors: &[&SparseTensorChunk],
+        i: UVec3,
+    ) -> [u32; 2] {
+        let outside = |next: IVec3| chunk.solid_between(neighbors, next);
+        let solid = |i: IVec3| match i.cmpge(IVec3::ZERO).all() && chunk.in_bounds(i.as_uvec3()) {
+            true => chunk.voxel(i.as_uvec3()).is_some(),
+            false => outside(i),
+        };
+
+        let mut packed = 0u64;
+        for (face, &direction) in FACES.iter().enumerate() {
+            let corners = match solid(i.as_ivec3() + direction) {
+                true => [1.0; 4],
+                false => chunk.face_occlusion(i, direction, outside),
+            };
+            for (corner, share) in corners.into_iter().enumerate() {
+                let level = (share * 3.0).round() as u64;
+                packed |= level << (2 * (4 * face + corner));
+            }
         }
 
-        assert!(matrices.len() <= Self::MAX_CHUNKS);
-        (matrices, voxels)
+        [packed as u32, (packed >> 32) as u32]
+    }
+
+    /// The objects which are drawn, in the order they were inserted in.
+    fn visible_objects(scene: &Scene) -> impl Iterator<Item = (SceneNodeId, Object)> + '_ {
+        let objects = |(id, entity): (SceneNodeId, &Entity)| match entity {
+            Entity::Object(o) if o.visible => Some((id, o.clone())),
+            _ => None,
+        };
+
+        scene.scene_graph.mutated_entities().filter_map(objects)
+    }
+
+    /// The visible objects grouped by the model they share, with the groups in
+    /// the order of their first object. This is the order of their chunks.
+    fn grouped_objects(scene: &Scene) -> Vec<Vec<(SceneNodeId, Object)>> {
+        let mut groups: Vec<Vec<(SceneNodeId, Object)>> = Vec::new();
+        for (id, object) in Self::visible_objects(scene) {
+            let group = groups
+                .iter_mut()
+                .find(|group| Rc::ptr_eq(&group[0].1.model, &object.model));
+            match group {
+                Some(group) => group.push((id, object)),
+                None => groups.push(vec![(id, object)]),
+            }
+        }
+
+        groups
+    }
+
+    /// The chunk the voxels of the object `id` are drawn with, or `None` if
+    /// the object isn't drawn.
+    fn highlighted_chunk(scene: &Scene, id: &SceneNodeId) -> Option<u16> {
+        let chunk = Self::grouped_objects(scene)
+            .into_iter()
+            .flatten()
+            .position(|(object, _)| &object == id)?;
+        Some(chunk as _)
+    }
+
+    /// Collapses equal materials into one, keeping the first occurrence of
+    /// every material in order. Returns the compacted palette together with
+    /// the index into it of every material in `materials`.
+    fn compact_materials(materials: &[Material]) -> (Vec<Material>, Vec<u16>) {
+        let mut palette: Vec<Material> = Vec::with_capacity(materials.len());
+        let remap = materials
+            .iter()
+            .map(|material| {
+                let index = palette
+                    .iter()
+                    .position(|m| m == material)
+                    .unwrap_or_else(|| {
+                        palette.push(*material);
+                        palette.len() - 1
+                    });
+                index as u16
+            })
+            .collect();
+
+        (palette, remap)
     }
 
     fn extract_lights(scene: &mut Scene) -> Vec<Light> {
@@ -291,23 +1149,515 @@ impl<'a> DeferredRenderer<'a> {
         entities.filter_map(lights).collect()
     }
 
-    fn setup_framebuffer(device: &Device<'a>, window_size: UVec2) -> Framebuffer {
+    fn setup_framebuffer(device: &Device<'a>, window_size: UVec2) -> Result<Framebuffer, RhiError> {
         let [width, height] = window_size.to_array().map(|v| v as _);
-        let positions = device.new_texture_2d(width, height, Format::R32G32B32A32Float);
-        let normals = device.new_texture_2d(width, height, Format::R32G32B32A32Float);
-        let albedo = device.new_texture_2d(width, height, Format::R32G32B32A32Float);
-        let roughness_and_metalness = device.new_texture_2d(width, height, Format::R32G32Float);
+        let positions = device.new_texture_2d(width, height, Format::R32G32B32A32Float)?;
+        let normals = device.new_texture_2d(width, height, Format::R32G32B32A32Float)?;
+        let albedo = device.new_texture_2d(width, height, Format::R32G32B32A32Float)?;
+        let roughness_and_metalness = device.new_texture_2d(width, height, Format::R32G32Float)?;
+        let tangents = device.new_texture_2d(width, height, Format::R32G32B32A32Float)?;
 
-        let depth = device.new_texture_2d(width, height, Format::D24);
+        let depth = device.new_texture_2d(width, height, Format::D24)?;
 
         let attachments = [
             Attachment::Color(positions, 0),
             Attachment::Color(normals, 1),
             Attachment::Color(albedo, 2),
             Attachment::Color(roughness_and_metalness, 3),
+            Attachment::Color(tangents, 4),
             Attachment::Depth(depth),
         ];
 
         device.new_framebuffer(attachments)
     }
 }
+
+#[test]
+fn debug_view_targets() {
+    let mut targets = Vec::new();
+    let mut view = GBufferTarget::cycle(None);
+    while let Some(target) = view {
+        targets.push(target.attachment());
+        view = GBufferTarget::cycle(view);
+    }
+
+    // Position, normal, albedo, roughness/metalness and tangent in the order
+    // they are attached in `setup_framebuffer`.
+    assert_eq!(targets, vec![0, 1, 2, 3, 4]);
+    assert_eq!(GBufferTarget::Albedo.attachment(), 2);
+}
+
+#[test]
+fn air_is_skipped() {
+    use crate::format::vox::{VoxMaterialId, VoxModel};
+
+    let vox = VoxModel {
+        transform: Mat4::IDENTITY,
+        size: (2, 1, 1),
+        positions: vec![
+            (vec3(0.0, 0.0, 0.0), VoxMaterialId(0)),
+            (vec3(1.0, 0.0, 0.0), VoxMaterialId(3)),
+        ],
+    };
+
+    let model = Model::from(vox);
+    assert_eq!(model.positions[0].1, MaterialId::AIR);
+    assert_eq!(model.positions[1].1, MaterialId(3));
+
+    let mut scene = Scene::empty();
+    let root = scene.scene_graph.root();
+    scene
+        .scene_graph
+        .insert_entity(Object::new(Transform::IDENTITY, model), &root);
+    scene.scene_graph.evaluate_all();
+
+    let (_, _, models, _) =
+        DeferredRenderer::extract_matrices_and_voxels(&mut scene, &HashSet::new());
+    let voxels = &models[0].voxels;
+    assert_eq!(voxels.len(), 1);
+    assert_eq!(voxels[0].material_id, 3);
+}
+
+#[test]
+fn duplicate_materials() {
+    let red = Material {
+        albedo: [255, 0, 0, 255],
+        roughness: 0.5,
+        metalness: 0.0,
+    };
+    let blue = Material {
+        albedo: [0, 0, 255, 255],
+        ..red
+    };
+
+    let materials = [Material::AIR, red, blue, red, Material::AIR, blue, red];
+    let (palette, remap) = DeferredRenderer::compact_materials(&materials);
+
+    assert_eq!(palette, vec![Material::AIR, red, blue]);
+    assert_eq!(remap, vec![0, 1, 2, 1, 0, 2, 1]);
+    for (material, index) in materials.iter().zip(&remap) {
+        assert_eq!(palette[*index as usize], *material);
+    }
+}
+
+#[test]
+fn occluded_chunks_are_skipped() {
+    let mut scene = Scene::empty();
+    let mut chunk = SparseTensorChunk::nothing(uvec3(2, 2, 2));
+    chunk.insert(UVec3::ZERO, Some(MaterialId(1)));
+
+    scene.add_terrain(chunk.clone());
+    let hidden = scene.add_terrain(chunk);
+    scene.scene_graph.evaluate_all();
+
+    let occluded = HashSet::from([hidden]);
+    let (matrices, _, _, voxels) =
+        DeferredRenderer::extract_matrices_and_voxels(&mut scene, &occluded);
+    assert_eq!(matrices.len(), 2);
+    assert_eq!(voxels.len(), 1);
+    assert_eq!(voxels[0].chunk_id, 0);
+
+    // The bounds of a 2x2x2 chunk span from the corner of the first voxel to
+    // the opposite corner of the last one.
+    let bounds = DeferredRenderer::chunk_bounds(&SparseTensorChunk::nothing(uvec3(2, 2, 2)));
+    assert_eq!(
+        bounds.transform_point3(Vec3::splat(-0.5)),
+        Vec3::splat(-0.5)
+    );
+    assert_eq!(bounds.transform_point3(Vec3::splat(0.5)), Vec3::splat(1.5));
+}
+
+#[test]
+fn packed_occlusion() {
+    // A voxel with solid voxels above it on two sides of its top face.
+    let mut chunk = SparseTensorChunk::nothing(uvec3(3, 3, 3));
+    chunk.insert(uvec3(1, 1, 1), Some(MaterialId(1)));
+    chunk.insert(uvec3(1, 2, 2), Some(MaterialId(1)));
+    chunk.insert(uvec3(2, 2, 1), Some(MaterialId(1)));
+
+    let [low, high] = DeferredRenderer::voxel_occlusion(&chunk, &[], uvec3(1, 1, 1));
+    let packed = low as u64 | (high as u64) << 32;
+    let level = |face: usize, corner: usize| packed >> (2 * (4 * face + corner)) & 3;
+
+    // The top face spans z and x, and its corner at (+z, +x) is between both
+    // voxels, while the corners next to only one of them are a third darker.
+    let top = FACES.iter().position(|&face| face == IVec3::Y).unwrap();
+    assert_eq!([0, 1, 2, 3].map(|corner| level(top, corner)), [3, 2, 0, 2]);
+
+    // The faces to the sides and below see no voxels in front of them.
+    let bottom = FACES.iter().position(|&face| face == IVec3::NEG_Y).unwrap();
+    assert_eq!([0, 1, 2, 3].map(|corner| level(bottom, corner)), [3; 4]);
+
+    // A voxel on its own is lit everywhere.
+    let mut lone = SparseTensorChunk::nothing(UVec3::ONE);
+    lone.insert(UVec3::ZERO, Some(MaterialId(1)));
+    assert_eq!(
+        DeferredRenderer::voxel_occlusion(&lone, &[], UVec3::ZERO),
+        Voxel::UNOCCLUDED
+    );
+}
+
+#[test]
+fn occlusion_across_chunks() {
+    let mut scene = Scene::empty();
+    let mut chunk = SparseTensorChunk::nothing(UVec3::ONE);
+    chunk.insert(UVec3::ZERO, Some(MaterialId(1)));
+    scene.add_terrain(chunk.clone());
+    scene.scene_graph.evaluate_all();
+
+    let occlusion = |scene: &mut Scene| {
+        let (_, _, _, voxels) =
+            DeferredRenderer::extract_matrices_and_voxels(scene, &HashSet::new());
+        voxels
+            .iter()
+            .find(|voxel| voxel.chunk_id == 0)
+            .unwrap()
+            .occlusion
+    };
+    assert_eq!(occlusion(&mut scene), Voxel::UNOCCLUDED);
+
+    // A voxel of the next chunk over the top face, on its +z side, darkens the
+    // two corners of the face next to it.
+    chunk.transform = Mat4::from_translation(vec3(0.0, 1.0, 1.0));
+    let above = scene.add_terrain(chunk);
+    let [low, high] = occlusion(&mut scene);
+    let packed = low as u64 | (high as u64) << 32;
+    let top = FACES.iter().position(|&face| face == IVec3::Y).unwrap();
+    let level = |corner: usize| packed >> (2 * (4 * top + corner)) & 3;
+    assert_eq!([0, 1, 2, 3].map(level), [3, 2, 2, 3]);
+
+    // Clearing that voxel lights the face again.
+    scene.terrain_mut(&above).unwrap().remove(UVec3::ZERO);
+    assert_eq!(occlusion(&mut scene), Voxel::UNOCCLUDED);
+}
+
+#[test]
+fn buried_voxels_are_skipped() {
+    let mut scene = Scene::empty();
+    let mut chunk = SparseTensorChunk::nothing(uvec3(3, 3, 3));
+    for i in chunk.iter_coords().collect::<Vec<_>>() {
+        chunk.insert(i, Some(MaterialId(1)));
+    }
+    scene.add_terrain(chunk.clone());
+    scene.scene_graph.evaluate_all();
+
+    let drawn = |scene: &mut Scene| {
+        let (_, _, _, voxels) =
+            DeferredRenderer::extract_matrices_and_voxels(scene, &HashSet::new());
+        voxels.iter().filter(|voxel| voxel.chunk_id == 0).count()
+    };
+    assert_eq!(drawn(&mut scene), 26);
+
+    // The chunk next to it buries the center of the face they share as well.
+    chunk.transform = Mat4::from_translation(vec3(3.0, 0.0, 0.0));
+    scene.add_terrain(chunk);
+    assert_eq!(drawn(&mut scene), 25);
+}
+
+#[test]
+fn camera_inside_bounds() {
+    let mut chunk = SparseTensorChunk::nothing(uvec3(4, 4, 4));
+    chunk.transform = Mat4::from_translation(vec3(10.0, 0.0, 0.0));
+
+    assert!(DeferredRenderer::contains_camera(
+        &chunk,
+        vec3(11.0, 2.0, 2.0)
+    ));
+    // Right in front of the bounds the near plane may still cut into them.
+    assert!(DeferredRenderer::contains_camera(
+        &chunk,
+        vec3(9.0, 2.0, 2.0)
+    ));
+    assert!(!DeferredRenderer::contains_camera(
+        &chunk,
+        vec3(5.0, 2.0, 2.0)
+    ));
+    assert!(!DeferredRenderer::contains_camera(
+        &chunk,
+        vec3(11.0, 2.0, 20.0)
+    ));
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn geometry_pass_disables_blending() {
+    let (_sdl, _window, instance) = crate::rhi::headless();
+    let device = instance.new_device().unwrap();
+    device.set_blend(Some(BlendMode::Alpha));
+
+    // The debug view returns right after the geometry pass.
+    let mut renderer = DeferredRenderer::new(device.clone(), uvec2(1, 1)).unwrap();
+    renderer.set_debug_view(Some(GBufferTarget::Position));
+
+    let model = Model {
+        transform: Mat4::IDENTITY,
+        positions: vec![(Vec3::ZERO, MaterialId(1))],
+        size: UVec3::ONE,
+    };
+
+    let mut scene = Scene::empty();
+    let root = scene.scene_graph.root();
+    scene
+        .scene_graph
+        .insert_entity(Object::new(Transform::IDENTITY, model), &root);
+    scene.scene_graph.evaluate_all();
+    renderer.render(&mut scene, None);
+
+    assert_eq!(device.blend(), None);
+    assert_eq!(unsafe { gl::IsEnabled(gl::BLEND) }, gl::FALSE);
+}
+
+#[test]
+fn interleaved_voxel_layout() {
+    // The attributes bound from the instance buffer must land on the fields
+    // that are copied into it.
+    assert_eq!(Voxel::stride(), std::mem::size_of::<Voxel>());
+    assert_eq!(Voxel::offset(0), std::mem::offset_of!(Voxel, position));
+    assert_eq!(Voxel::offset(1), std::mem::offset_of!(Voxel, chunk_id));
+    assert_eq!(Voxel::offset(2), std::mem::offset_of!(Voxel, material_id));
+}
+
+#[test]
+fn route_segments() {
+    let route = [uvec3(0, 0, 0), uvec3(1, 0, 0), uvec3(1, 2, 0)];
+    let transform = Mat4::from_translation(vec3(10.0, 0.0, 0.0));
+
+    let segments = DeferredRenderer::route_segments(&route, transform);
+    assert_eq!(
+        segments,
+        [
+            [vec3(10.5, 0.5, 0.5), vec3(11.5, 0.5, 0.5)],
+            [vec3(11.5, 0.5, 0.5), vec3(11.5, 2.5, 0.5)],
+        ]
+    );
+
+    assert!(DeferredRenderer::route_segments(&route[..1], transform).is_empty());
+}
+
+#[test]
+fn flash_tint() {
+    let mut scene = Scene::empty();
+    let root = scene.scene_graph.root();
+    let mut object = Object::new(Transform::IDENTITY, Model::default());
+    let plain = scene.scene_graph.insert_entity(object.clone(), &root);
+    object.flash = Some((vec3(1.0, 0.0, 0.0), 0.5));
+    let flashing = scene.scene_graph.insert_entity(object, &root);
+    scene.add_terrain(SparseTensorChunk::nothing(uvec3(1, 1, 1)));
+    scene.scene_graph.evaluate_all();
+
+    let (matrices, tints, _, _) =
+        DeferredRenderer::extract_matrices_and_voxels(&mut scene, &HashSet::new());
+    assert_eq!(tints.len(), matrices.len());
+    assert_eq!(
+        tints,
+        vec![Vec4::ZERO, vec4(1.0, 0.0, 0.0, 0.5), Vec4::ZERO]
+    );
+
+    // Flashes set after the object was inserted reach the renderer as well.
+    scene.scene_graph.object_mut(&plain).unwrap().flash = Some((Vec3::ONE, 1.0));
+    scene.scene_graph.object_mut(&flashing).unwrap().flash = None;
+    scene.scene_graph.evaluate_all();
+
+    let (_, tints, _, _) =
+        DeferredRenderer::extract_matrices_and_voxels(&mut scene, &HashSet::new());
+    assert_eq!(tints[..2], [Vec4::ONE, Vec4::ZERO]);
+}
+
+#[test]
+fn invisible_objects() {
+    let model = Model {
+        positions: vec![(Vec3::ZERO, MaterialId(1)), (Vec3::X, MaterialId(1))],
+        size: uvec3(2, 1, 1),
+        ..Default::default()
+    };
+    let mut scene = Scene::empty();
+    let root = scene.scene_graph.root();
+    let hidden = scene
+        .scene_graph
+        .insert_entity(Object::new(Transform::IDENTITY, model), &root);
+    scene.scene_graph.object_mut(&hidden).unwrap().visible = false;
+    scene.scene_graph.evaluate_all();
+
+    let (matrices, tints, models, _) =
+        DeferredRenderer::extract_matrices_and_voxels(&mut scene, &HashSet::new());
+    assert!(matrices.is_empty() && tints.is_empty() && models.is_empty());
+
+    scene.scene_graph.object_mut(&hidden).unwrap().visible = true;
+    scene.scene_graph.evaluate_all();
+    let (_, _, models, _) =
+        DeferredRenderer::extract_matrices_and_voxels(&mut scene, &HashSet::new());
+    assert_eq!(models[0].voxels.len(), 2);
+}
+
+#[test]
+fn highlighted_object() {
+    let model = Model {
+        positions: vec![(Vec3::ZERO, MaterialId(1)), (Vec3::X, MaterialId(1))],
+        size: uvec3(2, 1, 1),
+        ..Default::default()
+    };
+    let mut scene = Scene::empty();
+    let root = scene.scene_graph.root();
+    let object = Object::new(Transform::IDENTITY, model);
+    let hidden = scene.scene_graph.insert_entity(object.clone(), &root);
+    let plain = scene.scene_graph.insert_entity(object.clone(), &root);
+    let highlighted = scene.scene_graph.insert_entity(object, &root);
+    scene.scene_graph.object_mut(&hidden).unwrap().visible = false;
+    scene.scene_graph.evaluate_all();
+
+    // Hidden objects have no chunk, so the highlight is on the second chunk.
+    let chunk = DeferredRenderer::highlighted_chunk(&scene, &highlighted).unwrap();
+    assert_eq!(chunk, 1);
+    assert_eq!(DeferredRenderer::highlighted_chunk(&scene, &hidden), None);
+    assert_eq!(DeferredRenderer::highlighted_chunk(&scene, &plain), Some(0));
+
+    let (_, _, models, _) =
+        DeferredRenderer::extract_matrices_and_voxels(&mut scene, &HashSet::new());
+    let outlined: Vec<_> = models[0]
+        .expand()
+        .filter(|voxel| voxel.chunk_id == chunk)
+        .collect();
+    assert_eq!(outlined.len(), 2);
+
+    // The outline reaches out by the same width on every side of the voxels.
+    let growth = DeferredRenderer::outline_growth(&[Vec3::ZERO, Vec3::X]);
+    let width = DeferredRenderer::OUTLINE_WIDTH;
+    assert!(growth
+        .transform_point3(Vec3::splat(-0.5))
+        .abs_diff_eq(Vec3::splat(-0.5 - width), 1e-5));
+    assert!(growth
+        .transform_point3(vec3(1.5, 0.5, 0.5))
+        .abs_diff_eq(vec3(1.5, 0.5, 0.5) + width, 1e-5));
+}
+
+#[test]
+fn instanced_objects() {
+    let chair = Rc::new(Model {
+        positions: vec![(Vec3::ZERO, MaterialId(1)), (Vec3::Y, MaterialId(2))],
+        size: uvec3(1, 2, 1),
+        ..Default::default()
+    });
+    let desk = Model {
+        positions: vec![(Vec3::ZERO, MaterialId(3))],
+        size: UVec3::ONE,
+        ..Default::default()
+    };
+
+    let mut scene = Scene::empty();
+    let root = scene.scene_graph.root();
+    let at = |x| Transform::from_translation(vec3(x, 0.0, 0.0));
+    scene
+        .scene_graph
+        .insert_entity(Object::new(at(1.0), Rc::clone(&chair)), &root);
+    scene
+        .scene_graph
+        .insert_entity(Object::new(at(2.0), desk), &root);
+    let second = scene
+        .scene_graph
+        .insert_entity(Object::new(at(3.0), chair), &root);
+    scene.scene_graph.evaluate_all();
+
+    // Both chairs are drawn from one copy of their voxels, and take the first
+    // two chunks as their group comes first.
+    let (matrices, _, models, _) =
+        DeferredRenderer::extract_matrices_and_voxels(&mut scene, &HashSet::new());
+    assert_eq!(models.len(), 2);
+    assert_eq!((models[0].chunk_id, models[0].instances), (0, 2));
+    assert_eq!(models[0].voxels.len(), 2);
+    assert_eq!((models[1].chunk_id, models[1].instances), (2, 1));
+
+    let x = |chunk: usize| matrices[chunk][0].w_axis.x;
+    assert_eq!([x(0), x(1), x(2)], [1.0, 3.0, 2.0]);
+    assert_eq!(
+        DeferredRenderer::highlighted_chunk(&scene, &second),
+        Some(1)
+    );
+
+    let chunks: Vec<_> = models[0].expand().map(|voxel| voxel.chunk_id).collect();
+    assert_eq!(chunks, [0, 0, 1, 1]);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn gpu_frustum_culling() {
+    let (_sdl, _window, instance) = crate::rhi::headless();
+    let device = instance.new_device().unwrap();
+
+    let shader = device
+        .new_shader(ComputeStage, DeferredRenderer::CULL_COMPUTE_SHADER_SRC)
+        .unwrap();
+    let program = device.new_compute_program(&shader).unwrap();
+
+    // A camera at the origin looking down -z.
+    let projection = Mat4::perspective_rh_gl(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+    let matrices = [[Mat4::IDENTITY, projection]];
+    let mut matrix_buffer = device.new_uniform_buffer("Chunks", 0, 1);
+    matrix_buffer.map_write().write(&matrices);
+
+    let voxel = |position| Voxel {
+        position,
+        chunk_id: 0,
+        material_id: 1,
+        occlusion: Voxel::UNOCCLUDED,
+    };
+    let voxels = [
+        voxel(vec3(0.0, 0.0, -5.0)),
+        voxel(vec3(0.0, 0.0, 5.0)),
+        voxel(vec3(100.0, 0.0, -5.0)),
+        // Straddles the right plane of the frustum, so it is kept.
+        voxel(vec3(5.2, 0.0, -5.0)),
+    ];
+    let voxel_buffer: Buffer<_> = device.new_buffer(BufferInit::Data(&voxels));
+    let visible: Buffer<_, true> = device.new_buffer(BufferInit::Capacity(voxels.len()));
+    let command: Buffer<_, true> = device.new_buffer(BufferInit::Data(&[DrawCommand::default()]));
+
+    matrix_buffer.bind();
+    DeferredRenderer::cull(&device, &program, &voxel_buffer, &visible, &command);
+
+    let count = command.read_range(0, 1)[0].instance_count as usize;
+    let mut kept = visible.read_range(0, count);
+    kept.sort_by(|a, b| a.position.x.total_cmp(&b.position.x));
+    assert_eq!(kept, [voxels[0], voxels[3]]);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn g_buffer_attachments() {
+    let (_sdl, _window, instance) = crate::rhi::headless();
+    let device = instance.new_device().unwrap();
+
+    let framebuffer = DeferredRenderer::setup_framebuffer(&device, uvec2(8, 4)).unwrap();
+    assert_eq!(framebuffer.attachment_count(), GBufferTarget::ALL.len());
+    assert!(framebuffer.has_depth());
+
+    let formats = GBufferTarget::ALL.map(|target| framebuffer.color(target.attachment()).format());
+    assert_eq!(
+        formats,
+        [
+            Format::R32G32B32A32Float,
+            Format::R32G32B32A32Float,
+            Format::R32G32B32A32Float,
+            Format::R32G32Float,
+            Format::R32G32B32A32Float,
+        ]
+    );
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn clear_color_shows_through() {
+    let (_sdl, _window, instance) = crate::rhi::headless();
+    let device = instance.new_device().unwrap();
+
+    let mut renderer = DeferredRenderer::new(device.clone(), uvec2(1, 1)).unwrap();
+    let mut scene = Scene::empty();
+    scene.scene_graph.evaluate_all();
+
+    // Nothing covers the pixel, so the lighting pass keeps the clear color.
+    device
+        .default_framebuffer()
+        .clear(vec4(0.2, 0.4, 0.6, 1.0), true);
+    renderer.render(&mut scene, None);
+
+    assert_eq!(device.default_framebuffer().read(), [51, 102, 153, 255]);
+}
@@ -1,13 +1,23 @@
+use std::path::PathBuf;
+
 use glam::*;
+use image::RgbaImage;
 use sdl2::video::Window;
 
+pub use self::deferred_renderer::GBufferTarget;
 use self::deferred_renderer::*;
+use self::profiler::*;
 use self::text_renderer::*;
+pub use self::texture_atlas::{TextureAtlas, TextureAtlasBuilder};
 use crate::rhi::*;
 use crate::scene::*;
 
 mod deferred_renderer;
+mod profiler;
+#[cfg(test)]
+mod software_renderer;
 mod text_renderer;
+mod texture_atlas;
 
 #[repr(C)]
 struct QuadVertex(Vec2, Vec2);
@@ -32,12 +42,16 @@ const QUAD: [QuadVertex; 6] = [
     QuadVertex(vec2(-1.0, -1.0), vec2(0.0, 0.0)),
 ];
 
+/// A vertex of the unit cube: position, normal and tangent.
+///
+/// The tangent lies in the plane of the face, so together with the normal it
+/// spans the tangent frame used for tangent-space normal maps.
 #[repr(C)]
-struct CubeVertex(Vec4, Vec4);
+struct CubeVertex(Vec4, Vec4, Vec4);
 
 unsafe impl BufferLayout for CubeVertex {
-    const LAYOUT: &'static [Format] = &[Format::Vec4, Format::Vec4];
-    const PADDING: &'static [usize] = &[0, 0];
+    const LAYOUT: &'static [Format] = &[Format::Vec4, Format::Vec4, Format::Vec4];
+    const PADDING: &'static [usize] = &[0, 0, 0];
     const COPYABLE: bool = true;
 
     fn to_bytes(_items: &[Self]) -> Vec<u8> {
@@ -47,113 +61,355 @@ unsafe impl BufferLayout for CubeVertex {
 
 #[rustfmt::skip]
 const CUBE: [CubeVertex; 36] = [
-    CubeVertex(vec4(-0.5, -0.5, -0.5, 1.0),  vec4(0.0,  0.0, -1.0, 0.0)),
-    CubeVertex(vec4( 0.5, -0.5, -0.5, 1.0),  vec4(0.0,  0.0, -1.0, 0.0)),
-    CubeVertex(vec4( 0.5,  0.5, -0.5, 1.0),  vec4(0.0,  0.0, -1.0, 0.0)),
-    CubeVertex(vec4( 0.5,  0.5, -0.5, 1.0),  vec4(0.0,  0.0, -1.0, 0.0)),
-    CubeVertex(vec4(-0.5,  0.5, -0.5, 1.0),  vec4(0.0,  0.0, -1.0, 0.0)),
-    CubeVertex(vec4(-0.5, -0.5, -0.5, 1.0),  vec4(0.0,  0.0, -1.0, 0.0)),
-
-    CubeVertex(vec4(-0.5, -0.5,  0.5, 1.0),  vec4(0.0,  0.0,  1.0, 0.0)),
-    CubeVertex(vec4( 0.5, -0.5,  0.5, 1.0),  vec4(0.0,  0.0,  1.0, 0.0)),
-    CubeVertex(vec4( 0.5,  0.5,  0.5, 1.0),  vec4(0.0,  0.0,  1.0, 0.0)),
-    CubeVertex(vec4( 0.5,  0.5,  0.5, 1.0),  vec4(0.0,  0.0,  1.0, 0.0)),
-    CubeVertex(vec4(-0.5,  0.5,  0.5, 1.0),  vec4(0.0,  0.0,  1.0, 0.0)),
-    CubeVertex(vec4(-0.5, -0.5,  0.5, 1.0),  vec4(0.0,  0.0,  1.0, 0.0)),
-
-    CubeVertex(vec4(-0.5,  0.5,  0.5, 1.0), vec4(-1.0,  0.0,  0.0, 0.0)),
-    CubeVertex(vec4(-0.5,  0.5, -0.5, 1.0), vec4(-1.0,  0.0,  0.0, 0.0)),
-    CubeVertex(vec4(-0.5, -0.5, -0.5, 1.0), vec4(-1.0,  0.0,  0.0, 0.0)),
-    CubeVertex(vec4(-0.5, -0.5, -0.5, 1.0), vec4(-1.0,  0.0,  0.0, 0.0)),
-    CubeVertex(vec4(-0.5, -0.5,  0.5, 1.0), vec4(-1.0,  0.0,  0.0, 0.0)),
-    CubeVertex(vec4(-0.5,  0.5,  0.5, 1.0), vec4(-1.0,  0.0,  0.0, 0.0)),
-
-    CubeVertex(vec4(0.5,  0.5,  0.5, 1.0),  vec4(1.0,  0.0,  0.0, 0.0)),
-    CubeVertex(vec4(0.5,  0.5, -0.5, 1.0),  vec4(1.0,  0.0,  0.0, 0.0)),
-    CubeVertex(vec4(0.5, -0.5, -0.5, 1.0),  vec4(1.0,  0.0,  0.0, 0.0)),
-    CubeVertex(vec4(0.5, -0.5, -0.5, 1.0),  vec4(1.0,  0.0,  0.0, 0.0)),
-    CubeVertex(vec4(0.5, -0.5,  0.5, 1.0),  vec4(1.0,  0.0,  0.0, 0.0)),
-    CubeVertex(vec4(0.5,  0.5,  0.5, 1.0),  vec4(1.0,  0.0,  0.0, 0.0)),
-
-    CubeVertex(vec4(-0.5, -0.5, -0.5, 1.0),  vec4(0.0, -1.0,  0.0, 0.0)),
-    CubeVertex(vec4( 0.5, -0.5, -0.5, 1.0),  vec4(0.0, -1.0,  0.0, 0.0)),
-    CubeVertex(vec4( 0.5, -0.5,  0.5, 1.0),  vec4(0.0, -1.0,  0.0, 0.0)),
-    CubeVertex(vec4( 0.5, -0.5,  0.5, 1.0),  vec4(0.0, -1.0,  0.0, 0.0)),
-    CubeVertex(vec4(-0.5, -0.5,  0.5, 1.0),  vec4(0.0, -1.0,  0.0, 0.0)),
-    CubeVertex(vec4(-0.5, -0.5, -0.5, 1.0),  vec4(0.0, -1.0,  0.0, 0.0)),
-
-    CubeVertex(vec4(-0.5,  0.5, -0.5, 1.0),  vec4(0.0,  1.0,  0.0, 0.0)),
-    CubeVertex(vec4( 0.5,  0.5, -0.5, 1.0),  vec4(0.0,  1.0,  0.0, 0.0)),
-    CubeVertex(vec4( 0.5,  0.5,  0.5, 1.0),  vec4(0.0,  1.0,  0.0, 0.0)),
-    CubeVertex(vec4( 0.5,  0.5,  0.5, 1.0),  vec4(0.0,  1.0,  0.0, 0.0)),
-    CubeVertex(vec4(-0.5,  0.5,  0.5, 1.0),  vec4(0.0,  1.0,  0.0, 0.0)),
-    CubeVertex(vec4(-0.5,  0.5, -0.5, 1.0),  vec4(0.0,  1.0,  0.0, 0.0))
+    CubeVertex(vec4(-0.5, -0.5, -0.5, 1.0),  vec4(0.0,  0.0, -1.0, 0.0), vec4(-1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4( 0.5, -0.5, -0.5, 1.0),  vec4(0.0,  0.0, -1.0, 0.0), vec4(-1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4( 0.5,  0.5, -0.5, 1.0),  vec4(0.0,  0.0, -1.0, 0.0), vec4(-1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4( 0.5,  0.5, -0.5, 1.0),  vec4(0.0,  0.0, -1.0, 0.0), vec4(-1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4(-0.5,  0.5, -0.5, 1.0),  vec4(0.0,  0.0, -1.0, 0.0), vec4(-1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4(-0.5, -0.5, -0.5, 1.0),  vec4(0.0,  0.0, -1.0, 0.0), vec4(-1.0,  0.0,  0.0, 0.0)),
+
+    CubeVertex(vec4(-0.5, -0.5,  0.5, 1.0),  vec4(0.0,  0.0,  1.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4( 0.5, -0.5,  0.5, 1.0),  vec4(0.0,  0.0,  1.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4( 0.5,  0.5,  0.5, 1.0),  vec4(0.0,  0.0,  1.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4( 0.5,  0.5,  0.5, 1.0),  vec4(0.0,  0.0,  1.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4(-0.5,  0.5,  0.5, 1.0),  vec4(0.0,  0.0,  1.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4(-0.5, -0.5,  0.5, 1.0),  vec4(0.0,  0.0,  1.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+
+    CubeVertex(vec4(-0.5,  0.5,  0.5, 1.0), vec4(-1.0,  0.0,  0.0, 0.0), vec4( 0.0,  0.0,  1.0, 0.0)),
+    CubeVertex(vec4(-0.5,  0.5, -0.5, 1.0), vec4(-1.0,  0.0,  0.0, 0.0), vec4( 0.0,  0.0,  1.0, 0.0)),
+    CubeVertex(vec4(-0.5, -0.5, -0.5, 1.0), vec4(-1.0,  0.0,  0.0, 0.0), vec4( 0.0,  0.0,  1.0, 0.0)),
+    CubeVertex(vec4(-0.5, -0.5, -0.5, 1.0), vec4(-1.0,  0.0,  0.0, 0.0), vec4( 0.0,  0.0,  1.0, 0.0)),
+    CubeVertex(vec4(-0.5, -0.5,  0.5, 1.0), vec4(-1.0,  0.0,  0.0, 0.0), vec4( 0.0,  0.0,  1.0, 0.0)),
+    CubeVertex(vec4(-0.5,  0.5,  0.5, 1.0), vec4(-1.0,  0.0,  0.0, 0.0), vec4( 0.0,  0.0,  1.0, 0.0)),
+
+    CubeVertex(vec4(0.5,  0.5,  0.5, 1.0),  vec4(1.0,  0.0,  0.0, 0.0), vec4( 0.0,  0.0, -1.0, 0.0)),
+    CubeVertex(vec4(0.5,  0.5, -0.5, 1.0),  vec4(1.0,  0.0,  0.0, 0.0), vec4( 0.0,  0.0, -1.0, 0.0)),
+    CubeVertex(vec4(0.5, -0.5, -0.5, 1.0),  vec4(1.0,  0.0,  0.0, 0.0), vec4( 0.0,  0.0, -1.0, 0.0)),
+    CubeVertex(vec4(0.5, -0.5, -0.5, 1.0),  vec4(1.0,  0.0,  0.0, 0.0), vec4( 0.0,  0.0, -1.0, 0.0)),
+    CubeVertex(vec4(0.5, -0.5,  0.5, 1.0),  vec4(1.0,  0.0,  0.0, 0.0), vec4( 0.0,  0.0, -1.0, 0.0)),
+    CubeVertex(vec4(0.5,  0.5,  0.5, 1.0),  vec4(1.0,  0.0,  0.0, 0.0), vec4( 0.0,  0.0, -1.0, 0.0)),
+
+    CubeVertex(vec4(-0.5, -0.5, -0.5, 1.0),  vec4(0.0, -1.0,  0.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4( 0.5, -0.5, -0.5, 1.0),  vec4(0.0, -1.0,  0.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4( 0.5, -0.5,  0.5, 1.0),  vec4(0.0, -1.0,  0.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4( 0.5, -0.5,  0.5, 1.0),  vec4(0.0, -1.0,  0.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4(-0.5, -0.5,  0.5, 1.0),  vec4(0.0, -1.0,  0.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4(-0.5, -0.5, -0.5, 1.0),  vec4(0.0, -1.0,  0.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+
+    CubeVertex(vec4(-0.5,  0.5, -0.5, 1.0),  vec4(0.0,  1.0,  0.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4( 0.5,  0.5, -0.5, 1.0),  vec4(0.0,  1.0,  0.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4( 0.5,  0.5,  0.5, 1.0),  vec4(0.0,  1.0,  0.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4( 0.5,  0.5,  0.5, 1.0),  vec4(0.0,  1.0,  0.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4(-0.5,  0.5,  0.5, 1.0),  vec4(0.0,  1.0,  0.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0)),
+    CubeVertex(vec4(-0.5,  0.5, -0.5, 1.0),  vec4(0.0,  1.0,  0.0, 0.0), vec4( 1.0,  0.0,  0.0, 0.0))
 ];
 
+/// Maps pixels of a window of `window_size` to normalized device coordinates,
+/// with pixel (0, 0) in the bottom left corner. Everything drawn in screen
+/// space shares it, so it follows the window when it's resized.
+fn screen_ortho(window_size: UVec2) -> Mat4 {
+    let [width, height] = window_size.as_vec2().to_array();
+    Mat4::orthographic_rh_gl(0.0, width, 0.0, height, 0.0, 1.0)
+}
+
+/// How a [`Renderer`] is set up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RendererConfig {
+    /// Waits for the vertical blank before presenting a frame.
+    pub vsync: bool,
+    /// Measures how long every frame takes on the GPU.
+    pub profile: bool,
+    /// Reports OpenGL errors through a debug callback.
+    pub debug: bool,
+    /// Samples per pixel of the window's framebuffer, where 0 disables
+    /// multisampling. It has to be requested before the window is created.
+    pub msaa_samples: u8,
+    pub clear_color: Vec4,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            vsync: true,
+            profile: false,
+            debug: false,
+            msaa_samples: 0,
+            clear_color: vec4(0.0, 0.0, 0.0, 1.0),
+        }
+    }
+}
+
 pub struct Renderer<'a> {
     _instance: Instance,
     device: Device<'a>,
     swapchain: Swapchain,
     deferred_renderer: DeferredRenderer<'a>,
     text_renderer: TextRenderer<'a>,
+    profiler: Option<Profiler<'a>>,
+    clear_color: Vec4,
+    /// Where to save the next frame, see [`Renderer::screenshot`].
+    screenshot: Option<PathBuf>,
 }
 
 impl<'a> Renderer<'a> {
-    pub fn new(window: &Window, vsync: bool) -> Self {
-        let _instance = Instance::new(window, false);
-        let device = _instance.new_device();
-        let swapchain = _instance.new_swapchain(vsync);
-
-        unsafe {
-            gl::Enable(gl::BLEND);
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-        }
+    /// The most terrain chunks and objects that can be drawn in a frame.
+    pub const MAX_CHUNKS: usize = DeferredRenderer::MAX_CHUNKS;
+
+    pub fn new(window: &Window, config: RendererConfig) -> Result<Self, RhiError> {
+        let _instance = Instance::new(window, config.debug)?;
+        let device = _instance.new_device()?;
+        let swapchain = _instance.new_swapchain(config.vsync);
 
         let window_size = UVec2::from(window.size());
 
-        Self {
+        Ok(Self {
             _instance,
             device: device.clone(),
             swapchain,
-            deferred_renderer: DeferredRenderer::new(device.clone(), window_size),
-            text_renderer: TextRenderer::new(device.clone(), window_size),
-        }
+            deferred_renderer: DeferredRenderer::new(device.clone(), window_size)?,
+            text_renderer: TextRenderer::new(device.clone(), window_size)?,
+            profiler: config.profile.then(|| Profiler::new(device.clone(), false)),
+            clear_color: config.clear_color,
+            screenshot: None,
+        })
     }
 
-    pub fn render(&mut self, scene: &mut Scene) -> Option<f32> {
+    /// Renders the scene with its transforms interpolated by `alpha` between
+    /// the previous and current fixed update.
+    ///
+    /// When profiling, this returns the GPU time of a frame in milliseconds
+    /// once its measurement is available.
+    pub fn render(&mut self, scene: &mut Scene, alpha: f32) -> Option<f32> {
         let Self {
             device,
             swapchain,
             deferred_renderer,
             text_renderer,
+            profiler,
+            clear_color,
+            screenshot,
             ..
         } = self;
 
-        scene.scene_graph.evaluate_all();
+        if let Some(profiler) = profiler {
+            profiler.begin_profile("frame");
+        }
+
+        scene.scene_graph.evaluate_all_interpolated(alpha);
 
-        device
-            .default_framebuffer()
-            .clear(vec4(0.0, 0.0, 0.0, 1.0), true);
+        // The lighting pass discards pixels without geometry, so the clear color
+        // is what ends up behind the terrain.
+        device.default_framebuffer().clear(*clear_color, true);
 
-        deferred_renderer.render(scene);
+        deferred_renderer.render(scene, profiler.as_mut());
+
+        if let Some(profiler) = profiler {
+            profiler.begin_profile("text");
+        }
         text_renderer.render(scene, &mut device.default_framebuffer());
+        if let Some(profiler) = profiler {
+            profiler.end_profile("text");
+        }
+
+        // What the window shows after presenting is undefined, so the frame is read
+        // while it is still in the back buffer.
+        if let Some(path) = screenshot.take() {
+            let framebuffer = device.default_framebuffer();
+            let image = Self::flip_rows(framebuffer.size(), framebuffer.read());
+            if let Err(error) = image.save(&path) {
+                eprintln!("failed to save the screenshot: {error}");
+            }
+        }
 
         device.unbind_framebuffer();
         swapchain.present();
 
-        Some(1.0)
+        let (_, gpu_time) = profiler.as_mut()?.end_profile("frame")?;
+        Some(gpu_time as f32)
+    }
+
+    /// The GPU time in milliseconds of the whole frame and of each of its
+    /// passes, from the last frames whose measurements are available. This is
+    /// empty unless profiling is enabled.
+    pub fn last_frame_times(&self) -> Vec<(&'static str, f32)> {
+        let Some(profiler) = &self.profiler else {
+            return Vec::new();
+        };
+        profiler
+            .gpu_times()
+            .map(|(task, time)| (task, time as f32))
+            .collect()
+    }
+
+    pub fn clear_color(&self) -> Vec4 {
+        self.clear_color
+    }
+
+    pub fn set_clear_color(&mut self, color: Vec4) {
+        self.clear_color = color;
+    }
+
+    /// Creates an environment cubemap from six RGBA8 face images of `size` by
+    /// `size` texels, in the order +X, -X, +Y, -Y, +Z, -Z.
+    pub fn new_environment(&self, size: usize, faces: [&[u8]; 6]) -> TextureCube {
+        let mut environment = self
+            .device
+            .new_texture_cube(size, Format::R8G8B8A8)
+            .unwrap();
+        for (face, bytes) in faces.iter().enumerate() {
+            environment.write_face(face, bytes);
+        }
+
+        environment
+    }
+
+    /// Packs and uploads the material textures added to `builder`.
+    pub fn new_texture_atlas(&self, builder: TextureAtlasBuilder) -> TextureAtlas {
+        builder.build(&self.device)
+    }
+
+    pub fn set_environment(&mut self, environment: Option<TextureCube>) {
+        self.deferred_renderer.set_environment(environment);
+    }
+
+    pub fn debug_view(&self) -> Option<GBufferTarget> {
+        self.deferred_renderer.debug_view()
+    }
+
+    pub fn set_debug_view(&mut self, target: Option<GBufferTarget>) {
+        self.deferred_renderer.set_debug_view(target);
+    }
+
+    pub fn occupancy_overlay(&self) -> Option<&TerrainId> {
+        self.deferred_renderer.occupancy_overlay()
+    }
+
+    pub fn set_occupancy_overlay(&mut self, id: Option<TerrainId>) {
+        self.deferred_renderer.set_occupancy_overlay(id);
+    }
+
+    pub fn set_highlight(&mut self, id: Option<SceneNodeId>) {
+        self.deferred_renderer.set_highlight(id);
+    }
+
+    /// Saves the next frame to `path` once it has been rendered, in the image
+    /// format given by its extension, e.g. PNG.
+    pub fn screenshot(&mut self, path: impl Into<PathBuf>) {
+        self.screenshot = Some(path.into());
+    }
+
+    /// OpenGL reads pixels starting with the bottom row, while images start
+    /// with the top row.
+    fn flip_rows(size: UVec2, pixels: Vec<u8>) -> RgbaImage {
+        let image = RgbaImage::from_raw(size.x, size.y, pixels).expect("too few pixels");
+        image::imageops::flip_vertical(&image)
+    }
+
+    /// Draws `route` through the voxels of a chunk placed by `chunk_transform`
+    /// as connected lines on top of the next frame.
+    pub fn draw_route(&mut self, route: &[UVec3], chunk_transform: Mat4, color: Vec4) {
+        self.deferred_renderer
+            .draw_route(route, chunk_transform, color);
     }
 
     pub fn resize(&mut self, window_size: UVec2) {
         let Self {
+            device,
             deferred_renderer,
             text_renderer,
             ..
         } = self;
 
-        unsafe { gl::Viewport(0, 0, window_size.x as _, window_size.y as _) };
+        device.set_viewport(uvec4(0, 0, window_size.x, window_size.y));
 
         deferred_renderer.resize(window_size);
         text_renderer.resize(window_size);
     }
 }
+
+#[test]
+fn cube_tangent_frame() {
+    for CubeVertex(_, normal, tangent) in &CUBE {
+        assert_eq!(tangent.length(), 1.0);
+        assert_eq!(normal.dot(*tangent), 0.0);
+    }
+}
+
+#[test]
+fn screen_ortho_corners() {
+    let ortho = screen_ortho(uvec2(640, 480));
+    assert_eq!(ortho.project_point3(Vec3::ZERO), vec3(-1.0, -1.0, -1.0));
+    assert_eq!(
+        ortho.project_point3(vec3(640.0, 480.0, 0.0)),
+        vec3(1.0, 1.0, -1.0)
+    );
+    assert_eq!(
+        ortho.project_point3(vec3(320.0, 240.0, 0.0)),
+        vec3(0.0, 0.0, -1.0)
+    );
+}
+
+#[test]
+fn default_config() {
+    let config = RendererConfig::default();
+    assert!(config.vsync);
+    assert!(!config.profile);
+    assert!(!config.debug);
+    assert_eq!(config.msaa_samples, 0);
+    assert_eq!(config.clear_color, vec4(0.0, 0.0, 0.0, 1.0));
+
+    let config = RendererConfig {
+        msaa_samples: 4,
+        ..Default::default()
+    };
+    assert_eq!(config.msaa_samples, 4);
+    assert!(config.vsync);
+}
+
+#[test]
+fn renderer_interface() {
+    // Compiles only while the one renderer keeps the signatures `main` relies on.
+    let _: fn(&Window, RendererConfig) -> Result<Renderer<'static>, RhiError> = Renderer::new;
+    let _: fn(&mut Renderer<'static>, &mut Scene, f32) -> Option<f32> = Renderer::render;
+    let _: fn(&mut Renderer<'static>, UVec2) = Renderer::resize;
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn screenshot_round_trip() {
+    // The renderer creates its own context for the window.
+    let (_sdl, window, _) = crate::rhi::headless();
+    let config = RendererConfig {
+        vsync: false,
+        clear_color: vec4(0.2, 0.4, 0.6, 1.0),
+        ..Default::default()
+    };
+    let mut renderer = Renderer::new(&window, config).unwrap();
+
+    let path = std::env::temp_dir().join("office-apocalypse-rendered.png");
+    renderer.screenshot(&path);
+    renderer.render(&mut Scene::empty(), 1.0);
+    let image = image::open(&path).unwrap().into_rgba8();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(image.dimensions(), window.size());
+    assert!(image.pixels().all(|pixel| pixel.0 == [51, 102, 153, 255]));
+}
+
+#[test]
+fn screenshot_png() {
+    // Two columns by three rows, where the bottom row comes first.
+    let rows = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+    let pixels = rows.iter().flat_map(|row| [*row, *row]).flatten().collect();
+
+    let path = std::env::temp_dir().join("office-apocalypse-screenshot.png");
+    Renderer::flip_rows(uvec2(2, 3), pixels)
+        .save(&path)
+        .unwrap();
+    let image = image::open(&path).unwrap().into_rgba8();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(image.dimensions(), (2, 3));
+    assert_eq!(image.get_pixel(1, 0).0, rows[2]);
+    assert_eq!(image.get_pixel(0, 2).0, rows[0]);
+}
@@ -4,24 +4,45 @@ use std::time::*;
 
 use crate::rhi::*;
 
-pub struct Profiler {
-    task: Option<&'static str>,
+/// The measurement of a single task, which has its own pair of timestamp
+/// queries so tasks can be profiled one after the other within a frame.
+struct Span {
+    task: &'static str,
     cpu_profiler: (Option<Instant>, Option<Instant>),
     gpu_profiler: (u32, u32),
-    sender: Option<Sender<(&'static str, f64, f64)>>,
-    thread: Option<JoinHandle<()>>,
+    /// The CPU and GPU time of the last completed measurement.
+    last: Option<(f64, f64)>,
 }
 
-impl Profiler {
-    pub fn new(print: bool) -> Self {
-        let cpu_profiler = (None, None);
-
+impl Span {
+    fn new(task: &'static str) -> Self {
         let gpu_profiler = unsafe {
             let mut queries = [u32::MAX, u32::MAX];
             gl!(gl::CreateQueries(gl::TIMESTAMP, 2, queries.as_mut_ptr())).unwrap();
             (queries[0], queries[1])
         };
 
+        Self {
+            task,
+            cpu_profiler: (None, None),
+            gpu_profiler,
+            last: None,
+        }
+    }
+}
+
+pub struct Profiler<'a> {
+    /// The profiled tasks in the order they were first begun.
+    spans: Vec<Span>,
+    sender: Option<Sender<(&'static str, f64, f64)>>,
+    thread: Option<JoinHandle<()>>,
+    // The queries are created directly, so the device is kept to keep the context
+    // alive until they are deleted.
+    _device: Device<'a>,
+}
+
+impl<'a> Profiler<'a> {
+    pub fn new(device: Device<'a>, print: bool) -> Self {
         let (sender, thread) = if print {
             let (sender, receiver) = channel();
             let thread = std::thread::spawn(move || {
@@ -38,18 +59,30 @@ impl Profiler {
         };
 
         Self {
-            task: None,
-            cpu_profiler,
-            gpu_profiler,
+            spans: Vec::new(),
             sender,
             thread,
+            _device: device,
         }
     }
 
+    fn span(&mut self, task: &'static str) -> &mut Span {
+        let index = match self.spans.iter().position(|span| span.task == task) {
+            Some(index) => index,
+            None => {
+                self.spans.push(Span::new(task));
+                self.spans.len() - 1
+            }
+        };
+        &mut self.spans[index]
+    }
+
+    /// Starts measuring `task`. While the previous measurement of the task
+    /// is still in flight, this does nothing.
     pub fn begin_profile(&mut self, task: &'static str) {
-        let _ = self.task.insert(task);
-        let (cpu_start, _) = &mut self.cpu_profiler;
-        let (gpu_start, _) = &mut self.gpu_profiler;
+        let span = self.span(task);
+        let (cpu_start, _) = &mut span.cpu_profiler;
+        let (gpu_start, _) = &mut span.gpu_profiler;
 
         if cpu_start.is_none() {
             let _ = cpu_start.insert(Instant::now());
@@ -62,8 +95,9 @@ impl Profiler {
         const RESULT: gl::types::GLenum = gl::QUERY_RESULT;
         const AVAILABLE: gl::types::GLenum = gl::QUERY_RESULT_AVAILABLE;
 
-        let (cpu_start, cpu_end) = &mut self.cpu_profiler;
-        let (gpu_start, gpu_end) = &mut self.gpu_profiler;
+        let span = self.span(task);
+        let (cpu_start, cpu_end) = &mut span.cpu_profiler;
+        let (gpu_start, gpu_end) = &mut span.gpu_profiler;
 
         if cpu_end.is_none() {
             let _ = cpu_end.insert(Instant::now());
@@ -80,7 +114,7 @@ impl Profiler {
                 let mut end = 0;
                 unsafe { gl!(gl::GetQueryObjectui64v(*gpu_end, RESULT, &mut end)).unwrap() };
 
-                (end - start) as f64 / 1_000_000.0
+                end.saturating_sub(start) as f64 / 1_000_000.0
             };
 
             let cpu_time = {
@@ -89,46 +123,81 @@ impl Profiler {
                 end.duration_since(start).as_secs_f64() * 1000.0
             };
 
+            span.cpu_profiler = (None, None);
+            span.last = Some((cpu_time, gpu_time));
+
             if let Some(sender) = &self.sender {
                 sender.send((task, cpu_time, gpu_time)).unwrap();
             }
 
-            self.task = None;
-            self.cpu_profiler = (None, None);
-
             Some((cpu_time, gpu_time))
         } else {
             None
         }
     }
+
+    /// The GPU time in milliseconds of the last completed measurement of
+    /// every task, in the order the tasks were first begun.
+    pub fn gpu_times(&self) -> impl Iterator<Item = (&'static str, f64)> + '_ {
+        self.spans
+            .iter()
+            .filter_map(|span| Some((span.task, span.last?.1)))
+    }
 }
 
-impl Drop for Profiler {
+impl Drop for Profiler<'_> {
     fn drop(&mut self) {
         drop(self.sender.take());
 
-        let start_query = self.gpu_profiler.0;
-        let end_query = self.gpu_profiler.1;
-        loop {
-            let mut available = 0;
-            unsafe {
-                gl!(gl::GetQueryObjectiv(
-                    end_query,
-                    gl::QUERY_RESULT_AVAILABLE,
-                    &mut available
-                ))
-                .unwrap()
-            }
+        for span in &self.spans {
+            let (start_query, end_query) = span.gpu_profiler;
+
+            // Only a query which was issued ever becomes available.
+            while span.cpu_profiler.1.is_some() {
+                let mut available = 0;
+                unsafe {
+                    gl!(gl::GetQueryObjectiv(
+                        end_query,
+                        gl::QUERY_RESULT_AVAILABLE,
+                        &mut available
+                    ))
+                    .unwrap()
+                }
 
-            if available as u8 == gl::TRUE {
-                break;
+                if available as u8 == gl::TRUE {
+                    break;
+                }
             }
-        }
 
-        unsafe { gl!(gl::DeleteQueries(2, [start_query, end_query].as_mut_ptr())).unwrap() };
+            unsafe { gl!(gl::DeleteQueries(2, [start_query, end_query].as_ptr())).unwrap() };
+        }
 
         if let Some(handle) = self.thread.take() {
             handle.join().unwrap();
         }
     }
 }
+
+#[test]
+#[ignore = "requires a video device"]
+fn sequential_spans() {
+    let (_sdl, _window, instance) = crate::rhi::headless();
+    let device = instance.new_device().unwrap();
+    let mut profiler = Profiler::new(device, false);
+
+    profiler.begin_profile("first");
+    let mut first = profiler.end_profile("first");
+    profiler.begin_profile("second");
+    let mut second = profiler.end_profile("second");
+
+    while first.is_none() || second.is_none() {
+        first = first.or_else(|| profiler.end_profile("first"));
+        second = second.or_else(|| profiler.end_profile("second"));
+    }
+
+    let times: Vec<_> = profiler.gpu_times().collect();
+    assert_eq!(
+        times,
+        [("first", first.unwrap().1), ("second", second.unwrap().1)]
+    );
+}
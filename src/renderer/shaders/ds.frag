@@ -1,10 +1,15 @@
 #version 460 core
 
 const uint MAX_MATERIALS = 256;
+// How much darker the most occluded corners of the voxels get.
+const float OCCLUSION_STRENGTH = 0.5;
 
 in vec4 fragPosition;
 in vec4 normal;
+in vec4 tangent;
 in flat uint materialId;  // used for indexing into materials
+in flat vec4 tint;
+in float occlusion;
 
 struct Material {
     vec4 albedo;
@@ -18,11 +23,17 @@ layout(location = 0) out vec4 gPosition;
 layout(location = 1) out vec4 gNormal;
 layout(location = 2) out vec4 gAlbedo;
 layout(location = 3) out vec2 gRoughnessAndMetallic;
+layout(location = 4) out vec4 gTangent;
 
 void main() {
     gPosition = fragPosition;
     gNormal = normalize(normal);
     gAlbedo = materials[materialId].albedo;
+    gAlbedo.rgb *= mix(1.0 - OCCLUSION_STRENGTH, 1.0, occlusion);
+    gAlbedo.rgb = mix(gAlbedo.rgb, tint.rgb, tint.a);
     gRoughnessAndMetallic.x = materials[materialId].roughness;
     gRoughnessAndMetallic.y = materials[materialId].metallic;
+
+    // The bitangent is reconstructed as cross(normal, tangent) * tangent.w.
+    gTangent = vec4(normalize(tangent.xyz), 1.0);
 }
\ No newline at end of file
@@ -4,10 +4,16 @@ const uint MAX_CHUNKS = 170;
 
 layout(location = 0) in vec4 a_position;
 layout(location = 1) in vec4 a_normal;
+layout(location = 2) in vec4 a_tangent;
 
-layout(location = 2) in vec3 a_offset;   // voxel position inside of the chunk
-layout(location = 3) in uint a_chunkId;  // used for indexing into chunks
-layout(location = 4) in uint a_materialId;  // used for indexing into materials.
+layout(location = 3) in vec3 a_offset;   // voxel position inside of the chunk
+layout(location = 4) in uint a_chunkId;  // used for indexing into chunks
+layout(location = 5) in uint a_materialId;  // used for indexing into materials.
+// The share of ambient light at the corners of every face, as two bits per
+// corner. The faces are in the order +x, -x, +y, -y, +z, -z, and the corners of
+// a face spanning the axes u and v in the order (-u, -v), (u, -v), (u, v) and
+// (-u, v), where u and v are the two axes following the axis of the face.
+layout(location = 6) in uvec2 a_occlusion;
 
 struct Chunk {
   mat4 modelMatrix;
@@ -15,17 +21,38 @@ struct Chunk {
 };
 
 layout(std140, binding = 0) uniform Chunks { Chunk chunks[MAX_CHUNKS]; };
+// The color of a flash in rgb and its strength in alpha.
+layout(std140, binding = 2) uniform Tints { vec4 tints[MAX_CHUNKS]; };
+
+// The amount of objects drawn with the same voxels. Every voxel is repeated for
+// each of them, and the objects take consecutive chunks from a_chunkId on.
+uniform int objects;
 
 out vec4 fragPosition;
 out vec4 normal;
+out vec4 tangent;
 out uint materialId;
+out vec4 tint;
+out float occlusion;
 
 void main() {
   vec4 position = vec4(a_position.xyz + a_offset, 1.0);
+  uint chunkId = a_chunkId + uint(gl_InstanceID % objects);
 
-  gl_Position = chunks[a_chunkId].mvpMatrix * position;
+  gl_Position = chunks[chunkId].mvpMatrix * position;
 
-  fragPosition = chunks[a_chunkId].modelMatrix * position;
+  fragPosition = chunks[chunkId].modelMatrix * position;
   normal = a_normal;
+  tangent = a_tangent;
   materialId = a_materialId;
+  tint = tints[chunkId];
+
+  uint axis = a_normal.x != 0.0 ? 0 : a_normal.y != 0.0 ? 1 : 2;
+  uint face = 2 * axis + uint(a_normal[axis] < 0.0);
+  bool u = a_position[(axis + 1) % 3] > 0.0;
+  bool v = a_position[(axis + 2) % 3] > 0.0;
+  uint corner = v ? (u ? 2 : 3) : (u ? 1 : 0);
+
+  uint bit = 2 * (4 * face + corner);
+  occlusion = float((a_occlusion[bit / 32] >> (bit % 32)) & 3) / 3.0;
 }
\ No newline at end of file
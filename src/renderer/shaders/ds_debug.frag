@@ -0,0 +1,11 @@
+#version 460 core
+
+in vec2 texcoord;
+
+uniform sampler2D gTarget;
+
+out vec4 color;
+
+void main() {
+    color = vec4(texture(gTarget, texcoord).xyz, 1.0);
+}
\ No newline at end of file
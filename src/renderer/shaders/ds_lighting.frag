@@ -1,6 +1,8 @@
 #version 460 core
 
 const float PI = 3.14159265359;
+const float AMBIENT_STRENGTH = 0.25;
+const float SUN_AMBIENT = 0.4;
 
 const uint MAX_LIGHTS = 256;
 const uint MAX_MATERIALS = 256;
@@ -12,6 +14,10 @@ uniform sampler2D gNormal;
 uniform sampler2D gAlbedo;
 uniform sampler2D gRoughnessAndMetallic;
 
+// Sampled in the direction of the normal for image-based ambient lighting. When
+// the renderer has no environment a black cubemap is bound instead.
+uniform samplerCube environment;
+
 struct Light {
     vec4 position;
     vec4 color;
@@ -23,16 +29,32 @@ layout(std140, binding = 1) uniform Camera {
     vec4 position;
 } camera;
 
+layout(std140, binding = 2) uniform Sun {
+    vec4 direction;
+    vec4 color;
+} sun;
+
 out vec4 color;
 
 void main() {
     vec3 worldPosition = texture(gWorldPosition, texcoord).xyz;
     vec3 normal  =  texture(gNormal, texcoord).xyz;
-    vec3 albedo  = texture(gAlbedo, texcoord).xyz;
+    vec4 albedo  = texture(gAlbedo, texcoord);
+
+    // The g-buffer is cleared to zero, so an albedo without alpha means that no
+    // geometry covers the pixel and the clear color should show through.
+    if (albedo.a == 0.0) {
+        discard;
+    }
 
     vec2 roughnessAndMetallic = texture(gRoughnessAndMetallic, texcoord).xy;
     float roughness = roughnessAndMetallic.x;
     float metallic = roughnessAndMetallic.y;
 
-    color = vec4(albedo, 1.0);
+    vec3 ambient = texture(environment, normal).rgb * albedo.rgb * AMBIENT_STRENGTH;
+
+    float diffuse = max(dot(normal, -sun.direction.xyz), 0.0);
+    vec3 sunlight = SUN_AMBIENT + (1.0 - SUN_AMBIENT) * diffuse * sun.color.rgb;
+
+    color = vec4(albedo.rgb * sunlight + ambient, 1.0);
 }
\ No newline at end of file
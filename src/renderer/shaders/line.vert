@@ -0,0 +1,14 @@
+#version 460 core
+
+layout(location = 0) in vec4 a_position;
+layout(location = 1) in vec4 a_color;
+
+// Transforms world space positions to clip space.
+layout(std140, binding = 0) uniform Bounds { mat4 mvpMatrix; };
+
+out vec4 v_color;
+
+void main() {
+  v_color = a_color;
+  gl_Position = mvpMatrix * a_position;
+}
@@ -0,0 +1,5 @@
+#version 460 core
+
+// Nothing is written, the bounding boxes are only drawn to count the samples
+// passing the depth test.
+void main() {}
@@ -0,0 +1,13 @@
+#version 460 core
+
+layout(location = 0) in vec4 a_position;
+
+const uint MAX_CHUNKS = 170;
+
+// Transforms the unit cube to the bounding box of every chunk in clip space.
+// The chunk is picked by the base instance of the draw.
+layout(std140, binding = 0) uniform Bounds { mat4 mvpMatrices[MAX_CHUNKS]; };
+
+void main() {
+  gl_Position = mvpMatrices[gl_BaseInstance] * a_position;
+}
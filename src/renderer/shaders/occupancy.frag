@@ -0,0 +1,9 @@
+#version 460 core
+
+out vec4 color;
+
+// Occupied voxels are tinted, so the structure of the chunk shows through the
+// lit scene.
+void main() {
+  color = vec4(0.0, 1.0, 0.0, 0.25);
+}
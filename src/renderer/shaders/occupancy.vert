@@ -0,0 +1,11 @@
+#version 460 core
+
+layout(location = 0) in vec4 a_position;
+layout(location = 1) in vec3 a_offset;
+
+// Transforms the voxels of a chunk to clip space.
+layout(std140, binding = 0) uniform Bounds { mat4 mvpMatrix; };
+
+void main() {
+  gl_Position = mvpMatrix * vec4(a_position.xyz + a_offset, 1.0);
+}
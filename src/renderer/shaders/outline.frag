@@ -0,0 +1,9 @@
+#version 460 core
+
+out vec4 color;
+
+// The outline of the highlighted object is drawn in a single bright color, so
+// it stands out against the lit scene.
+void main() {
+  color = vec4(1.0, 0.8, 0.1, 1.0);
+}
@@ -0,0 +1,11 @@
+#version 460 core
+
+in vec3 direction;
+
+uniform samplerCube environment;
+
+out vec4 color;
+
+void main() {
+    color = vec4(texture(environment, direction).rgb, 1.0);
+}
\ No newline at end of file
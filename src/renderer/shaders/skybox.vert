@@ -0,0 +1,16 @@
+#version 460 core
+
+layout(location = 0) in vec4 a_position;
+
+// The view matrix without its translation, so the skybox follows the camera.
+layout(std140, binding = 0) uniform Matrices { mat4 viewProjection; };
+
+out vec3 direction;
+
+void main() {
+  direction = a_position.xyz;
+
+  // Setting z to w places the skybox on the far plane behind all geometry.
+  vec4 position = viewProjection * vec4(a_position.xyz, 1.0);
+  gl_Position = position.xyww;
+}
\ No newline at end of file
@@ -3,11 +3,31 @@ in vec2 texcoord;
 
 uniform sampler2D textAtlas;
 
-uniform vec4 textColor;
+in vec4 textColor;
+
+// Draws a dark outline around the glyphs.
+uniform bool outline;
+
+// How far the outline reaches beyond the edge of a glyph, in texels.
+const float OUTLINE_WIDTH = 1.5;
 
 out vec4 color;
 
 void main() {
   vec4 sampled = vec4(1.0, 1.0, 1.0, texture(textAtlas, texcoord).a);
-  color = vec4(0.0, 1.0, 0.0, 1.0) * sampled;
-}
\ No newline at end of file
+  color = textColor * sampled;
+
+  if (outline) {
+    // The outline covers every texel within reach of the glyph, which is found
+    // by sampling the atlas in 8 directions around it.
+    vec2 texel = OUTLINE_WIDTH / vec2(textureSize(textAtlas, 0));
+    float coverage = 0.0;
+    for (int i = 0; i < 8; i++) {
+      float angle = radians(45.0 * float(i));
+      vec2 offset = vec2(cos(angle), sin(angle)) * texel;
+      coverage = max(coverage, texture(textAtlas, texcoord + offset).a);
+    }
+
+    color = vec4(color.rgb * sampled.a, textColor.a * max(sampled.a, coverage));
+  }
+}
@@ -2,14 +2,15 @@
 
 layout(location = 0) in vec2 a_position;
 layout(location = 1) in vec2 a_texcoord;
+layout(location = 2) in vec4 a_color;
 
-uniform float scale;
-
-uniform Matrices { mat4 ortho; };
+layout(std140, binding = 0) uniform Matrices { mat4 ortho; };
 
 out vec2 texcoord;
+out vec4 textColor;
 
 void main() {
-  gl_Position = ortho * vec4(a_position * scale, 0.0, 1.0);
+  gl_Position = ortho * vec4(a_position, 0.0, 1.0);
   texcoord = a_texcoord;
+  textColor = a_color;
 }
\ No newline at end of file
@@ -0,0 +1,32 @@
+#version 460 core
+in vec2 texcoord;
+in vec4 textColor;
+
+uniform sampler2D textAtlas;
+
+// Draws a dark outline around the glyphs.
+uniform bool outline;
+
+// The distance at which the outline ends. Values below 0.5 lie outside of the
+// glyph, so this is as far as the outline reaches.
+const float OUTLINE_EDGE = 0.35;
+
+out vec4 color;
+
+void main() {
+  // The atlas stores the distance to the edge of the glyph, where 0.5 lies
+  // exactly on the edge. Smoothing over the width of one pixel keeps the edge
+  // sharp no matter how much the text is scaled.
+  float distance = texture(textAtlas, texcoord).a;
+  float width = fwidth(distance);
+  float alpha = smoothstep(0.5 - width, 0.5 + width, distance);
+
+  color = vec4(textColor.rgb, textColor.a * alpha);
+
+  if (outline) {
+    // The distance field already knows how far away the glyph is, so the
+    // outline is just a second, wider edge drawn in black.
+    float coverage = smoothstep(OUTLINE_EDGE - width, OUTLINE_EDGE + width, distance);
+    color = vec4(color.rgb * alpha, textColor.a * coverage);
+  }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+
+use glam::*;
+use image::{Rgba, RgbaImage};
+
+use super::deferred_renderer::DeferredRenderer;
+use super::CUBE;
+use crate::scene::*;
+
+/// Rasterizes the voxels of a scene on the CPU, using the same matrices and
+/// cube as the deferred renderer. It only draws flat material colors, which is
+/// enough to test where geometry ends up on the screen without a GL context.
+pub struct SoftwareRenderer {
+    color: RgbaImage,
+    depth: Vec<f32>,
+}
+
+impl SoftwareRenderer {
+    const CLEAR_COLOR: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+    pub fn new(size: UVec2) -> Self {
+        Self {
+            color: RgbaImage::from_pixel(size.x, size.y, Self::CLEAR_COLOR),
+            depth: vec![f32::INFINITY; (size.x * size.y) as usize],
+        }
+    }
+
+    /// The rendered image, where the first row is the top of the screen.
+    pub fn image(&self) -> &RgbaImage {
+        &self.color
+    }
+
+    pub fn render(&mut self, scene: &mut Scene) -> &RgbaImage {
+        self.color
+            .pixels_mut()
+            .for_each(|pixel| *pixel = Self::CLEAR_COLOR);
+        self.depth.fill(f32::INFINITY);
+
+        let (matrices, _, models, voxels) =
+            DeferredRenderer::extract_matrices_and_voxels(scene, &HashSet::new());
+        let materials = scene.materials();
+
+        let objects = models.iter().flat_map(|model| model.expand());
+        for voxel in objects.chain(voxels) {
+            let [_, mvp] = matrices[voxel.chunk_id as usize];
+            let [r, g, b, a] = materials[voxel.material_id as usize].albedo;
+
+            let clip = CUBE.map(|vertex| mvp * (vertex.0.truncate() + voxel.position).extend(1.0));
+            for triangle in clip.chunks_exact(3) {
+                self.rasterize([triangle[0], triangle[1], triangle[2]], Rgba([r, g, b, a]));
+            }
+        }
+
+        &self.color
+    }
+
+    fn rasterize(&mut self, clip: [Vec4; 3], color: Rgba<u8>) {
+        // Triangles crossing the camera plane are dropped instead of clipped.
+        if clip.iter().any(|vertex| vertex.w <= 0.0) {
+            return;
+        }
+
+        let size = uvec2(self.color.width(), self.color.height()).as_vec2();
+        let screen = clip.map(|vertex| {
+            let ndc = vertex.truncate() / vertex.w;
+            vec3(
+                (ndc.x + 1.0) / 2.0 * size.x,
+                (1.0 - ndc.y) / 2.0 * size.y,
+                ndc.z,
+            )
+        });
+
+        let edge =
+            |a: Vec3, b: Vec3, p: Vec2| (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x);
+        let area = edge(screen[0], screen[1], screen[2].truncate());
+        if area == 0.0 {
+            return;
+        }
+
+        let min = screen
+            .iter()
+            .fold(size, |min, v| min.min(v.truncate()))
+            .max(Vec2::ZERO);
+        let max = screen
+            .iter()
+            .fold(Vec2::ZERO, |max, v| max.max(v.truncate()))
+            .min(size);
+
+        for y in min.y as u32..max.y.ceil() as u32 {
+            for x in min.x as u32..max.x.ceil() as u32 {
+                let p = vec2(x as f32, y as f32) + 0.5;
+
+                // Both windings are drawn, so the weights are normalized by the
+                // signed area.
+                let weights = vec3(
+                    edge(screen[1], screen[2], p),
+                    edge(screen[2], screen[0], p),
+                    edge(screen[0], screen[1], p),
+                ) / area;
+                if weights.min_element() < 0.0 {
+                    continue;
+                }
+
+                let depth = weights.dot(vec3(screen[0].z, screen[1].z, screen[2].z));
+                let index = (y * self.color.width() + x) as usize;
+                if depth < self.depth[index] {
+                    self.depth[index] = depth;
+                    self.color.put_pixel(x, y, color);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn single_cube() {
+    let mut scene = Scene::empty();
+
+    let red = Material {
+        albedo: [255, 0, 0, 255],
+        roughness: 1.0,
+        metalness: 0.0,
+    };
+    let mut materials = Box::new([Material::AIR; 256]);
+    materials[1] = red;
+    scene.set_materials(materials);
+
+    // The camera looks down the z axis from the origin.
+    let mut chunk = crate::tensor::SparseTensorChunk::nothing(UVec3::ONE);
+    chunk.insert(UVec3::ZERO, Some(MaterialId(1)));
+    chunk.transform = Mat4::from_translation(vec3(0.0, 0.0, 5.0));
+    scene.add_terrain(chunk);
+    scene.scene_graph.evaluate_all();
+
+    let mut renderer = SoftwareRenderer::new(uvec2(64, 64));
+    let image = renderer.render(&mut scene);
+
+    let red = Rgba([255, 0, 0, 255]);
+    for (x, y) in [(31, 31), (32, 32), (30, 33)] {
+        assert_eq!(*image.get_pixel(x, y), red, "({x}, {y}) isn't covered");
+    }
+    for (x, y) in [(0, 0), (63, 0), (0, 63), (63, 63), (32, 4)] {
+        assert_eq!(*image.get_pixel(x, y), SoftwareRenderer::CLEAR_COLOR);
+    }
+}
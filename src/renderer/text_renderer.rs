@@ -1,5 +1,10 @@
+use std::rc::Rc;
+use std::sync::OnceLock;
+
 use glam::*;
+use image::{Rgba, RgbaImage};
 
+use super::screen_ortho;
 use crate::format::fnt::*;
 use crate::rhi::*;
 use crate::scene::*;
@@ -9,11 +14,12 @@ use crate::scene::*;
 struct TextVertex {
     position: Vec2,
     texcoord: Vec2,
+    color: Vec4,
 }
 
 unsafe impl BufferLayout for TextVertex {
-    const LAYOUT: &'static [Format] = &[Format::Vec2, Format::Vec2];
-    const PADDING: &'static [usize] = &[0, 0];
+    const LAYOUT: &'static [Format] = &[Format::Vec2, Format::Vec2, Format::Vec4];
+    const PADDING: &'static [usize] = &[0, 0, 0];
     const COPYABLE: bool = true;
 
     fn to_bytes(items: &[Self]) -> Vec<u8> {
@@ -21,156 +27,549 @@ unsafe impl BufferLayout for TextVertex {
     }
 }
 
+const FONT_FACE: &[u8] = include_bytes!("../../assets/fonts/sans-serif/sans-serif.fnt");
+const FONT_IMAGE: &[u8] = include_bytes!("../../assets/fonts/sans-serif/sans-serif.png");
+
+/// The bundled font image, flipped so the bottom row comes first as OpenGL
+/// expects. Decoding the PNG is slow, so it is only done once.
+fn font_image() -> &'static RgbaImage {
+    static IMAGE: OnceLock<RgbaImage> = OnceLock::new();
+    IMAGE.get_or_init(|| {
+        image::load_from_memory(FONT_IMAGE)
+            .unwrap()
+            .flipv()
+            .into_rgba8()
+    })
+}
+
+/// How far from the edge of a glyph the distance field of the bundled font
+/// reaches, in texels.
+const SDF_SPREAD: u32 = 4;
+
+/// The bundled font image converted to a signed distance field.
+fn sdf_font_image() -> &'static RgbaImage {
+    static IMAGE: OnceLock<RgbaImage> = OnceLock::new();
+    IMAGE.get_or_init(|| distance_field(font_image(), SDF_SPREAD))
+}
+
+/// Converts the alpha of a bitmap to a signed distance field. Every texel
+/// stores the distance to the closest edge within `spread` texels, mapped so
+/// 0.5 lies on the edge and values above it are inside the glyph.
+fn distance_field(image: &RgbaImage, spread: u32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let inside = |x: u32, y: u32| image.get_pixel(x, y)[3] >= 128;
+
+    // Texels outside of the image count as outside of the glyph, and the closest
+    // of them is always right next to the border, so one texel of padding is
+    // enough.
+    let padded = uvec2(width + 2, height + 2);
+    let inside_padded = |x: u32, y: u32| {
+        (1..=width).contains(&x) && (1..=height).contains(&y) && inside(x - 1, y - 1)
+    };
+    let to_inside = squared_distances_2d(padded, inside_padded);
+    let to_outside = squared_distances_2d(padded, |x, y| !inside_padded(x, y));
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let center = inside(x, y);
+        let i = ((y + 1) * padded.x + x + 1) as usize;
+        let squared = if center { to_outside[i] } else { to_inside[i] };
+        let closest = squared.sqrt().min(spread as f32);
+
+        // The edge lies halfway between the two texels.
+        let distance = if center { closest - 0.5 } else { 0.5 - closest };
+        let value = 0.5 + distance / (2.0 * spread as f32);
+        Rgba([255, 255, 255, (value.clamp(0.0, 1.0) * 255.0) as u8])
+    })
+}
+
+/// The squared distance from every texel of a `size` grid to the closest texel
+/// for which `target` is true, in row-major order. The distance transform is
+/// separable, so it is done along the rows and then along the columns.
+fn squared_distances_2d(size: UVec2, target: impl Fn(u32, u32) -> bool) -> Vec<f32> {
+    let (width, height) = (size.x as usize, size.y as usize);
+    let mut distances: Vec<f32> = (0..size.y)
+        .flat_map(|y| (0..size.x).map(move |x| (x, y)))
+        .map(|(x, y)| if target(x, y) { 0.0 } else { FAR })
+        .collect();
+
+    for row in distances.chunks_mut(width) {
+        let transformed = squared_distances(row);
+        row.copy_from_slice(&transformed);
+    }
+
+    for x in 0..width {
+        let column: Vec<f32> = (0..height).map(|y| distances[y * width + x]).collect();
+        for (y, distance) in squared_distances(&column).into_iter().enumerate() {
+            distances[y * width + x] = distance;
+        }
+    }
+
+    distances
+}
+
+/// Stands in for an infinite distance, which would turn the intersections in
+/// [`squared_distances`] into NaN.
+const FAR: f32 = 1e20;
+
+/// The one dimensional distance transform by Felzenszwalb and Huttenlocher.
+/// For every index `q` this is the smallest `(q - p)² + f[p]`, found from the
+/// lower envelope of the parabolas rooted at every `p`.
+fn squared_distances(f: &[f32]) -> Vec<f32> {
+    let n = f.len();
+    // The roots of the parabolas making up the envelope, and the ranges in which
+    // they do, where parabola `k` is the lowest between `z[k]` and `z[k + 1]`.
+    let mut v = vec![0; n];
+    let mut z = vec![0.0; n + 1];
+    let mut k = 0;
+    z[0] = f32::NEG_INFINITY;
+    z[1] = f32::INFINITY;
+
+    let intersection = |q: usize, p: usize| {
+        let (qf, pf) = (q as f32, p as f32);
+        ((f[q] + qf * qf) - (f[p] + pf * pf)) / (2.0 * (qf - pf))
+    };
+
+    for q in 1..n {
+        let mut s = intersection(q, v[k]);
+        while s <= z[k] {
+            k -= 1;
+            s = intersection(q, v[k]);
+        }
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = f32::INFINITY;
+    }
+
+    k = 0;
+    (0..n)
+        .map(|q| {
+            while z[k + 1] < q as f32 {
+                k += 1;
+            }
+            let offset = q as f32 - v[k] as f32;
+            offset * offset + f[v[k]]
+        })
+        .collect()
+}
+
+/// A font face together with its glyph atlas on the GPU. Text renderers can
+/// share a font through an [`Rc`], so the atlas is only uploaded once.
+pub struct Font {
+    face: FontFace,
+    atlas: Texture2D,
+}
+
+impl Font {
+    pub fn new(device: &Device, face: FontFace, image: &RgbaImage) -> Self {
+        assert_eq!(image.dimensions(), (face.width as _, face.height as _));
+
+        let mut atlas = device
+            .new_texture_2d(face.width, face.height, Format::R8G8B8A8)
+            .unwrap();
+        atlas.write(image.as_raw());
+
+        unsafe {
+            gl::TextureParameteri(atlas.id, gl::TEXTURE_MIN_FILTER, gl::LINEAR as _);
+            gl::TextureParameteri(atlas.id, gl::TEXTURE_MAG_FILTER, gl::LINEAR as _);
+        }
+
+        Self { face, atlas }
+    }
+
+    /// The sans-serif font shipped with the game.
+    pub fn bundled(device: &Device) -> Self {
+        Self::new(device, parse(FONT_FACE), font_image())
+    }
+
+    /// The bundled font as a signed distance field, which stays sharp when
+    /// scaled.
+    pub fn bundled_sdf(device: &Device) -> Self {
+        let mut face = parse(FONT_FACE);
+        face.distance_range = Some(2 * SDF_SPREAD);
+        Self::new(device, face, sdf_font_image())
+    }
+
+    pub fn is_sdf(&self) -> bool {
+        self.face.distance_range.is_some()
+    }
+
+    pub fn face(&self) -> &FontFace {
+        &self.face
+    }
+}
+
 pub struct TextRenderer<'a> {
     device: Device<'a>,
     shaders: ShaderProgram,
-    font_face: FontFace,
-    atlas: Texture2D,
-    matrix_buffer: Buffer<Mat4, false, true>,
+    font: Rc<Font>,
+    matrix_buffer: UniformBuffer<Mat4>,
 }
 
 impl<'a> TextRenderer<'a> {
     const VERTEX_SHADER: &'static str = include_str!("./shaders/text.vert");
     const PIXEL_SHADER: &'static str = include_str!("./shaders/text.frag");
+    const SDF_PIXEL_SHADER: &'static str = include_str!("./shaders/text_sdf.frag");
 
-    const FONT_FACE: &'static [u8] = include_bytes!("../../assets/fonts/sans-serif/sans-serif.fnt");
-    const FONT_IMAGE: &'static [u8] =
-        include_bytes!("../../assets/fonts/sans-serif/sans-serif.png");
+    pub fn new(device: Device<'a>, window_size: UVec2) -> Result<Self, RhiError> {
+        let font = Rc::new(Font::bundled_sdf(&device));
+        Self::with_font(device, font, window_size)
+    }
 
-    pub fn new(device: Device<'a>, window_size: UVec2) -> Self {
+    pub fn with_font(
+        device: Device<'a>,
+        font: Rc<Font>,
+        window_size: UVec2,
+    ) -> Result<Self, RhiError> {
         let shaders = {
-            let vs = device.new_shader(VertexStage, Self::VERTEX_SHADER);
-            let ps = device.new_shader(PixelStage, Self::PIXEL_SHADER);
-            device.new_shader_program(&vs, &ps)
+            let vs = device.new_shader(VertexStage, Self::VERTEX_SHADER)?;
+            let ps = match font.is_sdf() {
+                true => device.new_shader(PixelStage, Self::SDF_PIXEL_SHADER)?,
+                false => device.new_shader(PixelStage, Self::PIXEL_SHADER)?,
+            };
+            device.new_shader_program(&vs, &ps)?
         };
 
-        let font_face = parse(Self::FONT_FACE);
-        let mut atlas = device.new_texture_2d(font_face.width, font_face.height, Format::R8G8B8A8);
-        let font_image = image::load_from_memory(Self::FONT_IMAGE).unwrap();
-        atlas.write(font_image.flipv().as_rgba8().as_ref().unwrap());
-
-        let [width, height] = window_size.to_array().map(|v| v as _);
-        let projection = Mat4::orthographic_rh_gl(0.0, width, 0.0, height, 0.0, 1.0);
-        let matrix_buffer = device.new_buffer(BufferInit::Data(&[projection]));
+        let mut matrix_buffer = device.new_uniform_buffer("Matrices", 0, 1);
+        matrix_buffer
+            .map_write()
+            .write(&[screen_ortho(window_size)]);
 
-        Self {
+        Ok(Self {
             device,
             shaders,
-            font_face,
-            atlas,
+            font,
             matrix_buffer,
-        }
+        })
     }
 
     pub fn render(&mut self, scene: &Scene, framebuffer: &mut Framebuffer) {
         let Self { device, .. } = self;
 
-        let Text {
-            position,
-            text,
-            color,
-            scale,
-        } = &scene.text[0];
+        let batches = Self::batches(&self.font.face, &scene.text);
+        if batches.is_empty() {
+            return;
+        }
+
+        device.set_blend(Some(BlendMode::Alpha));
+        device.set_depth_test(None);
+
+        device.bind_shader_program(&self.shaders);
+
+        unsafe { gl!(gl::BindTexture(gl::TEXTURE_2D, self.font.atlas.id)) }.unwrap();
+        self.matrix_buffer.bind();
+
+        device.bind_framebuffer(framebuffer);
+
+        for (outline, vertices) in batches {
+            let vertex_buffer: Buffer<_, false, false> =
+                device.new_buffer(BufferInit::Data(&vertices));
 
-        let position = vec2(position.x as _, position.y as _);
+            device.bind_vertex_buffer(BindProps {
+                binding: 0,
+                attributes: &["a_position", "a_texcoord", "a_color"],
+                buffer: &vertex_buffer,
+                instanced: false,
+            });
 
+            device.set_uniform_i32("outline", outline as _);
+            device.draw(vertices.len());
+        }
+    }
+
+    /// Lays out the texts in draw order. Consecutive texts with the same
+    /// outline mode are grouped into one batch, since the mode is a uniform of
+    /// the shader and every batch takes a draw call.
+    fn batches(face: &FontFace, texts: &[Text]) -> Vec<(bool, Vec<TextVertex>)> {
+        let mut batches: Vec<(bool, Vec<TextVertex>)> = Vec::new();
+        for text in Self::draw_order(texts) {
+            let Text {
+                position,
+                text: string,
+                color,
+                scale,
+                layout,
+                ..
+            } = text;
+
+            let position = vec2(position.x as _, position.y as _);
+            let line_height = face.line_height as f32 * scale;
+
+            let mut vertices = Vec::new();
+            for (i, (line, offset)) in layout.lines(face, string, *scale).into_iter().enumerate() {
+                let line_position = position + vec2(offset, -line_height * i as f32);
+                vertices.extend(Self::layout_line(face, line, line_position, *scale, *color));
+            }
+
+            match batches.last_mut() {
+                Some((outline, batch)) if *outline == text.outline => batch.extend(vertices),
+                _ => batches.push((text.outline, vertices)),
+            }
+        }
+
+        batches.retain(|(_, vertices)| !vertices.is_empty());
+        batches
+    }
+
+    /// Sorts the texts by layer. The texts are drawn in this order, one batch
+    /// after the other, so later glyphs are blended on top of earlier ones.
+    fn draw_order(texts: &[Text]) -> Vec<&Text> {
+        let mut texts: Vec<&Text> = texts.iter().collect();
+        texts.sort_by_key(|text| text.layer);
+        texts
+    }
+
+    /// Builds the quads of the glyphs in a single line of text, starting at
+    /// `position` and scaled by `scale`. The texture coordinates are unaffected
+    /// by the scale.
+    fn layout_line(
+        face: &FontFace,
+        text: &str,
+        position: Vec2,
+        scale: f32,
+        color: Vec4,
+    ) -> Vec<TextVertex> {
         let mut vertices = Vec::with_capacity(6 * text.chars().count());
         let mut advance = Vec2::default();
         for c in text.chars() {
             if c.is_whitespace() {
-                advance += vec2(38.0, 0.0);
+                advance += vec2(face.advance(c) as _, 0.0) * scale;
                 continue;
             }
 
-            let glyph = self
-                .font_face
-                .glyphs
-                .iter()
-                .find(|glyph| glyph.id == c)
-                .unwrap();
+            // Characters missing from the font are skipped.
+            let Some(glyph) = face.glyph(c) else {
+                continue;
+            };
 
             let glyph_size = vec2(glyph.size.x as _, glyph.size.y as _);
             let glyph_position = vec2(glyph.position.x as _, glyph.position.y as _);
-            let glyph_offset = vec2(glyph.offset.x as _, glyph.offset.y as _);
+            let glyph_offset = vec2(glyph.offset.x as _, glyph.offset.y as _) * scale;
             let glyph_height = vec2(0.0, glyph_size.y);
             let glyph_width = vec2(glyph_size.x, 0.0);
 
             // (font_face_width, 0) -> (1, 0)
             // (0, font_face_height) -> (0, 0)
             let to_opengl = |texcoord: Vec2| {
-                let x = texcoord.x / self.font_face.width as f32;
-                let y = 1.0 - (texcoord.y / self.font_face.height as f32);
+                let x = texcoord.x / face.width as f32;
+                let y = 1.0 - (texcoord.y / face.height as f32);
                 vec2(x, y)
             };
 
+            let origin = position - glyph_offset + advance;
+            let [width, height] = [glyph_width, glyph_height].map(|v| v * scale);
+
             vertices.extend_from_slice(&[
                 // top left -> top right -> bottom left
                 TextVertex {
-                    position: position - glyph_offset + advance,
+                    position: origin,
                     texcoord: to_opengl(glyph_position),
+                    color,
                 },
                 TextVertex {
-                    position: position + glyph_width - glyph_offset + advance,
+                    position: origin + width,
                     texcoord: to_opengl(glyph_position + glyph_width),
+                    color,
                 },
                 TextVertex {
-                    position: position - glyph_height - glyph_offset + advance,
+                    position: origin - height,
                     texcoord: to_opengl(glyph_position + glyph_height),
+                    color,
                 },
                 // top right -> bottom right -> bottom left
                 TextVertex {
-                    position: position + glyph_width - glyph_offset + advance,
+                    position: origin + width,
                     texcoord: to_opengl(glyph_position + glyph_width),
+                    color,
                 },
                 TextVertex {
-                    position: position + glyph_width - glyph_height - glyph_offset + advance,
+                    position: origin + width - height,
                     texcoord: to_opengl(glyph_position + glyph_size),
+                    color,
                 },
                 TextVertex {
-                    position: position - glyph_height - glyph_offset + advance,
+                    position: origin - height,
                     texcoord: to_opengl(glyph_position + glyph_height),
+                    color,
                 },
             ]);
 
-            advance += glyph_width
+            advance += width;
         }
 
-        let vertex_buffer: Buffer<_, false, false> = device.new_buffer(BufferInit::Data(&vertices));
+        vertices
+    }
 
-        unsafe {
-            gl::Enable(gl::BLEND);
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-        }
+    pub fn font(&self) -> &Rc<Font> {
+        &self.font
+    }
 
-        device.bind_vertex_buffer(BindProps {
-            binding: 0,
-            attributes: &["a_position", "a_texcoord"],
-            buffer: &vertex_buffer,
-            instanced: false,
-        });
+    pub fn resize(&mut self, window_size: UVec2) {
+        self.matrix_buffer
+            .map_write()
+            .write(&[screen_ortho(window_size)]);
+    }
+}
 
-        device.bind_shader_program(&self.shaders);
+#[test]
+fn bundled_font_atlas() {
+    let face = parse(FONT_FACE);
+    let image = font_image();
+    assert_eq!(image.dimensions(), (face.width as _, face.height as _));
 
-        unsafe {
-            gl::TextureParameteri(self.atlas.id, gl::TEXTURE_MIN_FILTER, gl::LINEAR as _);
-            gl::TextureParameteri(self.atlas.id, gl::TEXTURE_MAG_FILTER, gl::LINEAR as _);
-            gl!(gl::BindTexture(gl::TEXTURE_2D, self.atlas.id)).unwrap();
-
-            gl!(gl::BindBufferBase(
-                gl::UNIFORM_BUFFER,
-                0,
-                self.matrix_buffer.id
-            ))
-            .unwrap();
-        }
+    // The image is only decoded the first time.
+    assert!(std::ptr::eq(image, font_image()));
+}
 
-        device.bind_framebuffer(framebuffer);
-        device.draw(vertices.len());
-    }
+#[test]
+fn scaled_glyph_uvs() {
+    let face = parse(FONT_FACE);
+    let text = "Ammo 30";
 
-    pub fn resize(&mut self, window_size: UVec2) {
-        let [width, height] = window_size.to_array().map(|v| v as _);
-        let projection = Mat4::orthographic_rh_gl(0.0, width, 0.0, height, 0.0, 1.0);
-        self.matrix_buffer = self.device.new_buffer(BufferInit::Data(&[projection]));
+    let unscaled = TextRenderer::layout_line(&face, text, Vec2::ZERO, 1.0, Vec4::ONE);
+    let scaled = TextRenderer::layout_line(&face, text, Vec2::ZERO, 3.0, Vec4::ONE);
+    assert_eq!(
+        scaled.len(),
+        6 * text.chars().filter(|c| !c.is_whitespace()).count()
+    );
+
+    for (a, b) in unscaled.iter().zip(&scaled) {
+        assert_eq!(a.texcoord, b.texcoord);
+        assert!(b.texcoord.cmpge(Vec2::ZERO).all() && b.texcoord.cmple(Vec2::ONE).all());
+        assert!((a.position * 3.0).abs_diff_eq(b.position, 1e-4));
     }
 }
+
+#[test]
+fn distance_field_edge() {
+    // A 4 texel wide vertical bar in the middle of the image. The middle row
+    // is far enough from the top and bottom to only see the sides of the bar.
+    let image = RgbaImage::from_fn(8, 9, |x, _| {
+        Rgba([255, 255, 255, if (2..6).contains(&x) { 255 } else { 0 }])
+    });
+    let field = distance_field(&image, 2);
+    let alpha: Vec<u8> = (0..8).map(|x| field.get_pixel(x, 4)[3]).collect();
+
+    assert!(alpha[2] > 127 && alpha[1] < 128);
+    assert!(alpha[3] > alpha[2] && alpha[0] < alpha[1]);
+}
+
+#[test]
+fn distance_transform() {
+    let f = [FAR, 0.0, FAR, FAR, FAR, 0.0, FAR];
+    assert_eq!(squared_distances(&f), [1.0, 0.0, 1.0, 4.0, 1.0, 0.0, 1.0]);
+
+    // A single texel in the middle of the image, which the texels around it are
+    // measured from along the diagonals too.
+    let image = RgbaImage::from_fn(9, 9, |x, y| {
+        Rgba([255, 255, 255, if (x, y) == (4, 4) { 255 } else { 0 }])
+    });
+    let field = distance_field(&image, 4);
+    let expected = |distance: f32| ((0.5 + (0.5 - distance) / 8.0) * 255.0) as u8;
+    assert_eq!(field.get_pixel(5, 5)[3], expected(2f32.sqrt()));
+    assert_eq!(field.get_pixel(6, 7)[3], expected(13f32.sqrt()));
+    // Distances are clamped to the spread.
+    assert_eq!(field.get_pixel(7, 8)[3], expected(4.0));
+
+    // Texels next to the border of the image are next to the outside of the
+    // glyph.
+    let full = RgbaImage::from_pixel(3, 3, Rgba([255; 4]));
+    let field = distance_field(&full, 4);
+    assert_eq!(field.get_pixel(0, 1)[3], field.get_pixel(1, 0)[3]);
+    assert!(field.get_pixel(1, 1)[3] > field.get_pixel(0, 1)[3]);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn sdf_shader_compiles() {
+    let (_sdl, _window, instance) = crate::rhi::headless();
+    let device = instance.new_device().unwrap();
+
+    let font = Rc::new(Font::bundled_sdf(&device));
+    let renderer = TextRenderer::with_font(device, font, uvec2(1, 1)).unwrap();
+    assert!(renderer.font().is_sdf());
+}
+
+#[test]
+fn text_layers() {
+    let texts = [2, -1, 0, 2].map(|layer| Text {
+        layer,
+        ..Text::white(UVec2::ZERO, layer.to_string())
+    });
+
+    let layers: Vec<i32> = TextRenderer::draw_order(&texts)
+        .iter()
+        .map(|text| text.layer)
+        .collect();
+    assert_eq!(layers, [-1, 0, 2, 2]);
+}
+
+#[test]
+fn text_colors() {
+    let face = parse(FONT_FACE);
+    let red = vec4(1.0, 0.2, 0.2, 0.5);
+    let texts = [
+        Text::with_color(uvec2(0, 0), "FPS".to_string(), red),
+        Text::white(uvec2(0, 48), "Ammo 9".to_string()),
+    ];
+
+    let (_, vertices) = &TextRenderer::batches(&face, &texts)[0];
+    let colors: Vec<Vec4> = vertices.iter().map(|vertex| vertex.color).collect();
+    assert_eq!(colors.len(), 6 * "FPSAmmo9".len());
+    assert!(colors[..18].iter().all(|&color| color == red));
+    assert!(colors[18..].iter().all(|&color| color == Vec4::ONE));
+}
+
+#[test]
+fn outline_batches() {
+    let face = parse(FONT_FACE);
+    let text = |layer, outline| Text {
+        layer,
+        outline,
+        ..Text::white(uvec2(10, 20), "Ammo 9".to_string())
+    };
+
+    let plain = TextRenderer::batches(&face, &[text(0, false)]);
+    let outlined = TextRenderer::batches(&face, &[text(0, true)]);
+    assert!(!plain[0].0);
+    assert!(outlined[0].0);
+    // The outline is drawn by the shader, so the glyphs keep their quads.
+    assert_eq!(plain[0].1, outlined[0].1);
+
+    // Texts are batched in draw order, so the uniform changes between layers.
+    let texts = [text(2, true), text(0, false), text(1, true), text(3, true)];
+    let modes: Vec<bool> = TextRenderer::batches(&face, &texts)
+        .iter()
+        .map(|(outline, _)| *outline)
+        .collect();
+    assert_eq!(modes, [false, true]);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn outline_uniform() {
+    let (_sdl, _window, instance) = crate::rhi::headless();
+    let device = instance.new_device().unwrap();
+
+    let mut renderer = TextRenderer::new(device.clone(), uvec2(64, 64)).unwrap();
+    let mut scene = Scene::empty();
+
+    let uniform = |renderer: &mut TextRenderer, scene: &mut Scene, outline| {
+        scene.text = vec![Text {
+            outline,
+            ..Text::white(UVec2::ZERO, "FPS".to_string())
+        }];
+        renderer.render(scene, &mut device.default_framebuffer());
+
+        let name = std::ffi::CString::new("outline").unwrap();
+        let mut value = -1;
+        unsafe {
+            let location = gl::GetUniformLocation(renderer.shaders.id, name.as_ptr());
+            gl!(gl::GetUniformiv(renderer.shaders.id, location, &mut value)).unwrap();
+        }
+        value
+    };
+
+    assert_eq!(uniform(&mut renderer, &mut scene, true), 1);
+    assert_eq!(uniform(&mut renderer, &mut scene, false), 0);
+}
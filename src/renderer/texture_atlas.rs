@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use glam::*;
+use image::RgbaImage;
+
+use crate::rhi::*;
+use crate::scene::*;
+
+/// A region of a texture in OpenGL texture coordinates, so `min` is the bottom
+/// left corner and `max` is the top right corner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvRect {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+pub struct TextureAtlas {
+    texture: Texture2D,
+    rects: HashMap<MaterialId, UvRect>,
+}
+
+impl TextureAtlas {
+    pub fn texture(&self) -> &Texture2D {
+        &self.texture
+    }
+
+    /// The region of the atlas holding the texture of `material`.
+    pub fn rect(&self, material: MaterialId) -> Option<UvRect> {
+        self.rects.get(&material).copied()
+    }
+}
+
+/// Packs the textures of many materials into a single [`TextureAtlas`], so they
+/// can all be sampled from one texture binding.
+pub struct TextureAtlasBuilder {
+    width: u32,
+    images: Vec<(MaterialId, RgbaImage)>,
+}
+
+impl TextureAtlasBuilder {
+    /// The atlas is `width` texels wide and grows in height to fit the
+    /// images.
+    pub fn new(width: u32) -> Self {
+        Self {
+            width,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn add(mut self, material: MaterialId, image: RgbaImage) -> Self {
+        assert!(image.width() <= self.width, "image is wider than the atlas");
+        self.images.push((material, image));
+        self
+    }
+
+    pub fn build(self, device: &Device) -> TextureAtlas {
+        let (size, placements) = self.pack();
+
+        let mut pixels = RgbaImage::new(size.x, size.y);
+        for &(index, position) in &placements {
+            let image = &self.images[index].1;
+            image::imageops::replace(&mut pixels, image, position.x as _, position.y as _);
+        }
+
+        // Images are stored top to bottom while OpenGL expects the bottom row first.
+        let pixels = image::imageops::flip_vertical(&pixels);
+        let mut texture = device
+            .new_texture_2d(size.x as _, size.y as _, Format::R8G8B8A8)
+            .unwrap();
+        texture.write(pixels.as_raw());
+
+        let rects = placements
+            .into_iter()
+            .map(|(index, position)| {
+                let (material, image) = &self.images[index];
+                (*material, Self::uv_rect(size, position, image))
+            })
+            .collect();
+
+        TextureAtlas { texture, rects }
+    }
+
+    /// Places the images on shelves, where each shelf is as tall as the
+    /// tallest image on it. Sorting the images by height first keeps the
+    /// wasted space above the shorter images small.
+    ///
+    /// Returns the size of the atlas and the top left corner in texels of
+    /// every image, by its index in `images`.
+    fn pack(&self) -> (UVec2, Vec<(usize, UVec2)>) {
+        let mut order: Vec<usize> = (0..self.images.len()).collect();
+        order.sort_by_key(|&index| std::cmp::Reverse(self.images[index].1.height()));
+
+        let mut placements = Vec::with_capacity(order.len());
+        let mut cursor = UVec2::ZERO;
+        let mut shelf_height = 0;
+
+        for index in order {
+            let (width, height) = self.images[index].1.dimensions();
+
+            if cursor.x + width > self.width {
+                cursor = uvec2(0, cursor.y + shelf_height);
+                shelf_height = 0;
+            }
+
+            placements.push((index, cursor));
+            cursor.x += width;
+            shelf_height = shelf_height.max(height);
+        }
+
+        (uvec2(self.width, cursor.y + shelf_height), placements)
+    }
+
+    fn uv_rect(size: UVec2, position: UVec2, image: &RgbaImage) -> UvRect {
+        let size = size.as_vec2();
+        let position = position.as_vec2();
+        let image_size = vec2(image.width() as _, image.height() as _);
+
+        // The texels are flipped vertically when uploaded, so the bottom of the
+        // image ends up closest to the origin.
+        let min = vec2(position.x, size.y - position.y - image_size.y) / size;
+        let max = vec2(position.x + image_size.x, size.y - position.y) / size;
+        UvRect { min, max }
+    }
+}
+
+#[test]
+fn shelf_packing() {
+    let builder = TextureAtlasBuilder::new(16)
+        .add(MaterialId(1), RgbaImage::new(8, 4))
+        .add(MaterialId(2), RgbaImage::new(10, 8))
+        .add(MaterialId(3), RgbaImage::new(6, 6));
+
+    let (size, placements) = builder.pack();
+    assert_eq!(size.x, 16);
+
+    let rects: Vec<UvRect> = placements
+        .iter()
+        .map(|&(index, position)| {
+            TextureAtlasBuilder::uv_rect(size, position, &builder.images[index].1)
+        })
+        .collect();
+    assert_eq!(rects.len(), 3);
+
+    for (i, a) in rects.iter().enumerate() {
+        assert!(a.min.cmpge(Vec2::ZERO).all() && a.max.cmple(Vec2::ONE).all());
+        assert!(a.min.cmplt(a.max).all());
+
+        for b in &rects[i + 1..] {
+            let overlaps = a.min.cmplt(b.max).all() && b.min.cmplt(a.max).all();
+            assert!(!overlaps, "{a:?} overlaps {b:?}");
+        }
+    }
+}
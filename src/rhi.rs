@@ -3,7 +3,7 @@ use std::ffi::*;
 use std::marker::*;
 use std::rc::*;
 
-use glam::{Mat3, Mat4, Vec2, Vec3, Vec4};
+use glam::{uvec2, uvec4, Mat3, Mat4, Quat, UVec2, UVec3, UVec4, Vec2, Vec3, Vec4};
 use sdl2::video::*;
 
 macro_rules! gl {
@@ -29,6 +29,113 @@ macro_rules! gl {
 
 pub use gl;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RhiError {
+    /// The OpenGL context couldn't be created with the requested version.
+    ContextCreation { version: (u8, u8), reason: String },
+    /// The driver doesn't expose the listed OpenGL functions.
+    MissingFunctions(Vec<&'static str>),
+    /// An OpenGL call failed with the given error code.
+    Gl(gl::types::GLenum),
+    /// A shader failed to compile, with the driver's info log.
+    ShaderCompile(String),
+    /// A shader program failed to link, with the driver's info log.
+    ShaderLink(String),
+    /// A framebuffer can't be rendered to, with the status reported by OpenGL.
+    FramebufferIncomplete(gl::types::GLenum),
+    /// The format can't be used for the requested kind of resource.
+    UnsupportedFormat(Format),
+}
+
+impl std::fmt::Display for RhiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ContextCreation {
+                version: (major, minor),
+                reason,
+            } => write!(
+                f,
+                "failed to create an OpenGL {major}.{minor} context: {reason}"
+            ),
+            Self::MissingFunctions(functions) => write!(
+                f,
+                "the OpenGL driver is missing the required functions: {}",
+                functions.join(", ")
+            ),
+            Self::Gl(error) => write!(f, "OpenGL call failed with error 0x{error:04X}"),
+            Self::ShaderCompile(log) => write!(f, "failed to compile shader: {log}"),
+            Self::ShaderLink(log) => write!(f, "failed to link shader program: {log}"),
+            Self::FramebufferIncomplete(status) => {
+                write!(f, "framebuffer is incomplete with status 0x{status:04X}")
+            }
+            Self::UnsupportedFormat(format) => write!(f, "{format:?} isn't supported here"),
+        }
+    }
+}
+
+impl std::error::Error for RhiError {}
+
+/// Turns a NUL-terminated info log filled in by OpenGL into a string.
+fn info_log(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len])
+        .trim_end()
+        .to_string()
+}
+
+macro_rules! probe {
+    ($($f: ident),*) => {
+        [$((concat!("gl", stringify!($f)), gl::$f::is_loaded())),*]
+    };
+}
+
+/// Reports which of the Direct State Access functions used by the RHI were
+/// loaded. They require OpenGL 4.5+, and on older contexts they are left as
+/// null pointers which segfault when called.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub probed: Vec<&'static str>,
+    pub missing: Vec<&'static str>,
+}
+
+impl Capabilities {
+    pub fn probe() -> Self {
+        let functions = probe!(
+            CreateBuffers,
+            NamedBufferStorage,
+            MapNamedBuffer,
+            MapNamedBufferRange,
+            DrawArraysInstancedBaseInstance,
+            UnmapNamedBuffer,
+            CreateVertexArrays,
+            VertexArrayVertexBuffer,
+            VertexArrayElementBuffer,
+            EnableVertexArrayAttrib,
+            VertexArrayAttribBinding,
+            VertexArrayAttribFormat,
+            VertexArrayAttribIFormat,
+            VertexArrayBindingDivisor,
+            CreateTextures,
+            TextureStorage2D,
+            TextureSubImage2D,
+            CreateFramebuffers,
+            NamedFramebufferTexture,
+            NamedFramebufferDrawBuffers,
+            CheckNamedFramebufferStatus,
+            ClearNamedFramebufferfv
+        );
+
+        Self {
+            probed: functions.iter().map(|&(name, _)| name).collect(),
+            missing: functions
+                .iter()
+                .filter(|(_, loaded)| !loaded)
+                .map(|&(name, _)| name)
+                .collect(),
+        }
+    }
+}
+
 pub struct InstanceShared {
     window_context: Rc<WindowContext>,
     _context: GLContext,
@@ -37,35 +144,48 @@ pub struct InstanceShared {
 pub struct Instance(Rc<InstanceShared>);
 
 impl Instance {
-    pub fn new(window: &Window, debug: bool) -> Self {
-        let _context = window.gl_create_context().unwrap();
+    pub fn new(window: &Window, debug: bool) -> Result<Self, RhiError> {
+        let _context = window.gl_create_context().map_err(|reason| {
+            let version = window.subsystem().gl_attr().context_version();
+            RhiError::ContextCreation { version, reason }
+        })?;
         gl::load_with(|s| window.subsystem().gl_get_proc_address(s) as *const _);
 
+        let capabilities = Capabilities::probe();
+        if !capabilities.missing.is_empty() {
+            return Err(RhiError::MissingFunctions(capabilities.missing));
+        }
+
         if debug {
             unsafe { gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS) }
             unsafe { gl::DebugMessageCallback(Some(Self::debug_callback), std::ptr::null()) };
         }
 
-        Self(Rc::new(InstanceShared {
+        Ok(Self(Rc::new(InstanceShared {
             window_context: window.context(),
             _context,
-        }))
+        })))
     }
 
-    pub fn new_device<'a>(&self) -> Device<'a> {
+    pub fn new_device<'a>(&self) -> Result<Device<'a>, RhiError> {
         let mut vao = 0;
-        unsafe { gl!(gl::CreateVertexArrays(1, &mut vao)) }.unwrap();
+        unsafe { gl!(gl::CreateVertexArrays(1, &mut vao)) }.map_err(RhiError::Gl)?;
 
-        unsafe { gl!(gl::Enable(gl::DEPTH_TEST)) }.unwrap();
-        unsafe { gl!(gl::DepthFunc(gl::LEQUAL)) }.unwrap();
+        let depth_test = DepthFunc::LessEqual;
+        unsafe { gl!(gl::Enable(gl::DEPTH_TEST)) }.map_err(RhiError::Gl)?;
+        unsafe { gl!(gl::DepthFunc(depth_test.function())) }.map_err(RhiError::Gl)?;
 
         let shared = DeviceShared {
             vao,
             program: 0,
+            index_type: gl::UNSIGNED_INT,
+            blend: None,
+            depth_test: Some(depth_test),
+            stencil_test: None,
             _instance: Rc::clone(&self.0),
         };
 
-        Device(Rc::new(RefCell::new(shared)), PhantomData)
+        Ok(Device(Rc::new(RefCell::new(shared)), PhantomData))
     }
 
     pub fn new_swapchain(&self, vsync: bool) -> Swapchain {
@@ -101,21 +221,37 @@ impl Instance {
 struct DeviceShared {
     vao: u32,
     program: u32,
+    index_type: gl::types::GLenum,
+    blend: Option<BlendMode>,
+    depth_test: Option<DepthFunc>,
+    stencil_test: Option<StencilTest>,
     _instance: Rc<InstanceShared>,
 }
 
+impl Drop for DeviceShared {
+    fn drop(&mut self) {
+        let _ = unsafe { gl!(gl::DeleteVertexArrays(1, &self.vao)) };
+    }
+}
+
+/// The GL context is destroyed once the [`Instance`], the devices created
+/// from it and every resource created from those are dropped, in any order.
+/// Each resource holding a GL object keeps the device alive, which keeps the
+/// context alive, so the object is always deleted while its context exists.
+/// Anything that creates GL objects outside of the device has to hold on to a
+/// [`Device`] for the same reason.
 #[derive(Clone)]
 pub struct Device<'a>(Rc<RefCell<DeviceShared>>, PhantomData<&'a ()>);
 
 impl<'a> Device<'a> {
     pub fn default_framebuffer(&self) -> Framebuffer {
-        let mut _device = self.0.borrow_mut();
-
         Framebuffer {
             id: 0,
             textures: Vec::default(),
             depth: None,
+            level: 0,
             default: true,
+            _device: Rc::clone(&self.0),
         }
     }
 
@@ -168,7 +304,60 @@ impl<'a> Device<'a> {
         }
     }
 
-    pub fn new_texture_2d(&self, width: usize, height: usize, format: Format) -> Texture2D {
+    /// Creates `N` uniform buffers for the uniform block `block` at `binding`
+    /// with space for `capacity` elements each, see [`RingBuffer`].
+    pub fn new_ring_buffer<T, const N: usize>(
+        &self,
+        block: &'static str,
+        binding: usize,
+        capacity: usize,
+    ) -> RingBuffer<T, N>
+    where
+        T: BufferLayout,
+    {
+        RingBuffer {
+            buffers: std::array::from_fn(|_| self.new_uniform_buffer(block, binding, capacity)),
+            current: 0,
+        }
+    }
+
+    /// Creates a uniform buffer with space for `capacity` elements, which is
+    /// bound to the uniform block `block` at `binding`, see [`UniformBuffer`].
+    pub fn new_uniform_buffer<T>(
+        &self,
+        block: &'static str,
+        binding: usize,
+        capacity: usize,
+    ) -> UniformBuffer<T>
+    where
+        T: BufferLayout,
+    {
+        UniformBuffer {
+            buffer: self.new_buffer(BufferInit::Capacity(capacity)),
+            block,
+            binding,
+        }
+    }
+
+    /// Creates a texture with one of the texture or depth compatible formats.
+    pub fn new_texture_2d(
+        &self,
+        width: usize,
+        height: usize,
+        format: Format,
+    ) -> Result<Texture2D, RhiError> {
+        self.new_texture_2d_with_levels(width, height, 1, format)
+    }
+
+    /// Creates a texture with `levels` mip levels, each half the size of the
+    /// previous one.
+    pub fn new_texture_2d_with_levels(
+        &self,
+        width: usize,
+        height: usize,
+        levels: usize,
+        format: Format,
+    ) -> Result<Texture2D, RhiError> {
         let mut id = u32::MAX;
 
         let internal = match format {
@@ -177,65 +366,145 @@ impl<'a> Device<'a> {
             Format::R32G32B32A32Float => gl::RGBA32F,
             Format::R32G32Float => gl::RG32F,
             Format::R32Uint => gl::R32UI,
-            _ => panic!("Textures can only be created with texture compatible formats!"),
+            _ => return Err(RhiError::UnsupportedFormat(format)),
         };
 
-        unsafe {
-            gl!(gl::CreateTextures(gl::TEXTURE_2D, 1, &mut id)).unwrap();
+        unsafe { gl!(gl::CreateTextures(gl::TEXTURE_2D, 1, &mut id)) }.map_err(RhiError::Gl)?;
+        let texture = Texture2D {
+            id,
+            width,
+            height,
+            levels,
+            format,
+            _device: Rc::clone(&self.0),
+        };
 
+        unsafe {
             gl!(gl::TextureStorage2D(
                 id,
-                1,
+                levels as _,
                 internal,
                 width as _,
                 height as _
             ))
-            .unwrap();
         }
+        .map_err(RhiError::Gl)?;
+
+        Ok(texture)
+    }
+
+    /// Creates a cubemap where each of the six faces is `size` by `size`
+    /// texels.
+    pub fn new_texture_cube(&self, size: usize, format: Format) -> Result<TextureCube, RhiError> {
+        let mut id = u32::MAX;
+
+        let internal = match format {
+            Format::R8G8B8A8 => gl::RGBA8,
+            Format::R32G32B32A32Float => gl::RGBA32F,
+            _ => return Err(RhiError::UnsupportedFormat(format)),
+        };
 
-        Texture2D {
+        unsafe { gl!(gl::CreateTextures(gl::TEXTURE_CUBE_MAP, 1, &mut id)) }
+            .map_err(RhiError::Gl)?;
+        let texture = TextureCube {
             id,
-            width,
-            height,
+            size,
             format,
             _device: Rc::clone(&self.0),
+        };
+
+        unsafe { gl!(gl::TextureStorage2D(id, 1, internal, size as _, size as _)) }
+            .map_err(RhiError::Gl)?;
+
+        unsafe {
+            gl!(gl::TextureParameteri(
+                id,
+                gl::TEXTURE_MIN_FILTER,
+                gl::LINEAR as _
+            ))
+            .map_err(RhiError::Gl)?;
+            gl!(gl::TextureParameteri(
+                id,
+                gl::TEXTURE_MAG_FILTER,
+                gl::LINEAR as _
+            ))
+            .map_err(RhiError::Gl)?;
+            for wrap in [gl::TEXTURE_WRAP_S, gl::TEXTURE_WRAP_T, gl::TEXTURE_WRAP_R] {
+                gl!(gl::TextureParameteri(id, wrap, gl::CLAMP_TO_EDGE as _))
+                    .map_err(RhiError::Gl)?;
+            }
         }
+
+        Ok(texture)
     }
 
-    pub fn new_framebuffer<const N: usize>(&self, attachments: [Attachment; N]) -> Framebuffer {
+    pub fn new_framebuffer<const N: usize>(
+        &self,
+        attachments: [Attachment; N],
+    ) -> Result<Framebuffer, RhiError> {
+        self.new_framebuffer_at_level(attachments, 0)
+    }
+
+    /// Creates a framebuffer which renders to mip `level` of every attachment.
+    pub fn new_framebuffer_at_level<const N: usize>(
+        &self,
+        attachments: [Attachment; N],
+        level: usize,
+    ) -> Result<Framebuffer, RhiError> {
+        for attachment in &attachments {
+            let (texture, compatible) = match attachment {
+                Attachment::Color(texture, _) => (texture, Format::TEXTURE_COMPATIBLE),
+                Attachment::Depth(texture) => (texture, Format::DEPTH_COMPATIBLE),
+            };
+            if !compatible.contains(&texture.format) {
+                return Err(RhiError::UnsupportedFormat(texture.format));
+            }
+        }
+
         let mut id = u32::MAX;
-        unsafe { gl!(gl::CreateFramebuffers(1, &mut id)).unwrap() };
+        unsafe { gl!(gl::CreateFramebuffers(1, &mut id)) }.map_err(RhiError::Gl)?;
+
+        // From here on the framebuffer is deleted on drop, also when returning early.
+        let mut framebuffer = Framebuffer {
+            id,
+            textures: Vec::from_iter((0..attachments.len()).map(|_| None)),
+            depth: None,
+            level,
+            default: false,
+            _device: Rc::clone(&self.0),
+        };
 
-        let mut textures = Vec::from_iter((0..attachments.len()).map(|_| None));
-        let mut depth = None;
         for attachment in attachments {
             let (texture, attachment) = match attachment {
                 Attachment::Color(texture, index) => {
-                    assert!(Format::TEXTURE_COMPATIBLE.contains(&texture.format));
                     let texture_id = texture.id;
-                    textures[index] = Some(texture);
+                    framebuffer.textures[index] = Some(texture);
                     (texture_id, gl::COLOR_ATTACHMENT0 + index as u32)
                 }
                 Attachment::Depth(texture) => {
-                    assert!(Format::DEPTH_COMPATIBLE.contains(&texture.format));
-                    assert!(depth.is_none());
+                    assert!(framebuffer.depth.is_none());
                     let texture_id = texture.id;
-                    depth = Some(texture);
+                    framebuffer.depth = Some(texture);
                     (texture_id, gl::DEPTH_ATTACHMENT)
                 }
             };
 
-            unsafe { gl!(gl::NamedFramebufferTexture(id, attachment, texture, 0)) }.unwrap();
+            unsafe {
+                gl!(gl::NamedFramebufferTexture(
+                    id, attachment, texture, level as _
+                ))
+            }
+            .map_err(RhiError::Gl)?;
         }
 
-        if unsafe {
-            gl!(gl::CheckNamedFramebufferStatus(id, gl::FRAMEBUFFER)).unwrap()
-                != gl::FRAMEBUFFER_COMPLETE
-        } {
-            panic!("Framebuffer is not complete");
+        let status = unsafe { gl!(gl::CheckNamedFramebufferStatus(id, gl::FRAMEBUFFER)) }
+            .map_err(RhiError::Gl)?;
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(RhiError::FramebufferIncomplete(status));
         }
 
-        let points: Vec<_> = textures
+        let points: Vec<_> = framebuffer
+            .textures
             .iter()
             .enumerate()
             .filter_map(|(index, texture)| texture.as_ref().map(|_| index))
@@ -251,32 +520,35 @@ impl<'a> Device<'a> {
                 points.as_ptr()
             ))
         }
-        .unwrap();
+        .map_err(RhiError::Gl)?;
 
-        Framebuffer {
-            id,
-            textures,
-            depth,
-            default: false,
-        }
+        Ok(framebuffer)
     }
 
-    pub fn new_shader<S: Stage>(&self, _stage: S, src: &str) -> Shader<S> {
+    pub fn new_shader<S: Stage>(&self, _stage: S, src: &str) -> Result<Shader<S>, RhiError> {
         let stage = match S::STAGE_TYPE {
             StageType::Vertex => gl::VERTEX_SHADER,
             StageType::Geometry => gl::GEOMETRY_SHADER,
             StageType::Pixel => gl::FRAGMENT_SHADER,
+            StageType::Compute => gl::COMPUTE_SHADER,
         };
 
-        let id = unsafe { gl!(gl::CreateShader(stage)) }.unwrap();
+        let id = unsafe { gl!(gl::CreateShader(stage)) }.map_err(RhiError::Gl)?;
+        let shader = Shader(Rc::new(ShaderShared {
+            id,
+            _marker: PhantomData,
+            _device: Rc::clone(&self.0),
+        }));
 
         let string = &(src.as_ptr() as *const _);
-        unsafe { gl!(gl::ShaderSource(id, 1, string, [src.len() as _].as_ptr())) }.unwrap();
+        unsafe { gl!(gl::ShaderSource(id, 1, string, [src.len() as _].as_ptr())) }
+            .map_err(RhiError::Gl)?;
 
-        unsafe { gl!(gl::CompileShader(id)) }.unwrap();
+        unsafe { gl!(gl::CompileShader(id)) }.map_err(RhiError::Gl)?;
 
         let mut success = 0;
-        unsafe { gl!(gl::GetShaderiv(id, gl::COMPILE_STATUS, &mut success)) }.unwrap();
+        unsafe { gl!(gl::GetShaderiv(id, gl::COMPILE_STATUS, &mut success)) }
+            .map_err(RhiError::Gl)?;
         if success != 1 {
             let mut msg: [u8; 512] = [0; 512];
             unsafe {
@@ -288,27 +560,41 @@ impl<'a> Device<'a> {
                 ));
             };
 
-            let s = std::str::from_utf8(msg.as_slice()).unwrap();
-            panic!("{s}");
+            return Err(RhiError::ShaderCompile(info_log(&msg)));
         }
 
-        Shader(Rc::new(ShaderShared {
-            id,
-            _marker: PhantomData,
-            _device: Rc::clone(&self.0),
-        }))
+        Ok(shader)
+    }
+
+    pub fn new_shader_program(
+        &self,
+        vs: &VertexShader,
+        ps: &PixelShader,
+    ) -> Result<ShaderProgram, RhiError> {
+        self.link_program(&[vs.0.id, ps.0.id])
+    }
+
+    /// Creates a program for [`Device::dispatch_compute`].
+    pub fn new_compute_program(&self, cs: &ComputeShader) -> Result<ShaderProgram, RhiError> {
+        self.link_program(&[cs.0.id])
     }
 
-    pub fn new_shader_program(&self, vs: &VertexShader, ps: &PixelShader) -> ShaderProgram {
-        let id = unsafe { gl::CreateProgram() };
+    fn link_program(&self, shaders: &[u32]) -> Result<ShaderProgram, RhiError> {
+        let program = ShaderProgram {
+            id: unsafe { gl::CreateProgram() },
+            _device: Rc::clone(&self.0),
+        };
+        let id = program.id;
         unsafe {
-            gl!(gl::AttachShader(id, vs.0.id)).unwrap();
-            gl!(gl::AttachShader(id, ps.0.id)).unwrap();
-            gl!(gl::LinkProgram(id)).unwrap();
+            for shader in shaders {
+                gl!(gl::AttachShader(id, *shader)).map_err(RhiError::Gl)?;
+            }
+            gl!(gl::LinkProgram(id)).map_err(RhiError::Gl)?;
         }
 
         let mut success = 0;
-        unsafe { gl!(gl::GetProgramiv(id, gl::LINK_STATUS, &mut success)) }.unwrap();
+        unsafe { gl!(gl::GetProgramiv(id, gl::LINK_STATUS, &mut success)) }
+            .map_err(RhiError::Gl)?;
         if success != 1 {
             let mut msg: [u8; 512] = [0; 512];
             unsafe {
@@ -320,11 +606,10 @@ impl<'a> Device<'a> {
                 ));
             };
 
-            let s = std::str::from_utf8(msg.as_slice()).unwrap();
-            panic!("{s}");
+            return Err(RhiError::ShaderLink(info_log(&msg)));
         }
 
-        ShaderProgram { id }
+        Ok(program)
     }
 
     /// Binds vertex buffers to the device.
@@ -398,8 +683,28 @@ impl<'a> Device<'a> {
         }
     }
 
-    pub fn bind_index_buffer<const R: bool, const W: bool>(&self, buf: &'a Buffer<u32, R, W>) {
+    /// Advances the attributes of the instanced vertex buffer at `binding` once
+    /// every `divisor` instances, instead of once every instance.
+    pub fn set_instance_divisor(&self, binding: usize, divisor: usize) {
         let device = self.0.borrow();
+        unsafe {
+            gl!(gl::VertexArrayBindingDivisor(
+                device.vao,
+                binding as _,
+                divisor as _
+            ))
+        }
+        .unwrap();
+    }
+
+    /// Binds an index buffer of either `u16` or `u32` indices. The index type
+    /// is remembered by the device and used by the indexed draw calls.
+    pub fn bind_index_buffer<T, const R: bool, const W: bool>(&self, buf: &'a Buffer<T, R, W>)
+    where
+        T: IndexType,
+    {
+        let mut device = self.0.borrow_mut();
+        device.index_type = T::INDEX_TYPE;
         unsafe { gl!(gl::VertexArrayElementBuffer(device.vao, buf.id)) }.unwrap();
     }
 
@@ -410,15 +715,22 @@ impl<'a> Device<'a> {
         unsafe { gl!(gl::UseProgram(program.id)) }.unwrap();
     }
 
-    pub fn bind_uniform_buffer<T, const R: bool, const W: bool>(
+    pub fn bind_storage_buffer<T, const R: bool, const W: bool>(
         &self,
-        buf: &'a Buffer<T, R, W>,
+        buf: &Buffer<T, R, W>,
         binding: usize,
     ) where
         T: BufferLayout,
     {
-        let device = self.0.borrow_mut();
-        unsafe { gl!(gl::BindBufferBase(gl::UNIFORM_BUFFER, binding as _, buf.id)) }.unwrap();
+        let _device = self.0.borrow();
+        unsafe {
+            gl!(gl::BindBufferBase(
+                gl::SHADER_STORAGE_BUFFER,
+                binding as _,
+                buf.id
+            ))
+        }
+        .unwrap();
     }
 
     pub fn bind_texture_2d(&self, texture: &'a Texture2D, name: &str, location: usize) {
@@ -432,6 +744,46 @@ impl<'a> Device<'a> {
         }
     }
 
+    pub fn bind_texture_cube(&self, texture: &'a TextureCube, name: &str, location: usize) {
+        let device = self.0.borrow_mut();
+        let name = CString::new(name).unwrap();
+        unsafe {
+            gl!(gl::ActiveTexture(gl::TEXTURE0 + location as u32)).unwrap();
+            gl!(gl::BindTexture(gl::TEXTURE_CUBE_MAP, texture.id)).unwrap();
+            let uniform = gl::GetUniformLocation(device.program, name.as_ptr());
+            gl!(gl::Uniform1i(uniform, location.try_into().unwrap())).unwrap();
+        }
+    }
+
+    /// Binds `textures` to the consecutive texture units starting at
+    /// `base_location`, and points the elements of the `sampler2D` array
+    /// uniform `name` at them in the same order.
+    pub fn bind_texture_array(&self, textures: &[&'a Texture2D], name: &str, base_location: usize) {
+        let device = self.0.borrow_mut();
+        let name = CString::new(name).unwrap();
+        let units: Vec<i32> = (base_location..base_location + textures.len())
+            .map(|unit| unit.try_into().unwrap())
+            .collect();
+        unsafe {
+            for (texture, unit) in textures.iter().zip(&units) {
+                gl!(gl::ActiveTexture(gl::TEXTURE0 + *unit as u32)).unwrap();
+                gl!(gl::BindTexture(gl::TEXTURE_2D, texture.id)).unwrap();
+            }
+            let uniform = gl::GetUniformLocation(device.program, name.as_ptr());
+            gl!(gl::Uniform1iv(uniform, units.len() as _, units.as_ptr())).unwrap();
+        }
+    }
+
+    /// Sets the `int` or `bool` uniform `name` of the bound shader program.
+    pub fn set_uniform_i32(&self, name: &str, value: i32) {
+        let device = self.0.borrow();
+        let name = CString::new(name).unwrap();
+        unsafe {
+            let uniform = gl::GetUniformLocation(device.program, name.as_ptr());
+            gl!(gl::Uniform1i(uniform, value)).unwrap();
+        }
+    }
+
     pub fn bind_framebuffer(&self, framebuffer: &'a mut Framebuffer) {
         let _device = self.0.borrow();
         unsafe { gl!(gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer.id)) }.unwrap();
@@ -448,6 +800,13 @@ impl<'a> Device<'a> {
         unsafe { gl!(gl::DrawArrays(gl::TRIANGLES, 0, vertices as _)) }.unwrap();
     }
 
+    /// Draws `vertices` as separate lines between every pair of vertices.
+    pub fn draw_lines(&self, vertices: usize) {
+        let device = self.0.borrow();
+        unsafe { gl!(gl::BindVertexArray(device.vao)) }.unwrap();
+        unsafe { gl!(gl::DrawArrays(gl::LINES, 0, vertices as _)) }.unwrap();
+    }
+
     pub fn draw_indexed(&self, indices: usize) {
         let device = self.0.borrow();
         unsafe { gl!(gl::BindVertexArray(device.vao)) }.unwrap();
@@ -456,13 +815,100 @@ impl<'a> Device<'a> {
             gl!(gl::DrawElements(
                 gl::TRIANGLES,
                 indices as _,
-                gl::UNSIGNED_INT,
+                device.index_type,
                 std::ptr::null()
             ))
         }
         .unwrap();
     }
 
+    /// Draws `indices` indices starting at `first_index` in the bound index
+    /// buffer, with `base_vertex` added to every index. This allows many meshes
+    /// to share one vertex and index buffer.
+    pub fn draw_indexed_range(&self, indices: usize, first_index: usize, base_vertex: i32) {
+        let device = self.0.borrow();
+        unsafe { gl!(gl::BindVertexArray(device.vao)) }.unwrap();
+
+        let index_size = match device.index_type {
+            gl::UNSIGNED_SHORT => std::mem::size_of::<u16>(),
+            _ => std::mem::size_of::<u32>(),
+        };
+
+        unsafe {
+            gl!(gl::DrawElementsBaseVertex(
+                gl::TRIANGLES,
+                indices as _,
+                device.index_type,
+                (first_index * index_size) as *const _,
+                base_vertex
+            ))
+        }
+        .unwrap();
+    }
+
+    /// Issues `count` non-indexed draws whose parameters are read from
+    /// `commands` on the GPU.
+    pub fn multi_draw_indirect<const R: bool, const W: bool>(
+        &self,
+        commands: &Buffer<DrawCommand, R, W>,
+        count: usize,
+    ) {
+        let device = self.0.borrow();
+        assert!(count <= commands.len());
+
+        unsafe {
+            gl!(gl::BindVertexArray(device.vao)).unwrap();
+            gl!(gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, commands.id)).unwrap();
+
+            gl!(gl::MultiDrawArraysIndirect(
+                gl::TRIANGLES,
+                std::ptr::null(),
+                count as _,
+                0
+            ))
+            .unwrap();
+
+            gl!(gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0)).unwrap();
+        }
+    }
+
+    /// Issues `count` indexed draws whose parameters are read from `commands`
+    /// on the GPU, using the bound index buffer.
+    pub fn multi_draw_indexed_indirect<const R: bool, const W: bool>(
+        &self,
+        commands: &Buffer<DrawIndexedCommand, R, W>,
+        count: usize,
+    ) {
+        let device = self.0.borrow();
+        assert!(count <= commands.len());
+
+        unsafe {
+            gl!(gl::BindVertexArray(device.vao)).unwrap();
+            gl!(gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, commands.id)).unwrap();
+
+            gl!(gl::MultiDrawElementsIndirect(
+                gl::TRIANGLES,
+                device.index_type,
+                std::ptr::null(),
+                count as _,
+                0
+            ))
+            .unwrap();
+
+            gl!(gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0)).unwrap();
+        }
+    }
+
+    /// Runs the bound compute program in `groups` work groups. Everything it
+    /// writes is visible to the commands issued after it.
+    pub fn dispatch_compute(&self, groups: UVec3) {
+        let _device = self.0.borrow();
+        unsafe {
+            gl!(gl::DispatchCompute(groups.x, groups.y, groups.z)).unwrap();
+            gl!(gl::MemoryBarrier(gl::ALL_BARRIER_BITS)).unwrap();
+        }
+    }
+
     pub fn draw_instanced(&self, vertices: usize, instances: usize) {
         let device = self.0.borrow();
 
@@ -481,6 +927,27 @@ impl<'a> Device<'a> {
         }
     }
 
+    /// Like [`Device::draw_instanced`], but the instances are numbered from
+    /// `first_instance`, which shaders can read as `gl_BaseInstance`.
+    pub fn draw_instanced_from(&self, vertices: usize, instances: usize, first_instance: usize) {
+        let device = self.0.borrow();
+
+        unsafe {
+            gl!(gl::BindVertexArray(device.vao)).unwrap();
+
+            gl!(gl::DrawArraysInstancedBaseInstance(
+                gl::TRIANGLES,
+                0,
+                vertices as _,
+                instances as _,
+                first_instance as _
+            ))
+            .unwrap();
+
+            gl!(gl::BindVertexArray(0)).unwrap();
+        }
+    }
+
     pub fn draw_indexed_instanced(&self, indices: usize, instances: usize) {
         let device = self.0.borrow();
 
@@ -490,7 +957,7 @@ impl<'a> Device<'a> {
             gl!(gl::DrawElementsInstanced(
                 gl::TRIANGLES,
                 indices as _,
-                gl::UNSIGNED_INT,
+                device.index_type,
                 std::ptr::null(),
                 instances as _
             ))
@@ -498,36 +965,255 @@ impl<'a> Device<'a> {
         }
     }
 
-    pub fn blit(&self, src: (&Framebuffer, usize), dst: (&mut Framebuffer, usize), depth: bool) {
+    /// Enables or disables writes to the color and depth attachments of the
+    /// bound framebuffer, while depth testing stays enabled.
+    pub fn set_write_mask(&self, color: bool, depth: bool) {
         let _device = self.0.borrow();
+        let color = color as gl::types::GLboolean;
 
-        // If `src` is the default framebuffer we must query the size of the
-        // viewport using glGetIntegerV. The same holds true for `dst`.
-        //     let [src_width, src_height] = if let Some(texture) = &src.texture
-        // {         [texture.width as _, texture.height as _]
-        //     } else {
-        //         let mut buf = [0, 0, 0, 0];
-        //         unsafe { gl!(gl::GetIntegerv(gl::VIEWPORT, buf.as_mut_ptr()))
-        // }.unwrap();         [buf[2], buf[3]]
-        //     };
+        unsafe {
+            gl!(gl::ColorMask(color, color, color, color)).unwrap();
+            gl!(gl::DepthMask(depth as _)).unwrap();
+        }
+    }
 
-        //     let [dst_width, dst_height] = if let Some(texture) = &dst.texture
-        // {         [texture.width as _, texture.height as _]
-        //     } else {
-        //         let mut buf = [0, 0, 0, 0];
-        //         unsafe { gl!(gl::GetIntegerv(gl::VIEWPORT, buf.as_mut_ptr()))
-        // }.unwrap();         [buf[2], buf[3]]
-        //     };
+    /// Sets how fragments are blended with the framebuffer. `None` disables
+    /// blending, so fragments overwrite what was there.
+    pub fn set_blend(&self, mode: Option<BlendMode>) {
+        let mut device = self.0.borrow_mut();
+        device.blend = mode;
 
-        //     assert!(src_width <= dst_width);
-        //     assert!(src_height <= dst_height);
+        unsafe {
+            if let Some(mode) = mode {
+                let (src, dst) = mode.factors();
+                gl!(gl::Enable(gl::BLEND)).unwrap();
+                gl!(gl::BlendFunc(src, dst)).unwrap();
+            } else {
+                gl!(gl::Disable(gl::BLEND)).unwrap();
+            }
+        }
+    }
 
-        //     println!("src: {src_width},{src_height}");
-        //     println!("dst: {dst_width},{dst_height}");
+    /// The blend mode last set with [`Device::set_blend`].
+    pub fn blend(&self) -> Option<BlendMode> {
+        self.0.borrow().blend
+    }
 
-        //     unsafe {
-        //         gl!(gl::BlitNamedFramebuffer(
-        //             src.id,
+    /// Sets the comparison a fragment's depth must pass against the depth
+    /// buffer to be drawn. `None` disables depth testing, which also disables
+    /// writing to the depth buffer.
+    pub fn set_depth_test(&self, func: Option<DepthFunc>) {
+        let mut device = self.0.borrow_mut();
+        device.depth_test = func;
+
+        unsafe {
+            if let Some(func) = func {
+                gl!(gl::Enable(gl::DEPTH_TEST)).unwrap();
+                gl!(gl::DepthFunc(func.function())).unwrap();
+            } else {
+                gl!(gl::Disable(gl::DEPTH_TEST)).unwrap();
+            }
+        }
+    }
+
+    /// The depth test last set with [`Device::set_depth_test`].
+    pub fn depth_test(&self) -> Option<DepthFunc> {
+        self.0.borrow().depth_test
+    }
+
+    /// Sets how fragments are tested against and written to the stencil
+    /// buffer of the bound framebuffer. `None` disables stencil testing.
+    pub fn set_stencil_test(&self, test: Option<StencilTest>) {
+        let mut device = self.0.borrow_mut();
+        device.stencil_test = test;
+
+        unsafe {
+            match test {
+                Some(StencilTest::Write(reference)) => {
+                    gl!(gl::Enable(gl::STENCIL_TEST)).unwrap();
+                    gl!(gl::StencilFunc(gl::ALWAYS, reference as _, 0xff)).unwrap();
+                    gl!(gl::StencilOp(gl::KEEP, gl::KEEP, gl::REPLACE)).unwrap();
+                    gl!(gl::StencilMask(0xff)).unwrap();
+                }
+                Some(StencilTest::NotEqual(reference)) => {
+                    gl!(gl::Enable(gl::STENCIL_TEST)).unwrap();
+                    gl!(gl::StencilFunc(gl::NOTEQUAL, reference as _, 0xff)).unwrap();
+                    gl!(gl::StencilOp(gl::KEEP, gl::KEEP, gl::KEEP)).unwrap();
+                    gl!(gl::StencilMask(0)).unwrap();
+                }
+                // The mask is restored, since it applies to clearing the stencil
+                // buffer as well.
+                None => {
+                    gl!(gl::Disable(gl::STENCIL_TEST)).unwrap();
+                    gl!(gl::StencilMask(0xff)).unwrap();
+                }
+            }
+        }
+    }
+
+    /// The stencil test last set with [`Device::set_stencil_test`].
+    pub fn stencil_test(&self) -> Option<StencilTest> {
+        self.0.borrow().stencil_test
+    }
+
+    /// Maps rendering to the rectangle `(x, y, width, height)` in pixels of the
+    /// bound framebuffer, measured from the bottom left corner.
+    pub fn set_viewport(&self, rect: UVec4) {
+        let _device = self.0.borrow();
+
+        let [x, y, width, height] = rect.as_ivec4().to_array();
+        unsafe { gl!(gl::Viewport(x, y, width, height)) }.unwrap();
+    }
+
+    /// The rectangle last set with [`Device::set_viewport`], so a pass that
+    /// renders to a differently sized target can restore it afterwards.
+    pub fn viewport(&self) -> UVec4 {
+        let _device = self.0.borrow();
+
+        let mut viewport = [0; 4];
+        unsafe { gl!(gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr())) }.unwrap();
+        let [x, y, width, height] = viewport.map(|x| x as _);
+        uvec4(x, y, width, height)
+    }
+
+    /// Restricts drawing to the rectangle `(x, y, width, height)` in pixels,
+    /// measured from the bottom left corner. `None` draws to the whole
+    /// framebuffer again.
+    pub fn set_scissor(&self, rect: Option<(i32, i32, i32, i32)>) {
+        let _device = self.0.borrow();
+
+        unsafe {
+            if let Some((x, y, width, height)) = rect {
+                gl!(gl::Enable(gl::SCISSOR_TEST)).unwrap();
+                gl!(gl::Scissor(x, y, width, height)).unwrap();
+            } else {
+                gl!(gl::Disable(gl::SCISSOR_TEST)).unwrap();
+            }
+        }
+    }
+
+    /// Sets the width in pixels of lines, clamped to the widths supported by
+    /// the driver.
+    pub fn set_line_width(&self, width: f32) {
+        let _device = self.0.borrow();
+
+        let [min, max] = Self::float_range(gl::ALIASED_LINE_WIDTH_RANGE);
+        unsafe { gl!(gl::LineWidth(width.clamp(min, max))) }.unwrap();
+    }
+
+    /// Sets the size in pixels of points, clamped to the sizes supported by the
+    /// driver. `None` lets the vertex shader decide by writing `gl_PointSize`.
+    pub fn set_point_size(&self, size: Option<f32>) {
+        let _device = self.0.borrow();
+
+        unsafe {
+            if let Some(size) = size {
+                let [min, max] = Self::float_range(gl::POINT_SIZE_RANGE);
+                gl!(gl::Disable(gl::PROGRAM_POINT_SIZE)).unwrap();
+                gl!(gl::PointSize(size.clamp(min, max))).unwrap();
+            } else {
+                gl!(gl::Enable(gl::PROGRAM_POINT_SIZE)).unwrap();
+            }
+        }
+    }
+
+    fn float_range(name: gl::types::GLenum) -> [f32; 2] {
+        let mut range = [0.0; 2];
+        unsafe { gl!(gl::GetFloatv(name, range.as_mut_ptr())) }.unwrap();
+        range
+    }
+
+    /// Starts counting `kind` for the draws issued until [`Device::end_query`].
+    /// Only one query of each kind can be active at a time.
+    pub fn begin_query(&self, kind: QueryKind) -> Query {
+        let _device = self.0.borrow();
+
+        let mut id = 0;
+        unsafe {
+            gl!(gl::CreateQueries(kind.target(), 1, &mut id)).unwrap();
+            gl!(gl::BeginQuery(kind.target(), id)).unwrap();
+        }
+
+        Query {
+            id,
+            kind,
+            _device: Rc::clone(&self.0),
+        }
+    }
+
+    /// Begins `query` again, which replaces its result once it has ended. The
+    /// query should only be restarted once its last result is available.
+    pub fn restart_query(&self, query: &Query) {
+        let _device = self.0.borrow();
+        unsafe { gl!(gl::BeginQuery(query.kind.target(), query.id)) }.unwrap();
+    }
+
+    pub fn end_query(&self, query: &Query) {
+        let _device = self.0.borrow();
+        unsafe { gl!(gl::EndQuery(query.kind.target())) }.unwrap();
+    }
+
+    /// Returns the result of an ended query, or `None` if the GPU hasn't
+    /// finished the draws of the query yet. This never stalls.
+    pub fn query_result(&self, query: &Query) -> Option<u64> {
+        let _device = self.0.borrow();
+
+        let mut available = 0;
+        unsafe {
+            gl!(gl::GetQueryObjectiv(
+                query.id,
+                gl::QUERY_RESULT_AVAILABLE,
+                &mut available
+            ))
+            .unwrap()
+        };
+
+        if available == gl::FALSE as i32 {
+            return None;
+        }
+
+        let mut result = 0;
+        unsafe {
+            gl!(gl::GetQueryObjectui64v(
+                query.id,
+                gl::QUERY_RESULT,
+                &mut result
+            ))
+        }
+        .unwrap();
+        Some(result)
+    }
+
+    pub fn blit(&self, src: (&Framebuffer, usize), dst: (&mut Framebuffer, usize), depth: bool) {
+        let _device = self.0.borrow();
+
+        // If `src` is the default framebuffer we must query the size of the
+        // viewport using glGetIntegerV. The same holds true for `dst`.
+        //     let [src_width, src_height] = if let Some(texture) = &src.texture
+        // {         [texture.width as _, texture.height as _]
+        //     } else {
+        //         let mut buf = [0, 0, 0, 0];
+        //         unsafe { gl!(gl::GetIntegerv(gl::VIEWPORT, buf.as_mut_ptr()))
+        // }.unwrap();         [buf[2], buf[3]]
+        //     };
+
+        //     let [dst_width, dst_height] = if let Some(texture) = &dst.texture
+        // {         [texture.width as _, texture.height as _]
+        //     } else {
+        //         let mut buf = [0, 0, 0, 0];
+        //         unsafe { gl!(gl::GetIntegerv(gl::VIEWPORT, buf.as_mut_ptr()))
+        // }.unwrap();         [buf[2], buf[3]]
+        //     };
+
+        //     assert!(src_width <= dst_width);
+        //     assert!(src_height <= dst_height);
+
+        //     println!("src: {src_width},{src_height}");
+        //     println!("dst: {dst_width},{dst_height}");
+
+        //     unsafe {
+        //         gl!(gl::BlitNamedFramebuffer(
+        //             src.id,
         //             dst.id,
         //             0,
         //             0,
@@ -543,12 +1229,50 @@ impl<'a> Device<'a> {
         //     }
         //     .unwrap();
     }
+
+    /// Copies `len` elements starting at element `src_offset` of `src` to
+    /// element `dst_offset` of `dst`, without the data leaving the GPU. `dst`
+    /// grows to include the copied elements if they lie past its end.
+    pub fn copy_buffer<T, const R1: bool, const W1: bool, const R2: bool, const W2: bool>(
+        &self,
+        src: &Buffer<T, R1, W1>,
+        dst: &mut Buffer<T, R2, W2>,
+        src_offset: usize,
+        dst_offset: usize,
+        len: usize,
+    ) where
+        T: BufferLayout,
+    {
+        let _device = self.0.borrow();
+        assert!(
+            src_offset + len <= src.len,
+            "copy past the end of the source"
+        );
+        assert!(
+            dst_offset + len <= dst.capacity,
+            "copy past the capacity of the destination"
+        );
+
+        let stride = T::stride();
+        unsafe {
+            gl!(gl::CopyNamedBufferSubData(
+                src.id,
+                dst.id,
+                (src_offset * stride) as _,
+                (dst_offset * stride) as _,
+                (len * stride) as _
+            ))
+        }
+        .unwrap();
+        dst.len = dst.len.max(dst_offset + len);
+    }
 }
 
 pub struct Texture2D {
     pub id: u32,
     width: usize,
     height: usize,
+    levels: usize,
     format: Format,
     _device: Rc<RefCell<DeviceShared>>,
 }
@@ -576,6 +1300,12 @@ impl Texture2D {
         }
     }
 
+    /// Recomputes every level below level 0 from level 0, e.g. after a
+    /// [`Texture2D::write`].
+    pub fn generate_mipmaps(&self) {
+        unsafe { gl!(gl::GenerateTextureMipmap(self.id)) }.unwrap();
+    }
+
     pub fn width(&self) -> usize {
         self.width
     }
@@ -584,6 +1314,17 @@ impl Texture2D {
         self.height
     }
 
+    /// The number of mip levels, including the full size level 0.
+    pub fn levels(&self) -> usize {
+        self.levels
+    }
+
+    /// The size in texels of mip `level`.
+    pub fn level_size(&self, level: usize) -> UVec2 {
+        let size = uvec2(self.width as _, self.height as _);
+        (size >> level as u32).max(UVec2::ONE)
+    }
+
     pub fn format(&self) -> Format {
         self.format
     }
@@ -595,6 +1336,141 @@ impl Drop for Texture2D {
     }
 }
 
+pub struct TextureCube {
+    pub id: u32,
+    size: usize,
+    format: Format,
+    _device: Rc<RefCell<DeviceShared>>,
+}
+
+impl TextureCube {
+    /// Writes RGBA8 texels to one face, in the order +X, -X, +Y, -Y, +Z, -Z.
+    pub fn write_face(&mut self, face: usize, bytes: &[u8]) {
+        assert!(face < 6);
+        assert_eq!(
+            bytes.len(),
+            self.size * self.size * std::mem::size_of::<u8>() * 4
+        );
+
+        unsafe {
+            gl!(gl::TextureSubImage3D(
+                self.id,
+                0,
+                0,
+                0,
+                face as _,
+                self.size as _,
+                self.size as _,
+                1,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                bytes.as_ptr() as *const _
+            ))
+            .unwrap()
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+}
+
+impl Drop for TextureCube {
+    fn drop(&mut self) {
+        unsafe { gl!(gl::DeleteTextures(1, &mut self.id)).unwrap() };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Blends by the alpha of the fragment, for regular transparency.
+    Alpha,
+    /// Adds the fragment to the framebuffer, for glow and particles.
+    Additive,
+    /// Like [`BlendMode::Alpha`] for colors already multiplied by their alpha.
+    Premultiplied,
+}
+
+impl BlendMode {
+    fn factors(&self) -> (gl::types::GLenum, gl::types::GLenum) {
+        match self {
+            Self::Alpha => (gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA),
+            Self::Additive => (gl::ONE, gl::ONE),
+            Self::Premultiplied => (gl::ONE, gl::ONE_MINUS_SRC_ALPHA),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthFunc {
+    Less,
+    LessEqual,
+    Equal,
+    Always,
+}
+
+impl DepthFunc {
+    fn function(&self) -> gl::types::GLenum {
+        match self {
+            Self::Less => gl::LESS,
+            Self::LessEqual => gl::LEQUAL,
+            Self::Equal => gl::EQUAL,
+            Self::Always => gl::ALWAYS,
+        }
+    }
+}
+
+/// How fragments are tested against the stencil buffer, and what they write
+/// to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StencilTest {
+    /// Every fragment passes and writes the reference value.
+    Write(u8),
+    /// Only fragments where the stencil buffer doesn't hold the reference
+    /// value pass, and the stencil buffer is left as it is.
+    NotEqual(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryKind {
+    /// The number of samples which passed the depth test.
+    SamplesPassed,
+    /// Whether any sample passed the depth test, which may finish sooner than
+    /// counting every sample.
+    AnySamplesPassed,
+}
+
+impl QueryKind {
+    fn target(&self) -> gl::types::GLenum {
+        match self {
+            Self::SamplesPassed => gl::SAMPLES_PASSED,
+            Self::AnySamplesPassed => gl::ANY_SAMPLES_PASSED,
+        }
+    }
+}
+
+pub struct Query {
+    id: u32,
+    kind: QueryKind,
+    _device: Rc<RefCell<DeviceShared>>,
+}
+
+impl Query {
+    pub fn kind(&self) -> QueryKind {
+        self.kind
+    }
+}
+
+impl Drop for Query {
+    fn drop(&mut self) {
+        let _ = unsafe { gl!(gl::DeleteQueries(1, &self.id)) };
+    }
+}
+
 pub struct BindProps<'a, T: BufferLayout, const R: bool, const W: bool> {
     pub binding: usize,
     pub attributes: &'a [&'static str],
@@ -622,7 +1498,10 @@ pub struct Framebuffer {
     pub id: u32,
     textures: Vec<Option<Texture2D>>,
     depth: Option<Texture2D>,
+    /// The mip level of the attachments which is rendered to.
+    level: usize,
     default: bool,
+    _device: Rc<RefCell<DeviceShared>>,
 }
 
 impl Framebuffer {
@@ -668,6 +1547,69 @@ impl Framebuffer {
         }
     }
 
+    /// Sets every value of the stencil buffer to zero.
+    pub fn clear_stencil(&mut self) {
+        unsafe { gl!(gl::ClearNamedFramebufferiv(self.id, gl::STENCIL, 0, &0)) }.unwrap();
+    }
+
+    /// The number of color attachments, not counting the depth attachment.
+    pub fn attachment_count(&self) -> usize {
+        self.textures.iter().flatten().count()
+    }
+
+    pub fn has_depth(&self) -> bool {
+        self.depth.is_some()
+    }
+
+    /// The size in pixels of the rendered mip level of the first attachment.
+    /// The default framebuffer has no attachments, so its size is taken from
+    /// the viewport instead.
+    pub fn size(&self) -> UVec2 {
+        if self.default {
+            let mut viewport = [0; 4];
+            unsafe { gl!(gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr())) }.unwrap();
+            return uvec2(viewport[2] as _, viewport[3] as _);
+        }
+
+        let texture = self
+            .textures
+            .iter()
+            .flatten()
+            .chain(&self.depth)
+            .next()
+            .expect("A framebuffer must have at least one attachment");
+        texture.level_size(self.level)
+    }
+
+    /// Reads the first color attachment as RGBA8 pixels, starting with the
+    /// bottom row. The default framebuffer is read from its back buffer, which
+    /// holds the frame being drawn until it is presented.
+    pub fn read(&self) -> Vec<u8> {
+        let size = self.size();
+        let source = match self.default {
+            true => gl::BACK,
+            false => gl::COLOR_ATTACHMENT0,
+        };
+
+        let mut pixels = vec![0; size.x as usize * size.y as usize * 4];
+        unsafe {
+            gl!(gl::NamedFramebufferReadBuffer(self.id, source)).unwrap();
+            gl!(gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.id)).unwrap();
+            gl!(gl::PixelStorei(gl::PACK_ALIGNMENT, 1)).unwrap();
+            gl!(gl::ReadPixels(
+                0,
+                0,
+                size.x as _,
+                size.y as _,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_mut_ptr() as *mut _
+            ))
+            .unwrap();
+        }
+        pixels
+    }
+
     pub fn color(&self, index: usize) -> &Texture2D {
         let Self { id, textures, .. } = self;
         assert!(
@@ -852,12 +1794,78 @@ generate_layouts!([
     Vec2 => Vec2,
     Vec3 => Vec3,
     Vec4 => Vec4,
+    Quat => Vec4,
     Mat3 => Mat3,
     Mat4 => Mat4,
     u32 => U32,
     u16 => U16
 ]);
 
+/// The parameters of a single draw in [`Device::multi_draw_indirect`].
+///
+/// The layout matches `DrawArraysIndirectCommand` from the OpenGL spec.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DrawCommand {
+    pub count: u32,
+    pub instance_count: u32,
+    pub first: u32,
+    pub base_instance: u32,
+}
+
+unsafe impl BufferLayout for DrawCommand {
+    const LAYOUT: &'static [Format] = &[Format::U32, Format::U32, Format::U32, Format::U32];
+    const PADDING: &'static [usize] = &[0, 0, 0, 0];
+    const COPYABLE: bool = true;
+
+    fn to_bytes(_items: &[Self]) -> Vec<u8> {
+        unimplemented!()
+    }
+}
+
+/// The parameters of a single draw in [`Device::multi_draw_indexed_indirect`].
+///
+/// The layout matches `DrawElementsIndirectCommand` from the OpenGL spec.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DrawIndexedCommand {
+    pub count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub base_instance: u32,
+}
+
+unsafe impl BufferLayout for DrawIndexedCommand {
+    // `base_vertex` is signed, but it has the same size as an U32.
+    const LAYOUT: &'static [Format] = &[
+        Format::U32,
+        Format::U32,
+        Format::U32,
+        Format::U32,
+        Format::U32,
+    ];
+    const PADDING: &'static [usize] = &[0, 0, 0, 0, 0];
+    const COPYABLE: bool = true;
+
+    fn to_bytes(_items: &[Self]) -> Vec<u8> {
+        unimplemented!()
+    }
+}
+
+/// Types that can be stored in an index buffer.
+pub trait IndexType: BufferLayout {
+    const INDEX_TYPE: gl::types::GLenum;
+}
+
+impl IndexType for u16 {
+    const INDEX_TYPE: gl::types::GLenum = gl::UNSIGNED_SHORT;
+}
+
+impl IndexType for u32 {
+    const INDEX_TYPE: gl::types::GLenum = gl::UNSIGNED_INT;
+}
+
 pub enum BufferInit<'a, T: BufferLayout> {
     Data(&'a [T]),
     Capacity(usize),
@@ -883,22 +1891,238 @@ impl<T: BufferLayout, const R: bool, const W: bool> Buffer<T, R, W> {
     }
 }
 
-impl<T: BufferLayout, const R: bool, const W: bool> Drop for Buffer<T, R, W> {
-    fn drop(&mut self) {
-        unsafe { gl::DeleteBuffers(1, &self.id) }
-    }
-}
+impl<T: BufferLayout, const R: bool, const W: bool> Buffer<T, R, W> {
+    /// Maps the `len` elements starting at element `offset`. OpenGL can't map
+    /// an empty range, so nothing is mapped for one.
+    fn map_range(&self, offset: usize, len: usize, access: gl::types::GLenum) -> *mut c_void {
+        if len == 0 {
+            return std::ptr::null_mut();
+        }
+
+        let stride = T::stride();
+        unsafe {
+            gl!(gl::MapNamedBufferRange(
+                self.id,
+                (offset * stride) as _,
+                (len * stride) as _,
+                access
+            ))
+        }
+        .unwrap()
+    }
+
+    /// Unmaps a range mapped with [`Buffer::map_range`].
+    fn unmap_range(&self, mapped: *mut c_void) {
+        if !mapped.is_null() {
+            unsafe { gl!(gl::UnmapNamedBuffer(self.id)) }.unwrap();
+        }
+    }
+}
+
+impl<T: BufferLayout, const R: bool, const W: bool> Drop for Buffer<T, R, W> {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteBuffers(1, &self.id) }
+    }
+}
 
 impl<T: BufferLayout, const W: bool> Buffer<T, true, W> {
     pub fn map_read(&self) -> MapRead<T, W> {
         MapRead(self)
     }
+
+    /// Maps the `len` elements starting at element `offset` for reading,
+    /// without mapping the rest of the buffer.
+    pub fn map_read_range(&self, offset: usize, len: usize) -> MapReadRange<T, W> {
+        assert!(
+            offset + len <= self.len,
+            "mapped past the end of the buffer"
+        );
+
+        let mapped = self.map_range(offset, len, gl::MAP_READ_BIT);
+        MapReadRange {
+            buffer: self,
+            mapped,
+            len,
+        }
+    }
+
+    /// Copies `len` elements starting at element `offset` back from the GPU,
+    /// without mapping the rest of the buffer.
+    pub fn read_range(&self, offset: usize, len: usize) -> Vec<T>
+    where
+        T: Default + Clone,
+    {
+        assert!(offset + len <= self.len, "read past the end of the buffer");
+        assert!(T::COPYABLE, "only copyable elements can be read back");
+
+        let size = std::mem::size_of::<T>();
+        let mut storage = vec![T::default(); len];
+        unsafe {
+            gl!(gl::GetNamedBufferSubData(
+                self.id,
+                (offset * size) as _,
+                (len * size) as _,
+                storage.as_mut_ptr() as *mut _
+            ))
+        }
+        .unwrap();
+        storage
+    }
 }
 
 impl<T: BufferLayout, const R: bool> Buffer<T, R, true> {
     pub fn map_write(&mut self) -> MapWrite<T, R> {
         MapWrite(self)
     }
+
+    /// Maps the `len` elements starting at element `offset` for writing,
+    /// without mapping the rest of the buffer. The previous contents of the
+    /// range are discarded.
+    ///
+    /// # Panics
+    /// If the range starts after the elements written so far, since the gap
+    /// before it would become part of the buffer without being initialized.
+    pub fn map_write_range(&mut self, offset: usize, len: usize) -> MapWriteRange<T, R> {
+        assert!(
+            offset + len <= self.capacity,
+            "mapped past the end of the buffer"
+        );
+        assert!(
+            offset <= self.len,
+            "mapped past the elements written to the buffer"
+        );
+
+        let flags = gl::MAP_WRITE_BIT | gl::MAP_INVALIDATE_RANGE_BIT;
+        let mapped = self.map_range(offset, len, flags);
+        MapWriteRange {
+            buffer: self,
+            mapped,
+            offset,
+            len,
+        }
+    }
+
+    /// Sets every element the buffer has capacity for to `value`, without
+    /// mapping the buffer.
+    ///
+    /// # Panics
+    /// If the stride of `T` is not 2, 4, 8, 12 or 16 bytes, since OpenGL can
+    /// only clear buffers with values the size of a texel.
+    pub fn clear(&mut self, value: T) {
+        // The value is repeated as raw bits, so unsigned formats of the same
+        // size work for any element type.
+        let (internal_format, format, ty) = match T::stride() {
+            2 => (gl::R16UI, gl::RED_INTEGER, gl::UNSIGNED_SHORT),
+            4 => (gl::R32UI, gl::RED_INTEGER, gl::UNSIGNED_INT),
+            8 => (gl::RG32UI, gl::RG_INTEGER, gl::UNSIGNED_INT),
+            12 => (gl::RGB32UI, gl::RGB_INTEGER, gl::UNSIGNED_INT),
+            16 => (gl::RGBA32UI, gl::RGBA_INTEGER, gl::UNSIGNED_INT),
+            stride => panic!("can't clear a buffer with a stride of {stride} bytes"),
+        };
+
+        let bytes;
+        let data = if T::COPYABLE {
+            &value as *const T as *const _
+        } else {
+            bytes = T::to_bytes(std::slice::from_ref(&value));
+            bytes.as_ptr() as *const _
+        };
+
+        unsafe {
+            gl!(gl::ClearNamedBufferData(
+                self.id,
+                internal_format,
+                format,
+                ty,
+                data
+            ))
+        }
+        .unwrap();
+        self.len = self.capacity;
+    }
+}
+
+/// `N` uniform buffers used round-robin, so the buffer written this frame is
+/// not one the GPU may still be reading from a previous frame, which would
+/// stall the write until the GPU is done.
+pub struct RingBuffer<T: BufferLayout, const N: usize> {
+    buffers: [UniformBuffer<T>; N],
+    current: usize,
+}
+
+impl<T: BufferLayout, const N: usize> RingBuffer<T, N> {
+    /// Moves on to the buffer that was used the longest time ago and returns
+    /// it, which should be done once per frame before writing.
+    pub fn next(&mut self) -> &mut UniformBuffer<T> {
+        self.current = (self.current + 1) % N;
+        &mut self.buffers[self.current]
+    }
+
+    /// The buffer returned by the last call to [`RingBuffer::next`].
+    pub fn current(&self) -> &UniformBuffer<T> {
+        &self.buffers[self.current]
+    }
+}
+
+/// A writable buffer for the uniform block `block`, which remembers the
+/// binding point the block is declared at so it can't be bound anywhere else.
+pub struct UniformBuffer<T: BufferLayout> {
+    buffer: Buffer<T, false, true>,
+    block: &'static str,
+    binding: usize,
+}
+
+impl<T: BufferLayout> UniformBuffer<T> {
+    pub fn map_write(&mut self) -> MapWrite<T, false> {
+        self.buffer.map_write()
+    }
+
+    /// Binds the buffer at its binding point. In debug builds this panics if
+    /// the bound shader program declares the block at another binding.
+    pub fn bind(&self) {
+        #[cfg(debug_assertions)]
+        self.check_binding();
+
+        unsafe {
+            gl!(gl::BindBufferBase(
+                gl::UNIFORM_BUFFER,
+                self.binding as _,
+                self.buffer.id
+            ))
+        }
+        .unwrap();
+    }
+
+    #[cfg(debug_assertions)]
+    fn check_binding(&self) {
+        let program = self.buffer._device.borrow().program;
+        if program == 0 {
+            return;
+        }
+
+        let name = CString::new(self.block).unwrap();
+        let index = unsafe { gl!(gl::GetUniformBlockIndex(program, name.as_ptr())) }.unwrap();
+        // Programs without the block, or which optimized it away, can't disagree.
+        if index == gl::INVALID_INDEX {
+            return;
+        }
+
+        let mut binding = 0;
+        unsafe {
+            gl!(gl::GetActiveUniformBlockiv(
+                program,
+                index,
+                gl::UNIFORM_BLOCK_BINDING,
+                &mut binding
+            ))
+        }
+        .unwrap();
+        assert_eq!(
+            binding as usize, self.binding,
+            "uniform block `{}` is declared at binding {binding}, but bound at {}",
+            self.block, self.binding
+        );
+    }
 }
 
 pub struct MapRead<'a, T: BufferLayout, const W: bool>(&'a Buffer<T, true, W>);
@@ -948,10 +2172,70 @@ impl<'a, T: BufferLayout, const R: bool> Drop for MapWrite<'a, T, R> {
     }
 }
 
+pub struct MapReadRange<'a, T: BufferLayout, const W: bool> {
+    buffer: &'a Buffer<T, true, W>,
+    mapped: *mut c_void,
+    len: usize,
+}
+
+impl<'a, T: BufferLayout + Default + Clone, const W: bool> MapReadRange<'a, T, W> {
+    pub fn read(&self) -> Vec<T> {
+        assert!(T::COPYABLE, "only copyable elements can be read back");
+
+        let mut storage = vec![T::default(); self.len];
+        if self.len != 0 {
+            unsafe { std::ptr::copy(self.mapped as *const _, storage.as_mut_ptr(), self.len) };
+        }
+        storage
+    }
+}
+
+impl<'a, T: BufferLayout, const W: bool> Drop for MapReadRange<'a, T, W> {
+    fn drop(&mut self) {
+        self.buffer.unmap_range(self.mapped);
+    }
+}
+
+pub struct MapWriteRange<'a, T: BufferLayout, const R: bool> {
+    buffer: &'a mut Buffer<T, R, true>,
+    mapped: *mut c_void,
+    offset: usize,
+    len: usize,
+}
+
+impl<'a, T: BufferLayout, const R: bool> MapWriteRange<'a, T, R> {
+    /// Overwrites the mapped range with `items`, which must fill it exactly.
+    pub fn write(&mut self, items: &[T]) {
+        assert_eq!(
+            items.len(),
+            self.len,
+            "the items must fill the mapped range"
+        );
+
+        let buffer = &mut self.buffer;
+        buffer.len = buffer.len.max(self.offset + self.len);
+
+        if T::COPYABLE {
+            let count = items.len() * std::mem::size_of::<T>();
+            unsafe { std::ptr::copy(items.as_ptr() as *const u8, self.mapped as *mut u8, count) };
+        } else {
+            let bytes = T::to_bytes(items);
+            unsafe { std::ptr::copy(bytes.as_ptr(), self.mapped as *mut u8, bytes.len()) };
+        }
+    }
+}
+
+impl<'a, T: BufferLayout, const R: bool> Drop for MapWriteRange<'a, T, R> {
+    fn drop(&mut self) {
+        self.buffer.unmap_range(self.mapped);
+    }
+}
+
 pub enum StageType {
     Vertex,
     Geometry,
     Pixel,
+    Compute,
 }
 
 pub trait Stage {
@@ -973,6 +2257,11 @@ impl Stage for PixelStage {
     const STAGE_TYPE: StageType = StageType::Pixel;
 }
 
+pub struct ComputeStage;
+impl Stage for ComputeStage {
+    const STAGE_TYPE: StageType = StageType::Compute;
+}
+
 pub struct ShaderShared<S: Stage> {
     id: u32,
     _device: Rc<RefCell<DeviceShared>>,
@@ -990,9 +2279,11 @@ pub struct Shader<S: Stage>(Rc<ShaderShared<S>>);
 pub type VertexShader = Shader<VertexStage>;
 pub type GeometryShader = Shader<GeometryStage>;
 pub type PixelShader = Shader<PixelStage>;
+pub type ComputeShader = Shader<ComputeStage>;
 
 pub struct ShaderProgram {
     pub id: u32,
+    _device: Rc<RefCell<DeviceShared>>,
 }
 
 impl Drop for ShaderProgram {
@@ -1000,3 +2291,688 @@ impl Drop for ShaderProgram {
         let _ = unsafe { gl!(gl::DeleteProgram(self.id)) };
     }
 }
+
+#[test]
+fn capabilities() {
+    let capabilities = Capabilities::probe();
+    assert!(capabilities.probed.contains(&"glCreateBuffers"));
+    assert!(capabilities.probed.contains(&"glNamedBufferStorage"));
+    assert!(capabilities.probed.contains(&"glVertexArrayVertexBuffer"));
+    assert!(capabilities
+        .missing
+        .iter()
+        .all(|name| capabilities.probed.contains(name)));
+}
+
+#[cfg(test)]
+pub(crate) fn headless() -> (sdl2::Sdl, Window, Instance) {
+    let sdl = sdl2::init().unwrap();
+    let video_subsystem = sdl.video().unwrap();
+    video_subsystem.gl_attr().set_context_version(4, 6);
+    video_subsystem.gl_attr().set_stencil_size(8);
+    video_subsystem
+        .gl_attr()
+        .set_context_profile(GLProfile::Core);
+
+    let window = video_subsystem
+        .window("", 1, 1)
+        .hidden()
+        .opengl()
+        .build()
+        .unwrap();
+
+    let instance = Instance::new(&window, false).unwrap();
+    (sdl, window, instance)
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn draw_indexed_u16() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let indices: Buffer<u16> = device.new_buffer(BufferInit::Data(&[0, 1, 2, 2, 3, 0]));
+    device.bind_index_buffer(&indices);
+    device.draw_indexed(indices.len());
+
+    assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn clear_buffer() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let mut buffer: Buffer<u32, true, true> = device.new_buffer(BufferInit::Data(&[1, 2, 3, 4]));
+    buffer.clear(0xdeadbeef);
+
+    assert_eq!(buffer.map_read().read(), vec![0xdeadbeef; 4]);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn ring_buffer() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let mut ring: RingBuffer<Mat4, 3> = device.new_ring_buffer("Matrices", 0, 1);
+    let mut bound = Vec::new();
+    for frame in 0..6 {
+        ring.next()
+            .map_write()
+            .write(&[Mat4::from_scale(Vec3::splat(frame as f32))]);
+        ring.current().bind();
+
+        let mut id = 0;
+        unsafe { gl::GetIntegeri_v(gl::UNIFORM_BUFFER_BINDING, 0, &mut id) };
+        bound.push(id as u32);
+    }
+
+    assert_eq!(bound[..3], bound[3..]);
+    assert!(bound[0] != bound[1] && bound[1] != bound[2] && bound[0] != bound[2]);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn draw_indexed_range() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    // Two quads sharing one index buffer, where the second quad is drawn from
+    // the second half of the indices offset by four vertices.
+    let indices: Buffer<u32> =
+        device.new_buffer(BufferInit::Data(&[0, 1, 2, 2, 3, 0, 0, 1, 2, 2, 3, 0]));
+    device.bind_index_buffer(&indices);
+    device.draw_indexed_range(6, 6, 4);
+
+    assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn multi_draw_indirect() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let vs = device
+        .new_shader(
+            VertexStage,
+            "#version 460 core\nvoid main() { gl_Position = vec4(0.0); }",
+        )
+        .unwrap();
+    let ps = device
+        .new_shader(
+            PixelStage,
+            "#version 460 core\nout vec4 color;\nvoid main() { color = vec4(1.0); }",
+        )
+        .unwrap();
+    let program = device.new_shader_program(&vs, &ps).unwrap();
+    device.bind_shader_program(&program);
+
+    let commands: Buffer<DrawCommand> = device.new_buffer(BufferInit::Data(&[
+        DrawCommand {
+            count: 3,
+            instance_count: 2,
+            first: 0,
+            base_instance: 0,
+        },
+        DrawCommand {
+            count: 6,
+            instance_count: 1,
+            first: 0,
+            base_instance: 0,
+        },
+    ]));
+
+    let mut query = 0;
+    unsafe {
+        gl::CreateQueries(gl::PRIMITIVES_GENERATED, 1, &mut query);
+        gl::BeginQuery(gl::PRIMITIVES_GENERATED, query);
+    }
+
+    device.multi_draw_indirect(&commands, commands.len());
+
+    let mut primitives = 0;
+    unsafe {
+        gl::EndQuery(gl::PRIMITIVES_GENERATED);
+        gl::GetQueryObjectuiv(query, gl::QUERY_RESULT, &mut primitives);
+        gl::DeleteQueries(1, &query);
+    }
+
+    // Two instances of one triangle and one instance of two triangles.
+    assert_eq!(primitives, 4);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn texture_cube() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let vs = device
+        .new_shader(
+            VertexStage,
+            "#version 460 core\nvoid main() { gl_Position = vec4(0.0); }",
+        )
+        .unwrap();
+    let ps = device
+        .new_shader(
+            PixelStage,
+            "#version 460 core\nuniform samplerCube environment;\nout vec4 color;\nvoid main() { \
+         color = texture(environment, vec3(1.0)); }",
+        )
+        .unwrap();
+    let program = device.new_shader_program(&vs, &ps).unwrap();
+    device.bind_shader_program(&program);
+
+    let mut cubemap = device.new_texture_cube(4, Format::R8G8B8A8).unwrap();
+    for face in 0..6 {
+        let image = image::RgbaImage::from_pixel(4, 4, image::Rgba([face as u8 * 40, 0, 0, 255]));
+        cubemap.write_face(face, image.as_raw());
+    }
+
+    device.bind_texture_cube(&cubemap, "environment", 0);
+    assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn impossible_context_version() {
+    let sdl = sdl2::init().unwrap();
+    let video_subsystem = sdl.video().unwrap();
+    video_subsystem.gl_attr().set_context_version(9, 9);
+    video_subsystem
+        .gl_attr()
+        .set_context_profile(GLProfile::Core);
+
+    let window = video_subsystem
+        .window("", 1, 1)
+        .hidden()
+        .opengl()
+        .build()
+        .unwrap();
+
+    let result = Instance::new(&window, false);
+    assert!(matches!(
+        result,
+        Err(RhiError::ContextCreation {
+            version: (9, 9),
+            ..
+        })
+    ));
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn occlusion_query() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    // Every vertex is placed outside of the clip volume, so the triangle
+    // is clipped away and no samples can be written.
+    let vs = device
+        .new_shader(
+            VertexStage,
+            "#version 460 core\nvoid main() { gl_Position = vec4(2.0, 2.0, 2.0, 1.0); }",
+        )
+        .unwrap();
+    let ps = device
+        .new_shader(
+            PixelStage,
+            "#version 460 core\nout vec4 color;\nvoid main() { color = vec4(1.0); }",
+        )
+        .unwrap();
+    let program = device.new_shader_program(&vs, &ps).unwrap();
+    device.bind_shader_program(&program);
+
+    let query = device.begin_query(QueryKind::SamplesPassed);
+    device.draw(3);
+    device.end_query(&query);
+
+    let wait = |query: &Query| loop {
+        if let Some(samples) = device.query_result(query) {
+            break samples;
+        }
+    };
+    assert_eq!(wait(&query), 0);
+
+    // A query is reused by beginning it again once its result is back.
+    device.restart_query(&query);
+    device.draw(3);
+    device.end_query(&query);
+    assert_eq!(wait(&query), 0);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn scissor() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    device.set_scissor(Some((0, 0, 1, 1)));
+    assert_eq!(unsafe { gl::IsEnabled(gl::SCISSOR_TEST) }, gl::TRUE);
+
+    device.set_scissor(None);
+    assert_eq!(unsafe { gl::IsEnabled(gl::SCISSOR_TEST) }, gl::FALSE);
+    assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn line_width_and_point_size() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let get = |name| {
+        let mut value = 0.0;
+        unsafe { gl::GetFloatv(name, &mut value) };
+        value
+    };
+
+    // Every driver supports lines and points of one pixel.
+    device.set_line_width(1.0);
+    assert_eq!(get(gl::LINE_WIDTH), 1.0);
+    device.set_point_size(Some(1.0));
+    assert_eq!(get(gl::POINT_SIZE), 1.0);
+
+    // Sizes outside the supported range are clamped instead of failing.
+    device.set_line_width(f32::MAX);
+    device.set_point_size(Some(0.0));
+    assert!(get(gl::POINT_SIZE) > 0.0);
+
+    device.set_point_size(None);
+    assert_eq!(unsafe { gl::IsEnabled(gl::PROGRAM_POINT_SIZE) }, gl::TRUE);
+    assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn blend_modes() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let blend_func = || {
+        let (mut src, mut dst) = (0, 0);
+        unsafe {
+            gl::GetIntegerv(gl::BLEND_SRC_RGB, &mut src);
+            gl::GetIntegerv(gl::BLEND_DST_RGB, &mut dst);
+        }
+        (src as gl::types::GLenum, dst as gl::types::GLenum)
+    };
+
+    for mode in [
+        BlendMode::Alpha,
+        BlendMode::Additive,
+        BlendMode::Premultiplied,
+    ] {
+        device.set_blend(Some(mode));
+        assert_eq!(unsafe { gl::IsEnabled(gl::BLEND) }, gl::TRUE);
+        assert_eq!(blend_func(), mode.factors());
+    }
+
+    device.set_blend(None);
+    assert_eq!(unsafe { gl::IsEnabled(gl::BLEND) }, gl::FALSE);
+    assert_eq!(device.blend(), None);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn depth_test() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+    assert_eq!(device.depth_test(), Some(DepthFunc::LessEqual));
+
+    device.set_depth_test(None);
+    assert_eq!(unsafe { gl::IsEnabled(gl::DEPTH_TEST) }, gl::FALSE);
+
+    device.set_depth_test(Some(DepthFunc::Less));
+    assert_eq!(unsafe { gl::IsEnabled(gl::DEPTH_TEST) }, gl::TRUE);
+
+    let mut func = 0;
+    unsafe { gl::GetIntegerv(gl::DEPTH_FUNC, &mut func) };
+    assert_eq!(func as gl::types::GLenum, gl::LESS);
+    assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn stencil_test() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+    assert_eq!(device.stencil_test(), None);
+
+    device.set_stencil_test(Some(StencilTest::Write(1)));
+    assert_eq!(unsafe { gl::IsEnabled(gl::STENCIL_TEST) }, gl::TRUE);
+    assert_eq!(device.stencil_test(), Some(StencilTest::Write(1)));
+
+    device.set_stencil_test(Some(StencilTest::NotEqual(1)));
+    let mut func = 0;
+    unsafe { gl::GetIntegerv(gl::STENCIL_FUNC, &mut func) };
+    assert_eq!(func as gl::types::GLenum, gl::NOTEQUAL);
+
+    device.set_stencil_test(None);
+    assert_eq!(unsafe { gl::IsEnabled(gl::STENCIL_TEST) }, gl::FALSE);
+    device.default_framebuffer().clear_stencil();
+    assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn framebuffer_introspection() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let texture = |format| device.new_texture_2d(32, 16, format).unwrap();
+    let framebuffer = device
+        .new_framebuffer([
+            Attachment::Color(texture(Format::R8G8B8A8), 0),
+            Attachment::Color(texture(Format::R32G32B32A32Float), 1),
+            Attachment::Color(texture(Format::R32Uint), 2),
+            Attachment::Depth(texture(Format::D24)),
+        ])
+        .unwrap();
+    assert_eq!(framebuffer.attachment_count(), 3);
+    assert_eq!(framebuffer.size(), uvec2(32, 16));
+    assert!(framebuffer.has_depth());
+
+    let framebuffer = device
+        .new_framebuffer([Attachment::Color(
+            device.new_texture_2d(8, 4, Format::R8G8B8A8).unwrap(),
+            0,
+        )])
+        .unwrap();
+    assert_eq!(framebuffer.attachment_count(), 1);
+    assert_eq!(framebuffer.size(), uvec2(8, 4));
+    assert!(!framebuffer.has_depth());
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn framebuffer_errors() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let depth = device.new_texture_2d(4, 4, Format::D24).unwrap();
+    let result = device.new_framebuffer([Attachment::Color(depth, 0)]);
+    assert_eq!(result.err(), Some(RhiError::UnsupportedFormat(Format::D24)));
+
+    let result = device.new_framebuffer::<0>([]);
+    assert_eq!(
+        result.err(),
+        Some(RhiError::FramebufferIncomplete(
+            gl::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT
+        ))
+    );
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn shader_errors() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let result = device.new_shader(VertexStage, "#version 460 core\nvoid main() {");
+    assert!(matches!(result, Err(RhiError::ShaderCompile(log)) if !log.is_empty()));
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn texture_format_errors() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    for format in [Format::Mat4, Format::Vec3, Format::U16] {
+        let result = device.new_texture_2d(4, 4, format);
+        assert_eq!(result.err(), Some(RhiError::UnsupportedFormat(format)));
+    }
+    assert!(device.new_texture_2d(4, 4, Format::R8G8B8A8).is_ok());
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn texture_cube_format_errors() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let result = device.new_texture_cube(4, Format::D24);
+    assert_eq!(result.err(), Some(RhiError::UnsupportedFormat(Format::D24)));
+    assert!(device.new_texture_cube(4, Format::R8G8B8A8).is_ok());
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn generate_mipmaps() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let mut texture = device
+        .new_texture_2d_with_levels(4, 4, 3, Format::R8G8B8A8)
+        .unwrap();
+    texture.write(&[255; 4 * 4 * 4]);
+    texture.generate_mipmaps();
+    assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn read_framebuffer() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let texture = device.new_texture_2d(4, 2, Format::R8G8B8A8).unwrap();
+    let mut framebuffer = device
+        .new_framebuffer([Attachment::Color(texture, 0)])
+        .unwrap();
+    framebuffer.clear(Vec4::new(1.0, 0.0, 1.0, 1.0), false);
+
+    let pixels = framebuffer.read();
+    assert_eq!(pixels.len(), 4 * 2 * 4);
+    assert!(pixels.chunks(4).all(|pixel| pixel == [255, 0, 255, 255]));
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn viewport() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let saved = device.viewport();
+    device.set_viewport(uvec4(2, 3, 40, 50));
+    assert_eq!(device.viewport(), uvec4(2, 3, 40, 50));
+
+    device.set_viewport(saved);
+    assert_eq!(device.viewport(), saved);
+    assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn framebuffer_mip_level() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let texture = device
+        .new_texture_2d_with_levels(32, 16, 3, Format::R8G8B8A8)
+        .unwrap();
+    assert_eq!(texture.levels(), 3);
+    assert_eq!(texture.level_size(2), uvec2(8, 4));
+
+    let framebuffer = device
+        .new_framebuffer_at_level([Attachment::Color(texture, 0)], 1)
+        .unwrap();
+    assert_eq!(framebuffer.size(), uvec2(16, 8));
+
+    let mut level = 0;
+    unsafe {
+        gl::GetNamedFramebufferAttachmentParameteriv(
+            framebuffer.id,
+            gl::COLOR_ATTACHMENT0,
+            gl::FRAMEBUFFER_ATTACHMENT_TEXTURE_LEVEL,
+            &mut level,
+        )
+    };
+    assert_eq!(level, 1);
+
+    // Render into the top right quarter of the level only.
+    device.set_viewport(uvec4(8, 4, 8, 4));
+    assert_eq!(device.viewport(), uvec4(8, 4, 8, 4));
+    assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn read_buffer_range() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let data: Vec<u32> = (0..8).collect();
+    let buffer: Buffer<u32, true> = device.new_buffer(BufferInit::Data(&data));
+    assert_eq!(buffer.read_range(2, 4), data[2..6]);
+    assert_eq!(buffer.read_range(0, 8), data);
+    assert!(buffer.read_range(8, 0).is_empty());
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn map_buffer_range() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let data: Vec<u32> = (0..8).collect();
+    let mut buffer: Buffer<u32, true, true> = device.new_buffer(BufferInit::Data(&data));
+
+    buffer.map_write_range(2, 3).write(&[100, 101, 102]);
+    assert_eq!(buffer.len(), 8);
+    assert_eq!(buffer.read_range(0, 8), [0, 1, 100, 101, 102, 5, 6, 7]);
+    assert_eq!(buffer.map_read_range(3, 2).read(), [101, 102]);
+    assert!(buffer.map_read_range(8, 0).read().is_empty());
+
+    // Writing past the elements written so far makes them part of the buffer.
+    let mut empty: Buffer<u32, true, true> = device.new_buffer(BufferInit::Capacity(4));
+    empty.map_write_range(0, 2).write(&[1, 2]);
+    assert_eq!(empty.len(), 2);
+    assert_eq!(empty.read_range(0, 2), [1, 2]);
+    assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+#[should_panic(expected = "mapped past the elements written to the buffer")]
+fn map_buffer_range_gap() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let mut buffer: Buffer<u32, true, true> = device.new_buffer(BufferInit::Capacity(4));
+    buffer.map_write_range(1, 2);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn copy_buffer_range() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let data: Vec<u32> = (0..8).collect();
+    let src: Buffer<u32> = device.new_buffer(BufferInit::Data(&data));
+    let mut dst: Buffer<u32, true> = device.new_buffer(BufferInit::Data(&[100; 6]));
+
+    device.copy_buffer(&src, &mut dst, 2, 1, 4);
+    assert_eq!(dst.len(), 6);
+    assert_eq!(dst.read_range(0, 6), [100, 2, 3, 4, 5, 100]);
+
+    let mut empty: Buffer<u32, true> = device.new_buffer(BufferInit::Capacity(4));
+    device.copy_buffer(&src, &mut empty, 6, 0, 2);
+    assert_eq!(empty.read_range(0, empty.len()), [6, 7]);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn resources_outlive_device() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let buffer: Buffer<u32, true> = device.new_buffer(BufferInit::Data(&[1, 2, 3]));
+    let query = device.begin_query(QueryKind::SamplesPassed);
+    device.end_query(&query);
+    drop((device, instance));
+
+    // The resources keep the context alive, so they can still be used and deleted.
+    assert_eq!(buffer.read_range(0, 3), [1, 2, 3]);
+    let id = buffer.id;
+    drop(buffer);
+    assert_eq!(unsafe { gl::IsBuffer(id) }, gl::FALSE);
+    drop(query);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn uniform_buffer_binding() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let vs = device
+        .new_shader(
+            VertexStage,
+            "#version 460 core\nlayout(std140, binding = 3) uniform Offset { vec4 offset; };\nvoid \
+             main() { gl_Position = offset; }",
+        )
+        .unwrap();
+    let ps = device
+        .new_shader(
+            PixelStage,
+            "#version 460 core\nout vec4 color;\nvoid main() { color = vec4(1.0); }",
+        )
+        .unwrap();
+    let program = device.new_shader_program(&vs, &ps).unwrap();
+    device.bind_shader_program(&program);
+
+    let mut offset: UniformBuffer<Vec4> = device.new_uniform_buffer("Offset", 3, 1);
+    offset.map_write().write(&[Vec4::ONE]);
+    assert_eq!(offset.binding, 3);
+
+    offset.bind();
+    let mut bound = 0;
+    unsafe { gl::GetIntegeri_v(gl::UNIFORM_BUFFER_BINDING, 3, &mut bound) };
+    assert_eq!(bound as u32, offset.buffer.id);
+}
+
+#[test]
+#[ignore = "requires a video device"]
+fn bind_texture_array() {
+    let (_sdl, _window, instance) = headless();
+    let device = instance.new_device().unwrap();
+
+    let vs = device
+        .new_shader(
+            VertexStage,
+            "#version 460 core\nvoid main() { gl_Position = vec4(0.0); }",
+        )
+        .unwrap();
+    let ps = device
+        .new_shader(
+            PixelStage,
+            "#version 460 core\nuniform sampler2D textures[3];\nout vec4 color;\nvoid main() { \
+             color = texture(textures[0], vec2(0.0)) + texture(textures[1], vec2(0.0)) + \
+             texture(textures[2], vec2(0.0)); }",
+        )
+        .unwrap();
+    let program = device.new_shader_program(&vs, &ps).unwrap();
+    device.bind_shader_program(&program);
+
+    let textures: Vec<_> = (0..3)
+        .map(|_| device.new_texture_2d(1, 1, Format::R8G8B8A8).unwrap())
+        .collect();
+    let textures: Vec<_> = textures.iter().collect();
+    device.bind_texture_array(&textures, "textures", 2);
+
+    for i in 0..3 {
+        let name = CString::new(format!("textures[{i}]")).unwrap();
+        let mut unit = -1;
+        unsafe {
+            let uniform = gl::GetUniformLocation(program.id, name.as_ptr());
+            gl::GetUniformiv(program.id, uniform, &mut unit);
+        }
+        assert_eq!(unit, 2 + i);
+    }
+}
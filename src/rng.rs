@@ -0,0 +1,74 @@
+use std::ops::Range;
+
+/// A small seedable PCG32 random number generator. The same seed always
+/// produces the same sequence, so a seed reproduces everything drawn from it.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    const MULTIPLIER: u64 = 6364136223846793005;
+    const INCREMENT: u64 = 1442695040888963407;
+
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self { state: 0 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(Self::INCREMENT);
+
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    /// A number in `range`, which must not be empty.
+    pub fn range(&mut self, range: Range<u32>) -> u32 {
+        assert!(!range.is_empty(), "empty range {range:?}");
+
+        // Scales the full 32 bits down to the range instead of taking the
+        // remainder, which favors the low bits.
+        let span = (range.end - range.start) as u64;
+        range.start + ((self.next_u32() as u64 * span) >> 32) as u32
+    }
+
+    /// A number in `0.0..1.0`.
+    pub fn f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+#[test]
+fn known_sequence() {
+    let mut rng = Rng::new(42);
+    let sequence: Vec<u32> = (0..4).map(|_| rng.next_u32()).collect();
+    assert_eq!(sequence, [3270867926, 1795671209, 1924641435, 1143034755]);
+
+    let mut a = Rng::new(7);
+    let mut b = Rng::new(7);
+    assert!((0..100).all(|_| a.f32() == b.f32()));
+}
+
+#[test]
+fn uniform_range() {
+    let mut rng = Rng::new(7);
+    let mut buckets = [0; 10];
+    for _ in 0..10000 {
+        buckets[rng.range(10..20) as usize - 10] += 1;
+    }
+
+    assert!(
+        buckets.iter().all(|&n| (900..1100).contains(&n)),
+        "{buckets:?}"
+    );
+    assert!((0..1000)
+        .map(|_| rng.f32())
+        .all(|x| (0.0..1.0).contains(&x)));
+}
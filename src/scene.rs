@@ -1,15 +1,39 @@
+use std::collections::HashMap;
 use std::mem::MaybeUninit;
+use std::ops::{Deref, DerefMut};
 use std::path::Path;
+use std::rc::Rc;
 
 use glam::*;
 
+use crate::format::fnt::FontFace;
 use crate::format::vox::{VoxMaterial, VoxModel};
+use crate::rhi::{BufferLayout, Format};
 use crate::tensor::SparseTensorChunk;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(transparent)]
 pub struct MaterialId(pub usize);
 
+impl MaterialId {
+    /// Material id 0 is reserved for empty space. Voxels with this id are never
+    /// stored in a chunk and never rendered.
+    pub const AIR: Self = Self(0);
+
+    pub fn is_air(&self) -> bool {
+        *self == Self::AIR
+    }
+}
+
+/// How a material behaves in the game, as opposed to how it looks.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MaterialKind {
+    #[default]
+    Solid,
+    Liquid,
+    Flammable,
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct Material {
     pub albedo: [u8; 4],
@@ -17,13 +41,179 @@ pub struct Material {
     pub metalness: f32,
 }
 
+impl Material {
+    /// The fully transparent material stored in the slot of
+    /// [`MaterialId::AIR`].
+    pub const AIR: Self = Self {
+        albedo: [0; 4],
+        roughness: 0.0,
+        metalness: 0.0,
+    };
+
+    /// Converts a .vox palette to a scene palette.
+    ///
+    /// In the .vox format color index 0 means empty and the palette starts at
+    /// index 1, so the materials are shifted up by one to line up with the
+    /// material ids of the voxels, leaving slot 0 for [`Material::AIR`].
+    pub fn palette(materials: &[VoxMaterial; 256]) -> Box<[Material; 256]> {
+        let mut palette = Box::new([Self::AIR; 256]);
+        for (slot, material) in palette[1..].iter_mut().zip(materials.iter()) {
+            *slot = Material::from(*material);
+        }
+
+        palette
+    }
+}
+
 impl From<VoxMaterial> for Material {
+    /// The lighting shader expects roughness and metalness in `0..=1` and
+    /// produces NaNs otherwise, so both are clamped to that range. NaNs fall
+    /// back to a rough dielectric.
     fn from(value: VoxMaterial) -> Self {
+        let unit = |value: f32, fallback: f32| {
+            if value.is_nan() {
+                fallback
+            } else {
+                value.clamp(0.0, 1.0)
+            }
+        };
+
         Self {
             albedo: value.albedo,
-            roughness: value.roughness,
-            metalness: value.metalness,
+            roughness: unit(value.roughness, 1.0),
+            metalness: unit(value.metalness, 0.0),
+        }
+    }
+}
+
+/// A transform decomposed into translation, rotation and scale, which is
+/// cheaper to store and upload than a full matrix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+        scale: Vec3::ONE,
+    };
+
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self {
+            translation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn from_rotation(rotation: Quat) -> Self {
+        Self {
+            rotation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn from_scale(scale: Vec3) -> Self {
+        Self {
+            scale,
+            ..Self::IDENTITY
+        }
+    }
+
+    /// Decomposes a matrix, which must not contain shear or perspective.
+    pub fn from_mat4(matrix: Mat4) -> Self {
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        Self {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn to_mat4(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.translation.is_finite() && self.rotation.is_finite() && self.scale.is_finite()
+    }
+
+    /// Blends from `self` at `alpha = 0` to `other` at `alpha = 1`.
+    pub fn interpolate(&self, other: &Self, alpha: f32) -> Self {
+        Self {
+            translation: self.translation.lerp(other.translation, alpha),
+            rotation: self.rotation.slerp(other.rotation, alpha),
+            scale: self.scale.lerp(other.scale, alpha),
+        }
+    }
+
+    /// Decodes transforms written by [`BufferLayout::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Vec<Self> {
+        let floats: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+
+        floats
+            .chunks_exact(Self::stride() / 4)
+            .map(|f| Self {
+                translation: vec3(f[0], f[1], f[2]),
+                rotation: Quat::from_xyzw(f[4], f[5], f[6], f[7]),
+                scale: vec3(f[8], f[9], f[10]),
+            })
+            .collect()
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Composes two transforms like their matrices would be multiplied, so `rhs` is
+/// applied first.
+///
+/// Scaling `self` non-uniformly after rotating by `rhs` shears the result,
+/// which a translation, rotation and scale can't hold. The shear is lost then,
+/// so the matrices have to be multiplied instead where it matters.
+impl std::ops::Mul for Transform {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self::from_mat4(self.to_mat4() * rhs.to_mat4())
+    }
+}
+
+impl std::ops::MulAssign for Transform {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+unsafe impl BufferLayout for Transform {
+    const LAYOUT: &'static [Format] = &[Format::Vec3, Format::Vec4, Format::Vec3];
+    const PADDING: &'static [usize] = &[4, 0, 4];
+    const COPYABLE: bool = false;
+
+    // The vectors are padded to 16 bytes to match the std140 layout of vec3.
+    fn to_bytes(items: &[Self]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(items.len() * Self::stride());
+
+        for item in items {
+            let translation = item.translation.extend(0.0);
+            let rotation = Vec4::from(item.rotation);
+            let scale = item.scale.extend(0.0);
+
+            for component in [translation, rotation, scale].iter().flat_map(Vec4::as_ref) {
+                bytes.extend_from_slice(&component.to_ne_bytes());
+            }
         }
+
+        bytes
     }
 }
 
@@ -35,12 +225,36 @@ pub struct Model {
     pub size: UVec3,
 }
 
+impl Model {
+    /// Moves the voxels so the center of their bounding box is at the origin,
+    /// which the model then rotates about. Returns the offset that was added
+    /// to every position.
+    pub fn center_origin(&mut self) -> Vec3 {
+        let Some(&(first, _)) = self.positions.first() else {
+            return Vec3::ZERO;
+        };
+        let (min, max) = self
+            .positions
+            .iter()
+            .fold((first, first), |(min, max), &(p, _)| {
+                (min.min(p), max.max(p))
+            });
+
+        // Each voxel covers the unit cube above its position.
+        let offset = -(min + max + 1.0) / 2.0;
+        for (position, _) in &mut self.positions {
+            *position += offset;
+        }
+        offset
+    }
+}
+
 impl From<VoxModel> for Model {
     fn from(value: VoxModel) -> Self {
         let positions = value
             .positions
             .into_iter()
-            .map(|(position, mat)| (position, MaterialId(mat.0 - 1)))
+            .map(|(position, mat)| (position, MaterialId(mat.0)))
             .collect();
 
         let transform = value.transform
@@ -56,9 +270,36 @@ impl From<VoxModel> for Model {
     }
 }
 
+/// A model animated by swapping between voxel frames, one after the other.
+#[derive(Debug, Clone)]
+pub struct AnimatedModel {
+    frames: Vec<Rc<Model>>,
+    /// Frames shown per second.
+    pub fps: f32,
+}
+
+impl AnimatedModel {
+    /// Panics if there are no `frames`, as there would be nothing to show.
+    pub fn new(frames: Vec<Rc<Model>>, fps: f32) -> Self {
+        assert!(!frames.is_empty(), "an animation needs at least one frame");
+
+        Self { frames, fps }
+    }
+
+    pub fn frames(&self) -> &[Rc<Model>] {
+        &self.frames
+    }
+
+    /// The index of the frame shown `time` seconds into the animation, which
+    /// starts over after the last frame.
+    pub fn frame(&self, time: f32) -> usize {
+        (time * self.fps).max(0.0) as usize % self.frames.len()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Light {
-    pub transform: Mat4,
+    pub transform: Transform,
     pub position: Vec3,
     pub color: Vec3,
 }
@@ -66,34 +307,65 @@ pub struct Light {
 impl Light {
     pub fn new(position: Vec3, color: Vec3) -> Self {
         Self {
-            transform: Mat4::from_translation(position),
+            transform: Transform::from_translation(position),
             position,
             color,
         }
     }
 }
 
+/// A light infinitely far away, like the sun, shining along `direction`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub color: Vec3,
+}
+
 #[derive(Debug, Clone)]
 pub struct Object {
-    pub transform: Mat4,
-    pub model: Model,
+    pub transform: Transform,
+    /// Objects spawned from the same asset share their model.
+    pub model: Rc<Model>,
     pub tag: Option<String>,
+    /// A color the object is blended towards, and how strongly in `0.0..=1.0`.
+    /// Used to flash enemies when they are hit.
+    pub flash: Option<(Vec3, f32)>,
+    /// Invisible objects stay in the scene graph but aren't drawn.
+    pub visible: bool,
+    /// The frames the model is swapped between, and the seconds the
+    /// animation has played for.
+    pub animation: Option<(Rc<AnimatedModel>, f32)>,
 }
 
 impl Object {
-    pub fn new(transform: Mat4, model: Model) -> Self {
+    pub fn new(transform: Transform, model: impl Into<Rc<Model>>) -> Self {
         Self {
             transform,
-            model,
+            model: model.into(),
             tag: None,
+            flash: None,
+            visible: true,
+            animation: None,
         }
     }
 
-    pub fn with_tag(transform: Mat4, model: Model, tag: String) -> Self {
+    pub fn with_tag(transform: Transform, model: impl Into<Rc<Model>>, tag: String) -> Self {
         Self {
             transform,
-            model,
+            model: model.into(),
             tag: Some(tag),
+            flash: None,
+            visible: true,
+            animation: None,
+        }
+    }
+
+    /// Advances the animation of the object by `dt` seconds and shows the
+    /// frame it has reached.
+    pub fn animate(&mut self, dt: f32) {
+        if let Some((animation, time)) = &mut self.animation {
+            *time += dt;
+            self.model = Rc::clone(&animation.frames[animation.frame(*time)]);
         }
     }
 }
@@ -106,7 +378,12 @@ pub enum Entity {
 }
 
 impl Entity {
-    fn transform(&self) -> Option<&Mat4> {
+    /// The transform of the entity as a matrix.
+    pub fn matrix(&self) -> Option<Mat4> {
+        self.transform().map(Transform::to_mat4)
+    }
+
+    fn transform(&self) -> Option<&Transform> {
         match self {
             Entity::Light(l) => Some(&l.transform),
             Entity::Object(o) => Some(&o.transform),
@@ -114,7 +391,7 @@ impl Entity {
         }
     }
 
-    fn transform_mut(&mut self) -> Option<&mut Mat4> {
+    fn transform_mut(&mut self) -> Option<&mut Transform> {
         match self {
             Entity::Light(l) => Some(&mut l.transform),
             Entity::Object(o) => Some(&mut o.transform),
@@ -135,12 +412,89 @@ macro_rules! impl_into_entity {
 
 impl_into_entity!(Light, Object, Camera);
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// How a [`Text`] is broken into lines and where each line is placed.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TextLayout {
+    /// Lines are wrapped at word boundaries to fit within this width in pixels.
+    /// Without a width, lines are only broken at newlines.
+    pub max_width: Option<f32>,
+    /// Lines are aligned within `max_width`, or within the widest line.
+    pub align: Align,
+}
+
+impl TextLayout {
+    /// Splits `text` into lines and returns each line with its horizontal
+    /// offset from the position of the text. A word wider than `max_width` is
+    /// placed on a line of its own.
+    pub fn lines<'a>(&self, face: &FontFace, text: &'a str, scale: f32) -> Vec<(&'a str, f32)> {
+        let width = |line: &str| face.measure(line) as f32 * scale;
+
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            // The byte range of the line being filled.
+            let mut line: Option<(usize, usize)> = None;
+            let mut word_start = 0;
+
+            for word in paragraph.split(' ') {
+                let word_end = word_start + word.len();
+
+                if !word.is_empty() {
+                    line = match line {
+                        Some((start, end)) => match self.max_width {
+                            Some(max) if width(&paragraph[start..word_end]) > max => {
+                                lines.push(&paragraph[start..end]);
+                                Some((word_start, word_end))
+                            }
+                            _ => Some((start, word_end)),
+                        },
+                        None => Some((word_start, word_end)),
+                    };
+                }
+
+                word_start = word_end + 1;
+            }
+
+            lines.push(line.map_or("", |(start, end)| &paragraph[start..end]));
+        }
+
+        let widest = lines.iter().map(|line| width(line)).fold(0.0, f32::max);
+        let bounds = self.max_width.unwrap_or(widest);
+
+        lines
+            .into_iter()
+            .map(|line| {
+                let offset = match self.align {
+                    Align::Left => 0.0,
+                    Align::Center => (bounds - width(line)) / 2.0,
+                    Align::Right => bounds - width(line),
+                };
+
+                (line, offset)
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Text {
     pub position: UVec2,
     pub text: String,
     pub color: Vec4,
     pub scale: f32,
+    pub layout: TextLayout,
+    /// Texts on higher layers are drawn on top of texts on lower layers.
+    pub layer: i32,
+    /// Draws a dark outline around the glyphs, which keeps the text legible on
+    /// bright backgrounds.
+    pub outline: bool,
 }
 
 impl Text {
@@ -150,6 +504,9 @@ impl Text {
             text,
             color: vec4(1.0, 1.0, 1.0, 1.0),
             scale: 1.0,
+            layout: TextLayout::default(),
+            layer: 0,
+            outline: false,
         }
     }
 
@@ -159,6 +516,9 @@ impl Text {
             text,
             color: vec4(0.0, 0.0, 0.0, 1.0),
             scale: 1.0,
+            layout: TextLayout::default(),
+            layer: 0,
+            outline: false,
         }
     }
 
@@ -168,21 +528,190 @@ impl Text {
             text,
             color,
             scale: 1.0,
+            layout: TextLayout::default(),
+            layer: 0,
+            outline: false,
         }
     }
 }
 
-#[derive(Debug)]
+/// A stable handle to a terrain chunk in a [`Scene`], which stays valid when
+/// other chunks are added or removed. The slots of removed chunks are reused,
+/// and the generation tells a handle to a removed chunk apart from one to the
+/// chunk that took its slot.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TerrainId {
+    index: usize,
+    generation: u32,
+}
+
+/// A voxel of a terrain chunk as it is drawn, with the ambient occlusion of
+/// its faces packed by the renderer, see [`Scene::drawn_voxels`].
+pub type DrawnVoxel = (UVec3, MaterialId, [u32; 2]);
+
+#[derive(Debug, Clone)]
+struct TerrainSlot {
+    generation: u32,
+    chunk: Option<SparseTensorChunk>,
+    /// The voxels the chunk was last drawn with, until it or a chunk next to
+    /// it changes.
+    drawn: Option<Vec<DrawnVoxel>>,
+}
+
+/// The terrain merged onto the world grid, where a voxel of the grid is solid
+/// if a voxel of any terrain chunk overlaps it, no matter how the chunk is
+/// scaled.
+#[derive(Debug, Clone, Default)]
+pub struct Collision {
+    /// The solid voxels of the grid with the material of every terrain voxel
+    /// overlapping them, in the order they were added.
+    voxels: HashMap<IVec3, Vec<MaterialId>>,
+}
+
+impl Collision {
+    /// How far a terrain voxel has to reach into a voxel of the grid to
+    /// overlap it, so rounding errors don't grow voxels by a whole voxel.
+    const EPSILON: f32 = 1e-4;
+
+    /// The voxels of the grid overlapped by every voxel of `chunk`.
+    fn overlapped(chunk: &SparseTensorChunk) -> impl Iterator<Item = (IVec3, MaterialId)> + '_ {
+        chunk.iter_voxels().flat_map(|(i, material_id)| {
+            let (min, max) = transformed_box(chunk.transform, i.as_vec3(), (i + 1).as_vec3());
+            let min = (min + Self::EPSILON).floor().as_ivec3();
+            let max = (max - Self::EPSILON).ceil().as_ivec3();
+            (min.z..max.z).flat_map(move |z| {
+                (min.y..max.y)
+                    .flat_map(move |y| (min.x..max.x).map(move |x| (ivec3(x, y, z), material_id)))
+            })
+        })
+    }
+
+    fn add(&mut self, chunk: &SparseTensorChunk) {
+        for (v, material_id) in Self::overlapped(chunk) {
+            self.voxels.entry(v).or_default().push(material_id);
+        }
+    }
+
+    fn remove(&mut self, chunk: &SparseTensorChunk) {
+        for (v, material_id) in Self::overlapped(chunk) {
+            if let Some(materials) = self.voxels.get_mut(&v) {
+                if let Some(i) = materials.iter().position(|&m| m == material_id) {
+                    materials.remove(i);
+                }
+                if materials.is_empty() {
+                    self.voxels.remove(&v);
+                }
+            }
+        }
+    }
+
+    /// The material of the voxel `v` of the grid, or `None` if it is empty.
+    /// Where several terrain voxels overlap, the one added first wins.
+    pub fn voxel(&self, v: IVec3) -> Option<MaterialId> {
+        self.voxels.get(&v).map(|materials| materials[0])
+    }
+
+    /// The amount of solid voxels of the grid.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.voxels.len()
+    }
+}
+
+/// A terrain chunk borrowed mutably from a [`Scene`]. Reading the chunk
+/// leaves the collision of the scene alone, and once the chunk has been
+/// changed its collision is updated when the guard is dropped. The edits are
+/// batched, see [`SparseTensorChunk::begin_edit`], and only the drawn voxels
+/// of the chunks next to the edited range are forgotten.
+pub struct TerrainMut<'a> {
+    scene: &'a mut Scene,
+    /// The slot of the chunk, which [`Scene::terrain_mut`] made sure is
+    /// occupied.
+    index: usize,
+    /// The world space bounds of the chunk from before it was first borrowed
+    /// mutably, or `None` while it has only been read.
+    changed: Option<(Vec3, Vec3)>,
+}
+
+impl Deref for TerrainMut<'_> {
+    type Target = SparseTensorChunk;
+
+    fn deref(&self) -> &Self::Target {
+        self.scene.terrain[self.index].chunk.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for TerrainMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        let Scene {
+            terrain, collision, ..
+        } = &mut *self.scene;
+        let chunk = terrain[self.index].chunk.as_mut().unwrap();
+        if self.changed.is_none() {
+            collision.remove(chunk);
+            chunk.begin_edit();
+            self.changed = Some(chunk_bounds(chunk));
+        }
+        chunk
+    }
+}
+
+impl Drop for TerrainMut<'_> {
+    fn drop(&mut self) {
+        if let Some(before) = self.changed {
+            let Scene {
+                terrain, collision, ..
+            } = &mut *self.scene;
+            let chunk = terrain[self.index].chunk.as_mut().unwrap();
+            collision.add(chunk);
+
+            // A chunk which was moved or resized may have left the chunks next to
+            // it or come close to others, while edited voxels only matter to the
+            // chunks next to them.
+            let edited = chunk.end_edit();
+            let after = chunk_bounds(chunk);
+            if after != before {
+                self.scene.forget_drawn(before);
+                self.scene.forget_drawn(after);
+            } else if let Some((min, max)) = edited {
+                let transform = chunk.transform;
+                let range = transformed_box(transform, min.as_vec3(), (max + 1).as_vec3());
+                self.scene.forget_drawn(range);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Scene {
     pub camera: SceneNodeId,
     pub scene_graph: SceneGraph,
-    pub terrain: Vec<SparseTensorChunk>,
+    terrain: Vec<TerrainSlot>,
+    /// The slots of `terrain` whose chunk has been removed.
+    free_terrain: Vec<usize>,
+    /// The terrain merged onto the world grid, see [`Scene::collision`]. It is
+    /// updated chunk by chunk as the terrain changes.
+    collision: Collision,
     pub text: Vec<Text>,
+    /// The time of day in the range 0..1, where 0.25 is sunrise, 0.5 is noon
+    /// and 0.75 is sunset.
+    pub time_of_day: f32,
     has_materials: bool,
     materials: Box<[Material; 256]>,
+    /// The kind of every material, indexed by material id.
+    material_kinds: Box<[MaterialKind; 256]>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::empty()
+    }
 }
 
 impl Scene {
+    /// The length of a full day in seconds.
+    const DAY_LENGTH: f32 = 600.0;
+
     pub fn new(camera: Camera) -> Self {
         let mut scene_graph = SceneGraph::new();
         let camera_id = scene_graph.insert_entity(camera, &scene_graph.root());
@@ -191,12 +720,22 @@ impl Scene {
             camera: camera_id,
             scene_graph,
             terrain: Vec::default(),
+            free_terrain: Vec::default(),
+            collision: Collision::default(),
             text: Vec::default(),
+            time_of_day: 0.5,
             has_materials: false,
-            materials: Box::new([Material::default(); 256]),
+            materials: Box::new([Material::AIR; 256]),
+            material_kinds: Box::new([MaterialKind::default(); 256]),
         }
     }
 
+    /// A scene with only a camera at the origin, which is handy for tests
+    /// that need a scene but no window.
+    pub fn empty() -> Self {
+        Self::new(Camera::new(Vec3::ZERO, 1.0))
+    }
+
     pub fn camera(&self) -> &Camera {
         let entity = self.scene_graph.entity(&self.camera).unwrap();
         match entity {
@@ -213,6 +752,240 @@ impl Scene {
         }
     }
 
+    pub fn add_terrain(&mut self, chunk: SparseTensorChunk) -> TerrainId {
+        self.collision.add(&chunk);
+        self.forget_drawn(chunk_bounds(&chunk));
+        let Some(index) = self.free_terrain.pop() else {
+            self.terrain.push(TerrainSlot {
+                generation: 0,
+                chunk: Some(chunk),
+                drawn: None,
+            });
+            return TerrainId {
+                index: self.terrain.len() - 1,
+                generation: 0,
+            };
+        };
+
+        let slot = &mut self.terrain[index];
+        slot.chunk = Some(chunk);
+        slot.drawn = None;
+        TerrainId {
+            index,
+            generation: slot.generation,
+        }
+    }
+
+    pub fn remove_terrain(&mut self, id: &TerrainId) -> Option<SparseTensorChunk> {
+        let slot = self.terrain_slot(id)?;
+        let chunk = slot.chunk.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_terrain.push(id.index);
+        self.collision.remove(&chunk);
+        self.forget_drawn(chunk_bounds(&chunk));
+        Some(chunk)
+    }
+
+    fn terrain_slot(&mut self, id: &TerrainId) -> Option<&mut TerrainSlot> {
+        self.terrain
+            .get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)
+    }
+
+    /// The collision of the chunk is updated once it has been changed through
+    /// the returned guard, see [`TerrainMut`].
+    pub fn terrain_mut(&mut self, id: &TerrainId) -> Option<TerrainMut<'_>> {
+        self.terrain_slot(id)?.chunk.as_ref()?;
+        Some(TerrainMut {
+            scene: self,
+            index: id.index,
+            changed: None,
+        })
+    }
+
+    /// The voxels the chunk `id` is drawn with, which `extract` works out from
+    /// the chunk and the chunks next to it. They are kept until the chunk or
+    /// one of the chunks next to it is added, removed or changed, so `extract`
+    /// only runs for chunks whose faces may have changed.
+    pub fn drawn_voxels(
+        &mut self,
+        id: &TerrainId,
+        extract: impl FnOnce(&SparseTensorChunk, &[&SparseTensorChunk]) -> Vec<DrawnVoxel>,
+    ) -> Option<&[DrawnVoxel]> {
+        if self.terrain_slot(id)?.drawn.is_none() {
+            let chunk = self.terrain[id.index].chunk.as_ref()?;
+            let around = neighborhood(chunk);
+            let neighbors: Vec<&SparseTensorChunk> = self
+                .terrain()
+                .filter(|(other, next)| other != id && overlaps(around, chunk_bounds(next)))
+                .map(|(_, next)| next)
+                .collect();
+
+            let drawn = extract(chunk, &neighbors);
+            self.terrain[id.index].drawn = Some(drawn);
+        }
+
+        self.terrain[id.index].drawn.as_deref()
+    }
+
+    /// Forgets the drawn voxels of every chunk next to the world space box
+    /// `bounds`, as something inside of it changed.
+    fn forget_drawn(&mut self, bounds: (Vec3, Vec3)) {
+        for slot in &mut self.terrain {
+            let chunk = slot.chunk.as_ref();
+            if chunk.is_some_and(|chunk| overlaps(neighborhood(chunk), bounds)) {
+                slot.drawn = None;
+            }
+        }
+    }
+
+    /// Returns all terrain chunks in the scene together with their ids.
+    pub fn terrain(&self) -> impl Iterator<Item = (TerrainId, &SparseTensorChunk)> {
+        self.terrain.iter().enumerate().filter_map(|(index, slot)| {
+            let id = TerrainId {
+                index,
+                generation: slot.generation,
+            };
+            slot.chunk.as_ref().map(|chunk| (id, chunk))
+        })
+    }
+
+    /// The world space box enclosing the voxels of every object and the space
+    /// of every terrain chunk, as its minimum and maximum corner, or `None`
+    /// for a scene without geometry. Objects are placed as of the last time
+    /// the scene graph was evaluated.
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        let objects = self
+            .scene_graph
+            .mutated_entities()
+            .filter_map(|(_, entity)| match entity {
+                Entity::Object(object) => {
+                    let positions = object.model.positions.iter().map(|&(p, _)| p);
+                    let min = positions.clone().reduce(Vec3::min)?;
+                    let max = positions.reduce(Vec3::max)? + 1.0;
+                    let transform = object.transform.to_mat4() * object.model.transform;
+                    Some(transformed_box(transform, min, max))
+                }
+                _ => None,
+            });
+        let terrain = self.terrain().map(|(_, chunk)| chunk_bounds(chunk));
+
+        objects
+            .chain(terrain)
+            .reduce(|(a_min, a_max), (b_min, b_max)| (a_min.min(b_min), a_max.max(b_max)))
+    }
+
+    /// All terrain merged onto the world grid, so systems asking whether the
+    /// world is solid don't have to look through every chunk.
+    pub fn collision(&self) -> &Collision {
+        &self.collision
+    }
+
+    /// Whether any terrain chunk has a voxel at `point` in world space, see
+    /// [`Scene::collision`].
+    pub fn is_solid(&self, point: Vec3) -> bool {
+        self.collision().voxel(point.floor().as_ivec3()).is_some()
+    }
+
+    /// The height of the top of the first solid terrain voxel found walking
+    /// down from `top` at `xz`, or `None` if there is no terrain below.
+    /// Voxels reaching above `top` count as long as they start below it, so
+    /// a ceiling above `top` is never mistaken for the ground.
+    ///
+    /// The chunks are read directly instead of through
+    /// [`Scene::collision`], and are walked along their own y axis, so this
+    /// expects terrain that is translated and scaled but not rotated.
+    pub fn ground_height(&self, xz: Vec2, top: f32) -> Option<f32> {
+        self.terrain()
+            .filter_map(|(_, chunk)| {
+                let local = chunk
+                    .transform
+                    .inverse()
+                    .transform_point3(vec3(xz.x, top, xz.y));
+                let column = local.xz().floor();
+                let in_bounds =
+                    column.cmpge(Vec2::ZERO).all() && column.cmplt(chunk.dim.xz().as_vec2()).all();
+                // The highest voxel that starts below `top`.
+                let start = (local.y.ceil() - 1.0).min(chunk.dim.y as f32 - 1.0);
+                if !in_bounds || start < 0.0 {
+                    return None;
+                }
+
+                let (x, z) = (column.x as u32, column.y as u32);
+                (0..=start as u32)
+                    .rev()
+                    .find(|&y| chunk.voxel(uvec3(x, y, z)).is_some())
+                    .map(|y| chunk.local_to_world(uvec3(x, y + 1, z)).y)
+            })
+            .reduce(f32::max)
+    }
+
+    /// Whether the straight line from `a` to `b` passes through no solid
+    /// terrain. The line is marched one voxel of the world grid at a time,
+    /// skipping the voxels of `a` and `b` themselves.
+    pub fn has_line_of_sight(&self, a: Vec3, b: Vec3) -> bool {
+        let delta = b - a;
+        let end = b.floor();
+        let mut voxel = a.floor();
+
+        let mut step = Vec3::ZERO;
+        // How far along the line, from 0 at `a` to 1 at `b`, the next voxel
+        // border and the distance between two borders on every axis.
+        let mut next_border = Vec3::splat(f32::INFINITY);
+        let mut border_distance = Vec3::splat(f32::INFINITY);
+        for axis in 0..3 {
+            if delta[axis] > 0.0 {
+                step[axis] = 1.0;
+                next_border[axis] = (voxel[axis] + 1.0 - a[axis]) / delta[axis];
+            } else if delta[axis] < 0.0 {
+                step[axis] = -1.0;
+                next_border[axis] = (voxel[axis] - a[axis]) / delta[axis];
+            } else {
+                continue;
+            }
+            border_distance[axis] = 1.0 / delta[axis].abs();
+        }
+
+        while voxel != end {
+            let axis = (0..3)
+                .min_by(|&i, &j| next_border[i].total_cmp(&next_border[j]))
+                .unwrap();
+            if next_border[axis] > 1.0 {
+                break;
+            }
+
+            voxel[axis] += step[axis];
+            next_border[axis] += border_distance[axis];
+
+            if voxel != end && self.is_solid(voxel + 0.5) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub fn advance_time_of_day(&mut self, dt: f32) {
+        self.time_of_day = (self.time_of_day + dt / Self::DAY_LENGTH).rem_euclid(1.0);
+    }
+
+    /// Computes the sun from the time of day. The sun rises in the east (+x)
+    /// and sets in the west (-x), and it is tinted warm when close to the
+    /// horizon.
+    pub fn sun(&self) -> DirectionalLight {
+        let angle = (self.time_of_day - 0.25) * std::f32::consts::TAU;
+        let to_sun = vec3(angle.cos(), angle.sin(), 0.0);
+
+        let elevation = to_sun.y.clamp(0.0, 1.0);
+        let warm = vec3(1.0, 0.5, 0.2);
+        let color = warm.lerp(Vec3::ONE, elevation.sqrt()) * elevation;
+
+        DirectionalLight {
+            direction: -to_sun,
+            color,
+        }
+    }
+
     pub fn has_materials(&self) -> bool {
         self.has_materials
     }
@@ -225,6 +998,50 @@ impl Scene {
         self.has_materials = true;
         self.materials = materials;
     }
+
+    /// Every material is [`MaterialKind::Solid`] until told otherwise.
+    pub fn material_kind(&self, id: MaterialId) -> MaterialKind {
+        self.material_kinds[id.0]
+    }
+
+    pub fn set_material_kind(&mut self, id: MaterialId, kind: MaterialKind) {
+        self.material_kinds[id.0] = kind;
+    }
+
+    /// The kind of the terrain voxel at `point` in world space, or `None` if
+    /// there is no terrain.
+    pub fn voxel_kind(&self, point: Vec3) -> Option<MaterialKind> {
+        let id = self.collision().voxel(point.floor().as_ivec3())?;
+        Some(self.material_kind(id))
+    }
+}
+
+/// The axis aligned box enclosing the box from `min` to `max` once it is
+/// transformed by `transform`.
+fn transformed_box(transform: Mat4, min: Vec3, max: Vec3) -> (Vec3, Vec3) {
+    let corners = (0..8).map(|corner| {
+        let pick = BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0);
+        transform.transform_point3(Vec3::select(pick, max, min))
+    });
+
+    let first = transform.transform_point3(min);
+    corners.fold((first, first), |(min, max), p| (min.min(p), max.max(p)))
+}
+
+/// The world space box enclosing the space of `chunk`.
+fn chunk_bounds(chunk: &SparseTensorChunk) -> (Vec3, Vec3) {
+    transformed_box(chunk.transform, Vec3::ZERO, chunk.dim.as_vec3())
+}
+
+/// The world space box enclosing the space of `chunk` and the voxels right
+/// next to it, which cover its faces and occlude their corners.
+fn neighborhood(chunk: &SparseTensorChunk) -> (Vec3, Vec3) {
+    transformed_box(chunk.transform, Vec3::NEG_ONE, chunk.dim.as_vec3() + 1.0)
+}
+
+/// Whether the boxes `a` and `b` share more than their boundary.
+fn overlaps((a_min, a_max): (Vec3, Vec3), (b_min, b_max): (Vec3, Vec3)) -> bool {
+    a_min.cmplt(b_max).all() && b_min.cmplt(a_max).all()
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -232,13 +1049,28 @@ pub struct Camera {
     pub position: Vec3,
     direction: Vec3,
     up: Vec3,
-    transform: Mat4,
+    /// The angle in radians the camera is rotated by around its direction.
+    roll: f32,
+    /// How strongly the camera shakes in `0.0..=1.0`.
+    trauma: f32,
+    /// Seconds a trauma of one takes to wear off.
+    shake_duration: f32,
+    /// Seconds the camera has been shaking for, which the shake is sampled at.
+    shake_time: f32,
+    /// The shake relative to the transform, which only moves the view.
+    shake: Transform,
+    transform: Transform,
     view: Mat4,
     projection: Mat4,
 }
 
 impl Camera {
     const FOV: f32 = std::f32::consts::FRAC_PI_2;
+    /// How far in units and how many radians the view shakes at most.
+    const MAX_SHAKE_OFFSET: f32 = 0.5;
+    const MAX_SHAKE_ANGLE: f32 = 0.05;
+    /// How quickly the shake changes direction.
+    const SHAKE_FREQUENCY: f32 = 25.0;
 
     pub fn new(position: Vec3, aspect_ratio: f32) -> Self {
         let direction = vec3(0.0, 0.0, 1.0);
@@ -248,7 +1080,12 @@ impl Camera {
             position,
             direction,
             up,
-            transform: Mat4::from_translation(position),
+            roll: 0.0,
+            trauma: 0.0,
+            shake_duration: 0.5,
+            shake_time: 0.0,
+            shake: Transform::IDENTITY,
+            transform: Transform::from_translation(position),
             view: Mat4::IDENTITY,
             projection: Mat4::perspective_rh_gl(Self::FOV, aspect_ratio, 0.1, 100.0),
         };
@@ -271,7 +1108,7 @@ impl Camera {
 
     pub fn set_translation(&mut self, position: Vec3) {
         self.position = position;
-        self.transform *= Mat4::from_translation(position);
+        self.transform.translation = position;
         self.update_view();
     }
 
@@ -300,14 +1137,146 @@ impl Camera {
         self.update_view();
     }
 
+    pub fn roll(&self) -> f32 {
+        self.roll
+    }
+
+    /// Rotates the camera by `roll` radians around its direction. Positive
+    /// angles tilt the top of the view to the right.
+    pub fn set_roll(&mut self, roll: f32) {
+        self.roll = roll;
+        self.update_view();
+    }
+
+    pub fn trauma(&self) -> f32 {
+        self.trauma
+    }
+
+    /// Shakes the camera harder, e.g. for a heavy hit. Trauma adds up to at
+    /// most one, and the shake grows with the square of it.
+    pub fn add_trauma(&mut self, trauma: f32) {
+        self.trauma = (self.trauma + trauma).clamp(0.0, 1.0);
+    }
+
+    /// Sets how many seconds a trauma of one takes to wear off.
+    pub fn set_shake_duration(&mut self, seconds: f32) {
+        self.shake_duration = seconds;
+    }
+
+    /// Lets the trauma wear off by `dt` seconds and moves the view to the
+    /// shake at the new time. Called once per frame.
+    pub fn update_shake(&mut self, dt: f32) {
+        self.trauma = (self.trauma - dt / self.shake_duration).max(0.0);
+        self.shake_time += dt;
+
+        // Sines of unrelated frequencies stand in for smooth noise, one per axis.
+        let t = self.shake_time * Self::SHAKE_FREQUENCY;
+        let noise = |seed: f32| ((t + seed).sin() + (2.3 * t + 1.7 * seed).sin()) / 2.0;
+
+        let strength = self.trauma * self.trauma;
+        let offset = vec3(noise(1.0), noise(2.0), noise(3.0)) * Self::MAX_SHAKE_OFFSET;
+        let [yaw, pitch, roll] = [noise(4.0), noise(5.0), noise(6.0)]
+            .map(|angle| angle * strength * Self::MAX_SHAKE_ANGLE);
+        self.shake = Transform {
+            translation: offset * strength,
+            rotation: Quat::from_euler(EulerRot::YXZ, yaw, pitch, roll),
+            scale: Vec3::ONE,
+        };
+        self.update_view_matrix();
+    }
+
+    /// The offset of the view from the transform of the camera by the shake.
+    pub fn shake(&self) -> &Transform {
+        &self.shake
+    }
+
+    /// Moves the camera back along its direction until the box from `min` to
+    /// `max`, e.g. [`Scene::bounds`], fits in the view with its center in the
+    /// middle of the screen.
+    pub fn fit(&mut self, (min, max): (Vec3, Vec3)) {
+        let center = (min + max) / 2.0;
+        let radius = (max - min).length() / 2.0;
+
+        // The sphere around the box has to fit in the narrower of the vertical
+        // and horizontal field of view.
+        let aspect_ratio = self.projection.y_axis.y / self.projection.x_axis.x;
+        let half_fov = ((Self::FOV / 2.0).tan() * aspect_ratio.min(1.0)).atan();
+        let distance = radius / half_fov.sin();
+
+        self.set_translation(center - self.direction * distance);
+    }
+
+    /// Turns the camera towards `target`. Looking at the camera's own position
+    /// leaves the direction unchanged.
+    pub fn look_at(&mut self, target: Vec3) {
+        let direction = target - self.position;
+        if direction != Vec3::ZERO {
+            self.set_direction(direction);
+        }
+    }
+
+    /// Moves the camera the fraction `t` of the way towards `position`, and
+    /// turns it along the shortest arc towards `direction`. Calling it every
+    /// frame eases the camera into place instead of cutting to it.
+    pub fn blend_towards(&mut self, position: Vec3, direction: Vec3, t: f32) {
+        let arc = Quat::from_rotation_arc(self.direction, direction.normalize());
+        let direction = Quat::IDENTITY.slerp(arc, t) * self.direction;
+
+        self.position = self.position.lerp(position, t);
+        self.transform.translation = self.position;
+        self.set_direction(direction);
+    }
+
+    /// Returns the origin on the near plane and the direction of the ray
+    /// passing through `ndc`, where (-1, -1) is the bottom left corner of the
+    /// screen and (1, 1) is the top right corner.
+    pub fn screen_to_world_ray(&self, ndc: Vec2) -> (Vec3, Vec3) {
+        let inverse = self.view_projection().inverse();
+        let near = inverse.project_point3(ndc.extend(-1.0));
+        let far = inverse.project_point3(ndc.extend(1.0));
+        (near, (far - near).normalize())
+    }
+
+    /// Projects `point` to normalized device coordinates, or `None` if the
+    /// point is behind the camera.
+    pub fn world_to_screen(&self, point: Vec3) -> Option<Vec2> {
+        let clip = self.view_projection() * point.extend(1.0);
+        if clip.w <= 0.0 {
+            return None;
+        }
+
+        Some(clip.xy() / clip.w)
+    }
+
     pub fn resize(&mut self, width: f32, height: f32) {
         self.projection = Mat4::perspective_rh_gl(Self::FOV, width / height, 0.1, 100.0);
     }
 
+    /// The transform of the camera is its world transform, which children of
+    /// the camera in the scene graph are placed relative to. The camera looks
+    /// down its local negative z axis, and the view is the inverse of the
+    /// transform.
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
     fn update_view(&mut self) {
         let right = vec3(0.0, 1.0, 0.0).cross(self.direction).normalize();
-        self.up = self.direction.cross(right).normalize();
-        self.view = Mat4::look_at_rh(self.position, self.position + self.direction, self.up);
+        let up = self.direction.cross(right).normalize();
+        self.up = Quat::from_axis_angle(self.direction, self.roll) * up;
+
+        let basis = Mat3::from_cols(self.right(), self.up, -self.direction);
+        self.transform = Transform {
+            translation: self.position,
+            rotation: Quat::from_mat3(&basis).normalize(),
+            scale: Vec3::ONE,
+        };
+        self.update_view_matrix();
+    }
+
+    /// The view is the inverse of the transform, shaken by the shake.
+    fn update_view_matrix(&mut self) {
+        self.view = (self.transform.to_mat4() * self.shake.to_mat4()).inverse();
     }
 }
 
@@ -319,6 +1288,8 @@ pub struct SceneNode {
     parent: SceneNodeId,
     base_entity: Entity,
     mutated_entity: Entity,
+    // The transform of `base_entity` at the previous fixed update.
+    previous_transform: Option<Transform>,
 }
 
 impl Clone for SceneNode {
@@ -327,6 +1298,7 @@ impl Clone for SceneNode {
             parent: SceneNodeId(self.parent.0),
             base_entity: self.base_entity.clone(),
             mutated_entity: self.mutated_entity.clone(),
+            previous_transform: self.previous_transform,
         }
     }
 }
@@ -335,29 +1307,48 @@ impl SceneNode {
     fn new(entity: Entity, parent: &SceneNodeId) -> Self {
         Self {
             parent: SceneNodeId(parent.0),
+            previous_transform: entity.transform().copied(),
             base_entity: entity.clone(),
             mutated_entity: entity,
         }
     }
 
-    fn evaluate(&mut self, parent: &Option<SceneNode>) {
-        if let Some(trans) = self.base_entity.transform() {
+    fn evaluate(&mut self, parent: &Option<SceneNode>, alpha: f32) {
+        if let Some(current) = self.base_entity.transform() {
+            let trans = match &self.previous_transform {
+                Some(previous) if alpha < 1.0 => previous.interpolate(current, alpha),
+                _ => *current,
+            };
+
             let new_trans =
                 if let Some(p_trans) = parent.as_ref().and_then(|p| p.mutated_entity.transform()) {
-                    *p_trans * *trans
+                    *p_trans * trans
                 } else {
-                    *trans
+                    trans
                 };
             *self
                 .mutated_entity
                 .transform_mut()
                 .expect("base_entity and mutated entity differ in type") = new_trans
         }
+
+        if let (Entity::Object(base), Entity::Object(mutated)) =
+            (&self.base_entity, &mut self.mutated_entity)
+        {
+            mutated.flash = base.flash;
+            mutated.visible = base.visible;
+            mutated.model = Rc::clone(&base.model);
+        }
+
+        // A camera below other nodes sees from where its world transform puts it.
+        if let Entity::Camera(camera) = &mut self.mutated_entity {
+            camera.update_view_matrix();
+        }
     }
 }
 
 // Actually a scene tree, because each node only has one parent.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SceneGraph {
     nodes: Vec<Option<SceneNode>>,
 }
@@ -416,6 +1407,13 @@ impl SceneGraph {
     }
 
     pub fn evaluate_all(&mut self) {
+        self.evaluate_all_interpolated(1.0);
+    }
+
+    /// Evaluates the graph with every transform blended between the previous
+    /// and the current fixed update, where `alpha` is the fraction of the fixed
+    /// timestep that has passed since the last update.
+    pub fn evaluate_all_interpolated(&mut self, alpha: f32) {
         for n in 0..self.nodes.len() {
             let parent = if let Some(node) = &self.nodes[n] {
                 self.nodes[node.parent.0].clone()
@@ -423,11 +1421,29 @@ impl SceneGraph {
                 continue;
             };
             if let Some(node) = &mut self.nodes[n] {
-                node.evaluate(&parent);
+                node.evaluate(&parent, alpha);
+
+                // A NaN or infinity would spread to every descendant and end up as
+                // invisible or exploded geometry, so it is caught where it appears.
+                debug_assert!(
+                    node.mutated_entity
+                        .transform()
+                        .is_none_or(Transform::is_finite),
+                    "node {n} has a non-finite transform: {:?}",
+                    node.mutated_entity.transform()
+                );
             }
         }
     }
 
+    /// Remembers the current transforms as the ones to interpolate from. Must
+    /// be called before each fixed update.
+    pub fn store_previous_transforms(&mut self) {
+        for node in self.nodes.iter_mut().flatten() {
+            node.previous_transform = node.base_entity.transform().copied();
+        }
+    }
+
     pub fn root(&self) -> SceneNodeId {
         SceneNodeId(0)
     }
@@ -442,6 +1458,22 @@ impl SceneGraph {
                 .map(|node| (SceneNodeId(i), &node.mutated_entity))
         })
     }
+
+    /// Returns the ids of all objects tagged with `tag`.
+    pub fn find_by_tag(&self, tag: &str) -> Vec<SceneNodeId> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(
+                |(i, node)| match node.as_ref().map(|node| &node.base_entity) {
+                    Some(Entity::Object(o)) if o.tag.as_deref() == Some(tag) => {
+                        Some(SceneNodeId(i))
+                    }
+                    _ => None,
+                },
+            )
+            .collect()
+    }
 }
 
 #[test]
@@ -449,29 +1481,721 @@ fn graph() {
     let mut g = SceneGraph::new();
     let root = g.root();
 
-    let transform = Mat4::from_cols_array_2d(&[[1., 2., 3., 4.]; 4]);
+    let transform = Transform {
+        translation: vec3(1.0, 2.0, 3.0),
+        rotation: Quat::from_rotation_z(0.5),
+        scale: vec3(2.0, 2.0, 2.0),
+    };
 
     let a = g.insert_entity(
         Object {
             transform,
-            model: Model::default(),
+            model: Rc::new(Model::default()),
             tag: None,
+            flash: None,
+            visible: true,
+            animation: None,
         },
         &root,
     );
     let b = g.insert_entity(
         Object {
             transform,
-            model: Model::default(),
+            model: Rc::new(Model::default()),
             tag: None,
+            flash: None,
+            visible: true,
+            animation: None,
         },
         &a,
     );
 
     g.evaluate_all();
 
+    let expected = transform.to_mat4() * transform.to_mat4();
+    assert!(g
+        .mutated_entity(&b)
+        .unwrap()
+        .matrix()
+        .unwrap()
+        .abs_diff_eq(expected, 1e-5));
+}
+
+#[test]
+fn material_ranges() {
+    let vox = VoxMaterial {
+        albedo: [255, 128, 0, 255],
+        roughness: 7.5,
+        metalness: -1.0,
+        transparency: 0.0,
+        specular: None,
+        ior: None,
+    };
+
+    let material = Material::from(vox);
+    assert_eq!(material.roughness, 1.0);
+    assert_eq!(material.metalness, 0.0);
+
+    let nan = Material::from(VoxMaterial {
+        roughness: f32::NAN,
+        metalness: f32::NAN,
+        ..vox
+    });
+    assert_eq!((nan.roughness, nan.metalness), (1.0, 0.0));
+
+    let bytes = Material::to_bytes(&[material, nan]);
+    assert!(bytes
+        .chunks_exact(4)
+        .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+        .all(f32::is_finite));
+}
+
+#[test]
+fn empty_scene() {
+    let scene = Scene::empty();
+
+    let entities: Vec<_> = scene.scene_graph.mutated_entities().collect();
+    assert_eq!(entities.len(), 1);
+    assert!(matches!(entities[0].1, Entity::Camera(_)));
+    assert_eq!(scene.terrain().count(), 0);
+    assert!(scene.text.is_empty());
+}
+
+#[test]
+fn terrain_ids() {
+    let mut scene = Scene::empty();
+
+    let a = scene.add_terrain(SparseTensorChunk::nothing(uvec3(1, 1, 1)));
+    let b = scene.add_terrain(SparseTensorChunk::nothing(uvec3(2, 2, 2)));
+
+    assert!(scene.remove_terrain(&a).is_some());
+    assert!(scene.terrain_mut(&a).is_none());
+    assert_eq!(scene.terrain_mut(&b).unwrap().dim, uvec3(2, 2, 2));
     assert_eq!(
-        g.mutated_entity(&b).unwrap().transform().unwrap(),
-        &(transform * transform)
+        scene.terrain().map(|(id, _)| id).collect::<Vec<_>>(),
+        vec![b]
+    );
+}
+
+#[test]
+fn terrain_slot_reuse() {
+    let mut scene = Scene::empty();
+
+    let mut id = scene.add_terrain(SparseTensorChunk::nothing(uvec3(1, 1, 1)));
+    for _ in 0..10 {
+        let old = id;
+        scene.remove_terrain(&old);
+        id = scene.add_terrain(SparseTensorChunk::nothing(uvec3(2, 2, 2)));
+
+        // The new chunk takes the slot of the old one, which can't be reached
+        // through the old id anymore.
+        assert_ne!(id, old);
+        assert!(scene.terrain_mut(&old).is_none());
+        assert!(scene.remove_terrain(&old).is_none());
+    }
+
+    assert_eq!(scene.terrain.len(), 1);
+    assert_eq!(scene.terrain_mut(&id).unwrap().dim, uvec3(2, 2, 2));
+}
+
+#[test]
+fn collision_chunk() {
+    let mut scene = Scene::empty();
+
+    let mut a = SparseTensorChunk::nothing(uvec3(2, 1, 1));
+    a.insert(uvec3(0, 0, 0), Some(MaterialId(1)));
+    a.insert(uvec3(1, 0, 0), Some(MaterialId(1)));
+    let mut b = SparseTensorChunk::nothing(uvec3(1, 1, 1));
+    b.insert(UVec3::ZERO, Some(MaterialId(2)));
+    b.transform = Mat4::from_translation(vec3(-3.0, 2.0, 0.0));
+    let b = scene.add_terrain(b);
+    scene.add_terrain(a);
+
+    let collision = scene.collision();
+    assert_eq!(collision.len(), 3);
+    assert!(std::ptr::eq(collision, scene.collision()));
+    assert!(scene.is_solid(vec3(1.5, 0.5, 0.5)));
+    assert!(scene.is_solid(vec3(-2.5, 2.5, 0.5)));
+    assert!(!scene.is_solid(vec3(-1.5, 0.5, 0.5)));
+
+    // Changing the terrain updates the collision.
+    scene.terrain_mut(&b).unwrap().remove(UVec3::ZERO);
+    assert_eq!(scene.collision().len(), 2);
+    assert!(!scene.is_solid(vec3(-2.5, 2.5, 0.5)));
+    scene.remove_terrain(&b);
+    assert_eq!(scene.collision().len(), 2);
+}
+
+#[test]
+fn collision_overlap() {
+    let mut scene = Scene::empty();
+
+    // A thin slab whose voxels contain no center of a voxel of the grid.
+    let mut slab = SparseTensorChunk::nothing(uvec3(2, 1, 1));
+    slab.insert(uvec3(0, 0, 0), Some(MaterialId(1)));
+    slab.insert(uvec3(1, 0, 0), Some(MaterialId(1)));
+    slab.transform = Mat4::from_scale(vec3(1.0, 1.0, 0.1));
+    let slab = scene.add_terrain(slab);
+    assert_eq!(scene.collision().len(), 2);
+    assert!(scene.is_solid(vec3(1.5, 0.5, 0.5)));
+
+    // A block overlapping the slab keeps the voxel solid once the slab is gone.
+    let mut block = SparseTensorChunk::nothing(uvec3(1, 1, 1));
+    block.insert(UVec3::ZERO, Some(MaterialId(2)));
+    let block = scene.add_terrain(block);
+    assert_eq!(scene.collision().len(), 2);
+
+    // Reading the chunk through the guard leaves the collision alone.
+    assert_eq!(scene.terrain_mut(&block).unwrap().data.len(), 1);
+    assert_eq!(scene.collision().voxel(IVec3::ZERO), Some(MaterialId(1)));
+    scene.remove_terrain(&slab);
+    assert_eq!(scene.collision().len(), 1);
+    // The material of the block is left, not the one of the removed slab.
+    assert_eq!(scene.collision().voxel(IVec3::ZERO), Some(MaterialId(2)));
+
+    scene.terrain_mut(&block).unwrap().remove(UVec3::ZERO);
+    assert_eq!(scene.collision().len(), 0);
+}
+
+#[test]
+fn drawn_voxels_cache() {
+    let mut scene = Scene::empty();
+    let a = scene.add_terrain(SparseTensorChunk::nothing(uvec3(2, 2, 2)));
+    let mut b = SparseTensorChunk::nothing(uvec3(2, 2, 2));
+    b.transform = Mat4::from_translation(vec3(2.0, 0.0, 0.0));
+    let b = scene.add_terrain(b);
+    let mut far = SparseTensorChunk::nothing(uvec3(2, 2, 2));
+    far.transform = Mat4::from_translation(vec3(10.0, 0.0, 0.0));
+    let far = scene.add_terrain(far);
+
+    // The amount of neighbors the voxels of `id` were extracted with, or `None`
+    // if they were kept from before.
+    let draw = |scene: &mut Scene, id: &TerrainId| {
+        let mut neighbors = None;
+        scene.drawn_voxels(id, |_, next| {
+            neighbors = Some(next.len());
+            Vec::new()
+        });
+        neighbors
+    };
+    assert_eq!(draw(&mut scene, &a), Some(1));
+    assert_eq!(draw(&mut scene, &far), Some(0));
+    assert_eq!(draw(&mut scene, &a), None);
+
+    // Changing `b` forgets the voxels of `a` as well, but not those of the far
+    // chunk.
+    scene
+        .terrain_mut(&b)
+        .unwrap()
+        .insert(UVec3::ZERO, Some(MaterialId(1)));
+    assert_eq!(draw(&mut scene, &a), Some(1));
+    assert_eq!(draw(&mut scene, &far), None);
+
+    // Only reading a chunk keeps them, and so do edits away from a chunk.
+    assert_eq!(scene.terrain_mut(&b).unwrap().data.len(), 1);
+    assert_eq!(draw(&mut scene, &a), None);
+    assert_eq!(draw(&mut scene, &b), Some(1));
+    scene
+        .terrain_mut(&b)
+        .unwrap()
+        .insert(UVec3::X, Some(MaterialId(1)));
+    assert_eq!(draw(&mut scene, &a), None);
+    assert_eq!(draw(&mut scene, &b), Some(1));
+
+    scene.remove_terrain(&b);
+    assert_eq!(draw(&mut scene, &a), Some(0));
+    assert_eq!(draw(&mut scene, &far), None);
+}
+
+#[test]
+fn material_kinds() {
+    let mut scene = Scene::empty();
+    let wood = MaterialId(7);
+    scene.set_material_kind(wood, MaterialKind::Flammable);
+
+    let mut chunk = SparseTensorChunk::nothing(uvec3(2, 1, 1));
+    chunk.insert(uvec3(0, 0, 0), Some(MaterialId(1)));
+    chunk.insert(uvec3(1, 0, 0), Some(wood));
+    scene.add_terrain(chunk);
+
+    assert_eq!(scene.material_kind(wood), MaterialKind::Flammable);
+    assert_eq!(
+        scene.voxel_kind(vec3(1.5, 0.5, 0.5)),
+        Some(MaterialKind::Flammable)
+    );
+    assert_eq!(
+        scene.voxel_kind(vec3(0.5, 0.5, 0.5)),
+        Some(MaterialKind::Solid)
+    );
+    assert_eq!(scene.voxel_kind(vec3(0.5, 1.5, 0.5)), None);
+}
+
+#[test]
+fn line_of_sight() {
+    let mut scene = Scene::empty();
+
+    let mut wall = SparseTensorChunk::nothing(uvec3(5, 8, 8));
+    for y in 0..8 {
+        for z in 0..8 {
+            wall.insert(uvec3(4, y, z), Some(MaterialId(1)));
+        }
+    }
+    let wall = scene.add_terrain(wall);
+
+    let (a, b) = (vec3(0.5, 2.5, 3.2), vec3(7.5, 5.5, 4.8));
+    assert!(!scene.has_line_of_sight(a, b));
+    assert!(!scene.has_line_of_sight(b, a));
+    assert!(scene.has_line_of_sight(a, vec3(3.9, 7.5, 0.5)));
+
+    scene.remove_terrain(&wall);
+    assert!(scene.has_line_of_sight(a, b));
+}
+
+#[test]
+fn sun_rotation() {
+    let mut scene = Scene::empty();
+    let noon = scene.sun();
+    assert!(noon.direction.abs_diff_eq(vec3(0.0, -1.0, 0.0), 1e-6));
+
+    // An eighth of a day is a 45 degree rotation.
+    scene.advance_time_of_day(Scene::DAY_LENGTH / 8.0);
+    let afternoon = scene.sun();
+    let angle = noon.direction.angle_between(afternoon.direction);
+    assert!((angle - std::f32::consts::FRAC_PI_4).abs() < 1e-4);
+
+    // The sun is dimmer when it is lower in the sky.
+    assert!(afternoon.color.length() < noon.color.length());
+}
+
+#[test]
+fn transform_bytes() {
+    let transforms = [
+        Transform::IDENTITY,
+        Transform {
+            translation: vec3(1.0, 2.0, 3.0),
+            rotation: Quat::from_rotation_y(1.2),
+            scale: vec3(0.5, 0.5, 2.0),
+        },
+    ];
+
+    let bytes = Transform::to_bytes(&transforms);
+    assert_eq!(bytes.len(), transforms.len() * Transform::stride());
+    assert_eq!(Transform::from_bytes(&bytes), transforms);
+}
+
+#[test]
+fn transform_composition() {
+    let parent = Transform {
+        translation: vec3(1.0, 2.0, 3.0),
+        rotation: Quat::from_rotation_y(0.3),
+        scale: Vec3::splat(2.0),
+    };
+    let child = Transform {
+        translation: vec3(-1.0, 0.5, 0.0),
+        rotation: Quat::from_rotation_z(0.7),
+        scale: vec3(1.0, 3.0, 0.5),
+    };
+
+    // A uniformly scaled parent keeps the composition free of shear.
+    let composed = (parent * child).to_mat4();
+    assert!(composed.abs_diff_eq(parent.to_mat4() * child.to_mat4(), 1e-5));
+
+    // Stretching a rotated child shears it, which is lost. Only where the
+    // origin of the child ends up is still right.
+    let stretched = Transform::from_scale(vec3(2.0, 1.0, 1.0));
+    let product = stretched.to_mat4() * child.to_mat4();
+    let composed = (stretched * child).to_mat4();
+    assert!(!composed.abs_diff_eq(product, 1e-3));
+    assert!(composed.w_axis.abs_diff_eq(product.w_axis, 1e-5));
+    assert!(product.x_axis.dot(product.y_axis).abs() > 1e-3);
+}
+
+#[test]
+fn transform_matrix() {
+    // The knife transform as it used to be built by hand.
+    let mut chain = Mat4::from_translation(vec3(3.0, -16.0, 10.0));
+    chain *= Mat4::from_scale(vec3(0.25, 0.25, 0.25));
+    chain *= Mat4::from_rotation_x(1.1);
+    chain *= Mat4::from_rotation_y(-1.6);
+
+    let transform = Transform {
+        translation: vec3(3.0, -16.0, 10.0),
+        rotation: Quat::from_rotation_x(1.1) * Quat::from_rotation_y(-1.6),
+        scale: vec3(0.25, 0.25, 0.25),
+    };
+
+    assert!(transform.to_mat4().abs_diff_eq(chain, 1e-5));
+}
+
+#[test]
+fn interpolated_graph() {
+    let mut g = SceneGraph::new();
+    let root = g.root();
+
+    let a = g.insert_entity(Object::new(Transform::IDENTITY, Model::default()), &root);
+    g.store_previous_transforms();
+
+    g.object_mut(&a).unwrap().transform = Transform {
+        translation: vec3(2.0, 0.0, 0.0),
+        rotation: Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+        scale: Vec3::ONE,
+    };
+
+    g.evaluate_all_interpolated(0.5);
+
+    let expected = Transform {
+        translation: vec3(1.0, 0.0, 0.0),
+        rotation: Quat::from_rotation_y(std::f32::consts::FRAC_PI_4),
+        scale: Vec3::ONE,
+    };
+    let matrix = g.mutated_entity(&a).unwrap().matrix().unwrap();
+    assert!(matrix.abs_diff_eq(expected.to_mat4(), 1e-5));
+}
+
+#[test]
+fn text_wrapping() {
+    let face =
+        crate::format::fnt::parse(include_bytes!("../assets/fonts/sans-serif/sans-serif.fnt"));
+
+    let text = "the office is overrun by zombies";
+    let max_width = face.measure("the office is") as f32;
+    let layout = TextLayout {
+        max_width: Some(max_width),
+        align: Align::Right,
+    };
+
+    let lines = layout.lines(&face, text, 1.0);
+    let words: Vec<&str> = lines.iter().map(|(line, _)| *line).collect();
+    assert_eq!(words, ["the office is", "overrun by", "zombies"]);
+
+    for (line, offset) in &lines {
+        assert_eq!(*offset, max_width - face.measure(line) as f32);
+    }
+    assert_eq!(lines[0].1, 0.0);
+
+    let centered = TextLayout {
+        align: Align::Center,
+        ..layout
+    };
+    let (line, offset) = centered.lines(&face, text, 1.0)[2];
+    assert_eq!(offset, (max_width - face.measure(line) as f32) / 2.0);
+
+    // Without a width only newlines break the text.
+    assert_eq!(TextLayout::default().lines(&face, "a b\nc", 2.0).len(), 2);
+}
+
+#[test]
+fn find_by_tag() {
+    let mut g = SceneGraph::new();
+    let root = g.root();
+
+    let object = |tag: Option<&str>| Object {
+        transform: Transform::IDENTITY,
+        model: Rc::new(Model::default()),
+        tag: tag.map(str::to_string),
+        flash: None,
+        visible: true,
+        animation: None,
+    };
+
+    let a = g.insert_entity(object(Some("enemy")), &root);
+    g.insert_entity(object(Some("gun")), &root);
+    let c = g.insert_entity(object(Some("enemy")), &a);
+    g.insert_entity(object(None), &root);
+    g.insert_entity(Light::new(Vec3::ZERO, Vec3::ONE), &root);
+
+    assert_eq!(g.find_by_tag("enemy"), vec![a.clone(), c]);
+    assert!(g.find_by_tag("knife").is_empty());
+
+    g.remove_entity(&a);
+    assert!(g.find_by_tag("enemy").is_empty());
+}
+
+#[test]
+fn scene_bounds() {
+    let mut scene = Scene::empty();
+    assert_eq!(scene.bounds(), None);
+
+    let mut chunk = SparseTensorChunk::nothing(uvec3(2, 2, 2));
+    scene.add_terrain(chunk.clone());
+    chunk.transform = Mat4::from_translation(vec3(4.0, -1.0, 0.0));
+    scene.add_terrain(chunk);
+
+    let (min, max) = scene.bounds().unwrap();
+    assert_eq!((min, max), (vec3(0.0, -1.0, 0.0), vec3(6.0, 2.0, 2.0)));
+
+    // Every corner of the box is on screen once the camera is fitted to it.
+    let mut camera = Camera::new(Vec3::ZERO, 4.0 / 3.0);
+    camera.set_direction(vec3(1.0, -1.0, 1.0));
+    camera.fit((min, max));
+    for corner in 0..8 {
+        let pick = BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0);
+        let ndc = camera
+            .world_to_screen(Vec3::select(pick, max, min))
+            .unwrap();
+        assert!(ndc.abs().cmple(Vec2::ONE).all(), "{ndc}");
+    }
+    let center = camera.world_to_screen((min + max) / 2.0).unwrap();
+    assert!(center.abs_diff_eq(Vec2::ZERO, 1e-4));
+}
+
+#[test]
+fn ground_height() {
+    let mut floor = SparseTensorChunk::nothing(uvec3(8, 1, 8));
+    for x in 0..8 {
+        for z in 0..8 {
+            floor.insert(uvec3(x, 0, z), Some(MaterialId(1)));
+        }
+    }
+    // A platform three voxels above the floor, reaching from 2 to 4.
+    let mut platform = SparseTensorChunk::nothing(uvec3(2, 2, 2));
+    for i in [
+        uvec3(0, 1, 0),
+        uvec3(1, 1, 0),
+        uvec3(0, 1, 1),
+        uvec3(1, 1, 1),
+    ] {
+        platform.insert(i, Some(MaterialId(1)));
+    }
+    platform.transform = Mat4::from_translation(vec3(2.0, 2.0, 2.0));
+
+    let mut scene = Scene::empty();
+    scene.add_terrain(floor);
+    scene.add_terrain(platform);
+
+    assert_eq!(scene.ground_height(vec2(2.5, 3.5), 10.0), Some(4.0));
+    assert_eq!(scene.ground_height(vec2(0.5, 0.5), 10.0), Some(1.0));
+    assert_eq!(scene.ground_height(vec2(-0.5, 0.5), 10.0), None);
+    assert_eq!(scene.ground_height(vec2(8.5, 0.5), 10.0), None);
+
+    // Below the platform it is an overhang, and the ground is the floor.
+    assert_eq!(scene.ground_height(vec2(2.5, 3.5), 1.0), Some(1.0));
+    assert_eq!(scene.ground_height(vec2(2.5, 3.5), 2.5), Some(1.0));
+    // A voxel starting below `top` is stepped onto.
+    assert_eq!(scene.ground_height(vec2(2.5, 3.5), 3.5), Some(4.0));
+    assert_eq!(scene.ground_height(vec2(2.5, 3.5), 0.5), Some(1.0));
+    assert_eq!(scene.ground_height(vec2(2.5, 3.5), 0.0), None);
+}
+
+#[test]
+fn camera_projection() {
+    let mut camera = Camera::new(vec3(1.0, 2.0, 3.0), 4.0 / 3.0);
+    camera.set_direction(vec3(1.0, 0.0, 1.0));
+
+    let (origin, direction) = camera.screen_to_world_ray(Vec2::ZERO);
+    assert!(direction.abs_diff_eq(camera.direction(), 1e-5));
+    assert!((origin - camera.translation())
+        .normalize()
+        .abs_diff_eq(camera.direction(), 1e-4));
+
+    let point = origin + direction * 10.0;
+    assert!(camera
+        .world_to_screen(point)
+        .unwrap()
+        .abs_diff_eq(Vec2::ZERO, 1e-5));
+    assert!(camera
+        .world_to_screen(camera.translation() - direction)
+        .is_none());
+
+    // A ray through the corner of the screen projects back onto the corner.
+    let (origin, direction) = camera.screen_to_world_ray(vec2(1.0, -1.0));
+    let corner = camera.world_to_screen(origin + direction * 5.0).unwrap();
+    assert!(corner.abs_diff_eq(vec2(1.0, -1.0), 1e-4));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "node 1 has a non-finite transform")]
+fn non_finite_transform() {
+    let mut g = SceneGraph::new();
+    let root = g.root();
+
+    let transform = Transform::from_translation(vec3(f32::NAN, 0.0, 0.0));
+    let a = g.insert_entity(Object::new(transform, Model::default()), &root);
+    g.insert_entity(Object::new(Transform::IDENTITY, Model::default()), &a);
+
+    g.evaluate_all();
+}
+
+#[test]
+fn camera_look_at() {
+    let mut camera = Camera::new(vec3(1.0, 2.0, 3.0), 1.0);
+    let target = vec3(4.0, 6.0, 3.0);
+
+    camera.look_at(target);
+    assert!(camera.direction().abs_diff_eq(vec3(0.6, 0.8, 0.0), 1e-6));
+
+    // The target ends up straight ahead, down the negative z axis in view space.
+    let view = camera.view().transform_point3(target);
+    assert!(view.truncate().abs_diff_eq(Vec2::ZERO, 1e-5));
+    assert!(view.z < 0.0);
+
+    camera.look_at(camera.translation());
+    assert!(camera.direction().abs_diff_eq(vec3(0.6, 0.8, 0.0), 1e-6));
+}
+
+#[test]
+fn camera_blend() {
+    let mut camera = Camera::new(Vec3::ZERO, 1.0);
+    camera.blend_towards(vec3(2.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 0.5);
+
+    let midpoint = vec3(1.0, 0.0, 1.0).normalize();
+    assert!(camera.direction().abs_diff_eq(midpoint, 1e-6));
+    assert!(camera.translation().abs_diff_eq(vec3(1.0, 0.0, 0.0), 1e-6));
+
+    camera.blend_towards(vec3(2.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 1.0);
+    assert!(camera.direction().abs_diff_eq(vec3(1.0, 0.0, 0.0), 1e-6));
+    assert!(camera.translation().abs_diff_eq(vec3(2.0, 0.0, 0.0), 1e-6));
+}
+
+#[test]
+fn camera_roll() {
+    let mut camera = Camera::new(Vec3::ZERO, 1.0);
+    camera.set_direction(vec3(1.0, 0.0, 1.0));
+    let (direction, up) = (camera.direction(), camera.up());
+
+    camera.set_roll(0.25);
+    assert_eq!(camera.direction(), direction);
+    assert!((camera.up().angle_between(up) - 0.25).abs() < 1e-5);
+    assert!(camera.up().dot(direction).abs() < 1e-6);
+    // The top of the view tilts to the right.
+    assert!(camera.up().dot(direction.cross(up)) > 0.0);
+
+    // The roll is kept when the camera turns.
+    camera.set_direction(vec3(0.0, 0.0, 1.0));
+    assert!((camera.up().angle_between(Vec3::Y) - 0.25).abs() < 1e-5);
+}
+
+#[test]
+fn camera_shake() {
+    let mut camera = Camera::new(Vec3::ZERO, 1.0);
+    camera.set_shake_duration(0.5);
+    camera.add_trauma(0.75);
+    camera.add_trauma(0.75);
+    assert_eq!(camera.trauma(), 1.0);
+
+    camera.update_shake(0.25);
+    assert_eq!(camera.trauma(), 0.5);
+    assert_ne!(camera.shake().translation, Vec3::ZERO);
+    assert_ne!(*camera.view(), camera.transform().to_mat4().inverse());
+
+    // The shake is gone once the trauma has worn off.
+    camera.update_shake(0.25);
+    assert_eq!(camera.trauma(), 0.0);
+    assert_eq!(*camera.shake(), Transform::IDENTITY);
+    assert!(camera
+        .view()
+        .abs_diff_eq(camera.transform().to_mat4().inverse(), 1e-6));
+
+    // The same moment is shaken by a quarter as much at half the trauma.
+    let mut weak = camera;
+    camera.add_trauma(1.0);
+    weak.add_trauma(0.5);
+    camera.update_shake(0.0);
+    weak.update_shake(0.0);
+    let ratio = weak.shake().translation.length() / camera.shake().translation.length();
+    assert!((ratio - 0.25).abs() < 1e-5, "{ratio}");
+}
+
+#[test]
+fn center_origin() {
+    let mut model = Model {
+        positions: vec![
+            (vec3(2.0, 4.0, 6.0), MaterialId(1)),
+            (vec3(5.0, 4.0, 7.0), MaterialId(1)),
+        ],
+        size: uvec3(8, 8, 8),
+        ..Default::default()
+    };
+
+    assert_eq!(model.center_origin(), vec3(-4.0, -4.5, -7.0));
+    let positions: Vec<_> = model.positions.iter().map(|&(p, _)| p).collect();
+    assert_eq!(positions, [vec3(-2.0, -0.5, -1.0), vec3(1.0, -0.5, 0.0)]);
+
+    // The voxels span from the lowest position to one past the highest.
+    let (min, max) = (
+        positions[0].min(positions[1]),
+        positions[0].max(positions[1]),
     );
+    assert_eq!(min + max + 1.0, Vec3::ZERO);
+
+    assert_eq!(model.center_origin(), Vec3::ZERO);
+}
+
+#[test]
+fn animated_model() {
+    let frame = |x: f32| {
+        Rc::new(Model {
+            positions: vec![(vec3(x, 0.0, 0.0), MaterialId(1))],
+            ..Default::default()
+        })
+    };
+    let animation = Rc::new(AnimatedModel::new(
+        vec![frame(0.0), frame(1.0), frame(2.0)],
+        4.0,
+    ));
+
+    let frames: Vec<_> = [0.0, 0.2, 0.25, 0.6, 0.75, 0.8, 1.3]
+        .map(|time| animation.frame(time))
+        .to_vec();
+    assert_eq!(frames, [0, 0, 1, 2, 0, 0, 2]);
+
+    let mut g = SceneGraph::new();
+    let root = g.root();
+    let mut object = Object::new(Transform::IDENTITY, Rc::clone(&animation.frames()[0]));
+    object.animation = Some((Rc::clone(&animation), 0.0));
+    let id = g.insert_entity(object, &root);
+
+    // The frame the object has reached is what gets drawn.
+    g.object_mut(&id).unwrap().animate(0.3);
+    g.evaluate_all();
+    match g.mutated_entity(&id) {
+        Some(Entity::Object(object)) => {
+            assert!(Rc::ptr_eq(&object.model, &animation.frames()[1]))
+        }
+        _ => unreachable!("the node is an object"),
+    }
+}
+
+#[test]
+#[should_panic(expected = "an animation needs at least one frame")]
+fn empty_animation() {
+    AnimatedModel::new(Vec::new(), 4.0);
+}
+
+#[test]
+fn camera_children() {
+    let mut scene = Scene::new(Camera::new(vec3(1.0, 2.0, 3.0), 1.0));
+    scene.camera_mut().set_direction(vec3(1.0, 0.0, 0.0));
+    let camera = *scene.camera();
+
+    // The view is the inverse of the world transform of the camera.
+    assert!((*camera.view() * camera.transform().to_mat4()).abs_diff_eq(Mat4::IDENTITY, 1e-5));
+    let forward = camera.transform().to_mat4().transform_vector3(-Vec3::Z);
+    assert!(forward.abs_diff_eq(camera.direction(), 1e-6));
+
+    // Something held one unit in front of the camera stays in front of it.
+    let held = Object::new(
+        Transform::from_translation(vec3(0.0, 0.0, -1.0)),
+        Model::default(),
+    );
+    let held = scene.scene_graph.insert_entity(held, &scene.camera);
+    scene.scene_graph.evaluate_all();
+
+    let Some(Entity::Object(object)) = scene.scene_graph.mutated_entity(&held) else {
+        panic!("the held object is missing");
+    };
+    let expected = *camera.transform() * Transform::from_translation(vec3(0.0, 0.0, -1.0));
+    assert!(object
+        .transform
+        .translation
+        .abs_diff_eq(expected.translation, 1e-5));
+    assert!(object
+        .transform
+        .translation
+        .abs_diff_eq(vec3(2.0, 2.0, 3.0), 1e-5));
+    assert!(object
+        .transform
+        .rotation
+        .abs_diff_eq(camera.transform().rotation, 1e-5));
 }
@@ -1,9 +1,34 @@
 use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
 use std::iter::FilterMap;
+use std::rc::Rc;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use glam::{IVec3, Mat4, UVec3, Vec3};
+
+use crate::scene::{MaterialId, Model, Object, Transform};
+
+/// The directions the six faces of a voxel point in.
+pub const FACES: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+/// The share of ambient light reaching a corner of a face, from whether the
+/// two voxels along the edges of the face next to the corner and the voxel
+/// diagonally across the corner are solid. Two solid sides hide the corner
+/// completely, however the diagonal voxel is.
+pub fn vertex_occlusion(side_u: bool, side_v: bool, corner: bool) -> f32 {
+    if side_u && side_v {
+        return 0.0;
+    }
 
-use glam::{Mat4, UVec3, Vec3, Vec4};
-
-use crate::scene::{MaterialId, Model, Object};
+    (3 - side_u as u8 - side_v as u8 - corner as u8) as f32 / 3.0
+}
 
 /// # Notes
 /// The tensor functionality will be used for:
@@ -14,16 +39,78 @@ pub struct SparseTensorChunk {
     pub data: HashMap<UVec3, MaterialId>,
     pub transform: Mat4,
     pub dim: UVec3,
+    edit: EditState,
+}
+
+/// Whether the edits of a [`SparseTensorChunk`] are being batched, see
+/// [`SparseTensorChunk::begin_edit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EditState {
+    Idle,
+    /// The smallest and largest corner of the voxels edited since the batch
+    /// began, if any.
+    Batching {
+        edited: Option<(UVec3, UVec3)>,
+    },
 }
 
 impl SparseTensorChunk {
     pub fn remove(&mut self, i: UVec3) {
         self.data.remove(&i);
+        self.record_edit(i);
     }
 
+    /// Inserting [`MaterialId::AIR`] clears the voxel at `i`.
+    ///
+    /// # Panics
+    /// If `i` is outside of the chunk.
     pub fn insert(&mut self, i: UVec3, vox: Option<MaterialId>) {
-        if let Some(vox) = vox {
-            self.data.insert(i, vox);
+        assert!(
+            self.in_bounds(i),
+            "voxel {i} is outside of a chunk of size {}",
+            self.dim
+        );
+        match vox {
+            Some(vox) if vox.is_air() => {
+                self.data.remove(&i);
+            }
+            Some(vox) => {
+                self.data.insert(i, vox);
+            }
+            None => return,
+        }
+        self.record_edit(i);
+    }
+
+    /// Starts a batch of edits, e.g. the voxels carved by an explosion, which
+    /// is compressed once by [`SparseTensorChunk::end_edit`] instead of after
+    /// every edit.
+    pub fn begin_edit(&mut self) {
+        if self.edit == EditState::Idle {
+            self.edit = EditState::Batching { edited: None };
+        }
+    }
+
+    /// Ends the batch of edits started by [`SparseTensorChunk::begin_edit`]
+    /// and compresses the chunk. Returns the smallest and largest corner of
+    /// the edited voxels, so whatever is derived from the chunk only has to
+    /// be updated in that range, or `None` if nothing was edited.
+    pub fn end_edit(&mut self) -> Option<(UVec3, UVec3)> {
+        let EditState::Batching { edited } = std::mem::replace(&mut self.edit, EditState::Idle)
+        else {
+            return None;
+        };
+        if edited.is_some() {
+            self.compress();
+        }
+        edited
+    }
+
+    fn record_edit(&mut self, i: UVec3) {
+        if let EditState::Batching { edited } = &mut self.edit {
+            let (min, max) = edited.get_or_insert((i, i));
+            *min = min.min(i);
+            *max = max.max(i);
         }
     }
 
@@ -39,11 +126,208 @@ impl SparseTensorChunk {
             dim,
             data: HashMap::new(),
             transform: Mat4::IDENTITY, //lower_bound: UVec3::ZERO,
+            edit: EditState::Idle,
+        }
+    }
+
+    /// Whether every component of `i` is inside `dim`.
+    pub fn in_bounds(&self, i: UVec3) -> bool {
+        i.cmplt(self.dim).all()
+    }
+
+    /// The position in world space of the corner of the voxel at `i`.
+    pub fn local_to_world(&self, i: UVec3) -> Vec3 {
+        self.transform.transform_point3(i.as_vec3())
+    }
+
+    /// The voxel containing the world space position `p`, or `None` if `p` is
+    /// outside of the chunk.
+    pub fn world_to_local(&self, p: Vec3) -> Option<UVec3> {
+        let local = self.transform.inverse().transform_point3(p);
+        // Negative components would saturate to zero when converted.
+        let i = local.cmpge(Vec3::ZERO).all().then(|| local.as_uvec3())?;
+        self.in_bounds(i).then_some(i)
+    }
+
+    /// Renders the layer `z` of the chunk as ASCII art, with `#` for voxels and
+    /// `.` for empty space. Rows are printed from the highest `y` down, so the
+    /// slice reads like a top-down map.
+    pub fn debug_slice(&self, z: u32) -> String {
+        let mut slice = String::new();
+        for y in (0..self.dim.y).rev() {
+            slice.push('|');
+            for x in 0..self.dim.x {
+                let occupied = self.voxel(UVec3::new(x, y, z)).is_some();
+                slice.push(if occupied { '#' } else { '.' });
+            }
+            slice.push_str("|\n");
         }
+
+        slice
+    }
+
+    /// Releases the memory left over from growing the chunk, e.g. after it was
+    /// built from many calls to [`combine`].
+    pub fn compress(&mut self) {
+        self.data.shrink_to_fit();
     }
 
-    fn iter(&self) -> impl Iterator<Item = (&UVec3, &MaterialId)> {
-        self.data.iter()
+    /// Every coordinate inside `dim` in linear index order, i.e. `x` changes
+    /// fastest and `z` slowest.
+    pub fn iter_coords(&self) -> impl Iterator<Item = UVec3> {
+        let dim = self.dim;
+        (0..dim.z).flat_map(move |z| {
+            (0..dim.y).flat_map(move |y| (0..dim.x).map(move |x| UVec3::new(x, y, z)))
+        })
+    }
+
+    /// The occupied voxels of the chunk, in no particular order.
+    pub fn iter_voxels(&self) -> impl Iterator<Item = (UVec3, MaterialId)> + '_ {
+        self.data.iter().map(|(&i, &material)| (i, material))
+    }
+
+    /// Serializes the chunk compactly for saving or sending it. After `dim`
+    /// and `transform`, the voxels are stored in the order of
+    /// [`SparseTensorChunk::iter_coords`] as runs: the amount of empty voxels,
+    /// the amount of occupied voxels and a byte with the material of each
+    /// occupied voxel. Voxels outside of `dim` aren't stored.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for v in self.dim.to_array() {
+            bytes.write_u32::<LittleEndian>(v).unwrap();
+        }
+        for v in self.transform.to_cols_array() {
+            bytes.write_f32::<LittleEndian>(v).unwrap();
+        }
+
+        let voxels: Vec<Option<u8>> = self
+            .iter_coords()
+            .map(|i| {
+                self.voxel(i)
+                    .map(|id| u8::try_from(id.0).expect("material ids fit in a byte"))
+            })
+            .collect();
+
+        let mut rest = &voxels[..];
+        while !rest.is_empty() {
+            let empty = rest.iter().take_while(|v| v.is_none()).count();
+            let occupied = rest[empty..].iter().take_while(|v| v.is_some()).count();
+            bytes.write_u32::<LittleEndian>(empty as _).unwrap();
+            bytes.write_u32::<LittleEndian>(occupied as _).unwrap();
+            bytes.extend(rest[empty..empty + occupied].iter().flatten());
+            rest = &rest[empty + occupied..];
+        }
+
+        bytes
+    }
+
+    /// Reads a chunk written by [`SparseTensorChunk::to_bytes`].
+    pub fn from_bytes(mut bytes: &[u8]) -> std::io::Result<Self> {
+        let mut dim = [0; 3];
+        bytes.read_u32_into::<LittleEndian>(&mut dim)?;
+        let mut transform = [0.0; 16];
+        bytes.read_f32_into::<LittleEndian>(&mut transform)?;
+
+        let mut chunk = Self::nothing(UVec3::from_array(dim));
+        chunk.transform = Mat4::from_cols_array(&transform);
+
+        let outside = || Error::new(ErrorKind::InvalidData, "voxels outside of the chunk");
+        let mut voxels = Vec::new();
+        {
+            let mut coords = chunk.iter_coords();
+            while !bytes.is_empty() {
+                let empty = bytes.read_u32::<LittleEndian>()? as usize;
+                let occupied = bytes.read_u32::<LittleEndian>()? as usize;
+                if coords.by_ref().take(empty).count() != empty {
+                    return Err(outside());
+                }
+
+                for _ in 0..occupied {
+                    let i = coords.next().ok_or_else(outside)?;
+                    voxels.push((i, MaterialId(bytes.read_u8()? as _)));
+                }
+            }
+        }
+
+        for (i, id) in voxels {
+            chunk.insert(i, Some(id));
+        }
+        Ok(chunk)
+    }
+
+    /// The faces of the voxels which aren't covered by another voxel, as the
+    /// voxel and the direction of the face. Whether the voxel next to a face
+    /// on the boundary of the chunk is solid is asked from `solid`, with the
+    /// local coordinate of that voxel outside of the chunk.
+    pub fn exposed_faces(&self, solid: impl Fn(IVec3) -> bool) -> Vec<(UVec3, IVec3)> {
+        let mut faces = Vec::new();
+        for (i, _) in self.iter_voxels() {
+            for direction in FACES {
+                if !self.is_solid(i.as_ivec3() + direction, &solid) {
+                    faces.push((i, direction));
+                }
+            }
+        }
+
+        faces
+    }
+
+    /// Whether the voxel at `i` is occupied, where voxels outside of the chunk
+    /// are asked from `solid`.
+    fn is_solid(&self, i: IVec3, solid: &impl Fn(IVec3) -> bool) -> bool {
+        match i.cmpge(IVec3::ZERO).all() && self.in_bounds(i.as_uvec3()) {
+            true => self.voxel(i.as_uvec3()).is_some(),
+            false => solid(i),
+        }
+    }
+
+    /// How much ambient light reaches each corner of the face of the voxel
+    /// `i` pointing in `direction`, judged by the three voxels in front of the
+    /// face around the corner. Voxels outside of the chunk are asked from
+    /// `solid` like in [`SparseTensorChunk::exposed_faces`].
+    ///
+    /// The face spans the two axes following the axis of `direction`, `u` and
+    /// `v`, and its corners are in the order `(-u, -v)`, `(u, -v)`, `(u, v)`
+    /// and `(-u, v)`.
+    pub fn face_occlusion(
+        &self,
+        i: UVec3,
+        direction: IVec3,
+        solid: impl Fn(IVec3) -> bool,
+    ) -> [f32; 4] {
+        let axis = direction
+            .abs()
+            .to_array()
+            .iter()
+            .position(|&d| d != 0)
+            .unwrap();
+        let u = IVec3::AXES[(axis + 1) % 3];
+        let v = IVec3::AXES[(axis + 2) % 3];
+
+        let front = i.as_ivec3() + direction;
+        [(-1, -1), (1, -1), (1, 1), (-1, 1)].map(|(su, sv)| {
+            let side_u = self.is_solid(front + su * u, &solid);
+            let side_v = self.is_solid(front + sv * v, &solid);
+            let corner = self.is_solid(front + su * u + sv * v, &solid);
+            vertex_occlusion(side_u, side_v, corner)
+        })
+    }
+
+    /// [`SparseTensorChunk::exposed_faces`] where the faces on the boundary of
+    /// the chunk are covered by the voxels of `neighbors`, in world space.
+    pub fn exposed_faces_between(&self, neighbors: &[&SparseTensorChunk]) -> Vec<(UVec3, IVec3)> {
+        self.exposed_faces(|next| self.solid_between(neighbors, next))
+    }
+
+    /// Whether the voxel at the local coordinate `next` outside of the chunk
+    /// is covered by a voxel of `neighbors`, judged by its center in world
+    /// space.
+    pub fn solid_between(&self, neighbors: &[&SparseTensorChunk], next: IVec3) -> bool {
+        let center = self.transform.transform_point3(next.as_vec3() + 0.5);
+        neighbors.iter().any(|neighbor| {
+            let i = neighbor.world_to_local(center);
+            i.is_some_and(|i| neighbor.voxel(i).is_some())
+        })
     }
 
     // pub fn from_model(model: &[(UVec3, MaterialId)], dim: UVec3) -> Self {
@@ -69,11 +353,20 @@ impl SparseTensorChunk {
 
 impl From<Model> for SparseTensorChunk {
     fn from(value: Model) -> Self {
-        let mut temp = Self::nothing(value.size);
+        let voxels: Vec<_> = value
+            .positions
+            .into_iter()
+            .map(|(position, material_id)| (position.as_uvec3(), material_id))
+            .collect();
+
+        // Transformed positions may lie outside of the size of the model.
+        let dim = voxels
+            .iter()
+            .fold(value.size, |dim, &(i, _)| dim.max(i + 1));
+        let mut temp = Self::nothing(dim);
         temp.transform *= value.transform;
 
-        for (position, material_id) in value.positions {
-            let index = UVec3::from_array(position.to_array().map(|v| v as _));
+        for (index, material_id) in voxels {
             temp.insert(index, Some(material_id));
         }
         temp
@@ -81,18 +374,17 @@ impl From<Model> for SparseTensorChunk {
 }
 
 impl From<Object> for SparseTensorChunk {
-    fn from(mut value: Object) -> Self {
-        if value.transform == Mat4::IDENTITY {
-            SparseTensorChunk::from(value.model)
-        } else {
-            value
-                .model
+    fn from(value: Object) -> Self {
+        let mut model = Rc::unwrap_or_clone(value.model);
+        if value.transform != Transform::IDENTITY {
+            let transform = value.transform.to_mat4();
+            model
                 .positions
                 .iter_mut()
-                .for_each(|(position, _)| *position = value.transform.transform_point3(*position));
-
-            SparseTensorChunk::from(value.model)
+                .for_each(|(position, _)| *position = transform.transform_point3(*position));
         }
+
+        SparseTensorChunk::from(model)
     }
 }
 
@@ -105,27 +397,29 @@ impl<'a> IntoIterator for &'a SparseTensorChunk {
     }
 }
 
-/// *DOES NOT AUTO-COMPRESS*
+/// Merges two chunks into one in the space of their transforms. Voxels of `b`
+/// replace voxels of `a` at the same position.
+///
+/// *DOES NOT AUTO-COMPRESS*, see [`SparseTensorChunk::compress`].
 pub fn combine(a: SparseTensorChunk, b: SparseTensorChunk) -> SparseTensorChunk {
-    let v4 = |v: Vec3| Vec4::from_array([v.x, v.y, v.z, 1.]);
-    let v3 = |v: Vec4| Vec3::from_slice(&v.to_array()[0..3]).as_uvec3();
+    let voxels_a = a
+        .into_iter()
+        .map(|(i, material_id)| (a.local_to_world(i).as_uvec3(), material_id));
+    let voxels_b = b
+        .into_iter()
+        .map(|(i, material_id)| (b.local_to_world(i).as_uvec3(), material_id));
+    let voxels: Vec<_> = voxels_a.chain(voxels_b).collect();
 
-    let mut dim = UVec3::ZERO;
+    let dim = voxels
+        .iter()
+        .fold(UVec3::ZERO, |dim, &(i, _)| dim.max(i + 1));
     let mut c = SparseTensorChunk::nothing(dim);
-
-    let map = |t: Mat4| move |(a, b): (UVec3, MaterialId)| (v3(t * v4(a.as_vec3())), b);
-
-    for (position, material_id) in a
-        .into_iter()
-        .map(map(a.transform))
-        .chain(b.into_iter().map(map(b.transform)))
-    {
-        let index = UVec3::from_array(position.to_array().map(|v| v as _));
-        dim = dim.max(index);
+    // Growing the map once up front saves rehashing it as it fills. Overlapping
+    // voxels make this an overestimate, which `compress` gives back.
+    c.data.reserve(voxels.len());
+    for (index, material_id) in voxels {
         c.insert(index, Some(material_id));
     }
-
-    c.dim = dim;
     c
 }
 
@@ -238,3 +532,239 @@ mod test {
     }
 }
 */
+
+#[test]
+fn debug_slice() {
+    let mut chunk = SparseTensorChunk::nothing(UVec3::new(4, 3, 2));
+    chunk.insert(UVec3::new(0, 0, 0), Some(MaterialId(1)));
+    chunk.insert(UVec3::new(3, 0, 0), Some(MaterialId(1)));
+    chunk.insert(UVec3::new(1, 2, 0), Some(MaterialId(2)));
+    chunk.insert(UVec3::new(2, 1, 1), Some(MaterialId(1)));
+
+    assert_eq!(chunk.debug_slice(0), "|.#..|\n|....|\n|#..#|\n");
+    assert_eq!(chunk.debug_slice(1), "|....|\n|..#.|\n|....|\n");
+}
+
+#[test]
+fn bounds() {
+    let chunk = SparseTensorChunk::nothing(UVec3::new(4, 5, 6));
+
+    assert!(chunk.in_bounds(UVec3::ZERO));
+    assert!(chunk.in_bounds(UVec3::new(3, 4, 5)));
+
+    // One past the last voxel on every axis separately.
+    assert!(!chunk.in_bounds(UVec3::new(4, 4, 5)));
+    assert!(!chunk.in_bounds(UVec3::new(3, 5, 5)));
+    assert!(!chunk.in_bounds(UVec3::new(3, 4, 6)));
+
+    // The linear index of this voxel would be in range, but it isn't.
+    assert!(!chunk.in_bounds(UVec3::new(5, 0, 0)));
+}
+
+#[test]
+#[should_panic(expected = "voxel [4, 0, 0] is outside of a chunk of size [4, 5, 6]")]
+fn insert_out_of_bounds() {
+    let mut chunk = SparseTensorChunk::nothing(UVec3::new(4, 5, 6));
+    chunk.insert(UVec3::new(3, 4, 5), Some(MaterialId(1)));
+    chunk.insert(UVec3::new(4, 0, 0), Some(MaterialId(1)));
+}
+
+#[test]
+fn local_world_round_trip() {
+    let mut chunk = SparseTensorChunk::nothing(UVec3::new(4, 5, 6));
+    chunk.transform = Mat4::from_translation(Vec3::new(10.0, -3.0, 2.0))
+        * Mat4::from_rotation_z(std::f32::consts::FRAC_PI_2);
+
+    let world = chunk.local_to_world(UVec3::new(1, 2, 3));
+    assert!(world.abs_diff_eq(Vec3::new(8.0, -2.0, 5.0), 1e-5));
+
+    // The corner of a voxel may round into its neighbour, so the center is
+    // converted back instead.
+    let half = chunk.transform.transform_vector3(Vec3::splat(0.5));
+    for i in (0..4 * 5 * 6).map(|i| UVec3::new(i % 4, i / 4 % 5, i / 20)) {
+        assert_eq!(
+            chunk.world_to_local(chunk.local_to_world(i) + half),
+            Some(i)
+        );
+    }
+
+    assert_eq!(chunk.world_to_local(Vec3::new(10.5, -2.5, 2.5)), None);
+    let outside = chunk.local_to_world(UVec3::new(1, 5, 1)) + half;
+    assert_eq!(chunk.world_to_local(outside), None);
+}
+
+#[test]
+fn compressed_combine() {
+    let mut cube = SparseTensorChunk::nothing(UVec3::splat(4));
+    for i in 0..64 {
+        cube.insert(UVec3::new(i % 4, i / 4 % 4, i / 16), Some(MaterialId(1)));
+    }
+
+    // Terrain cells overlap, so most voxels of the combined chunks coincide.
+    let empty = SparseTensorChunk::nothing(UVec3::ZERO);
+    let mut combined = std::iter::repeat_n(cube.clone(), 8).fold(empty, combine);
+    let capacity = combined.data.capacity();
+    combined.compress();
+    assert!(combined.data.capacity() * 2 <= capacity);
+    assert_eq!(combined.dim, cube.dim);
+    assert_eq!(combined.data, cube.data);
+
+    // Chunks are merged in world space, where the voxels of the later chunk
+    // replace the ones they overlap.
+    let mut a = SparseTensorChunk::nothing(UVec3::new(2, 1, 1));
+    a.insert(UVec3::new(0, 0, 0), Some(MaterialId(1)));
+    a.insert(UVec3::new(1, 0, 0), Some(MaterialId(1)));
+    let mut b = SparseTensorChunk::nothing(UVec3::ONE);
+    b.insert(UVec3::ZERO, Some(MaterialId(2)));
+    b.transform = Mat4::from_translation(Vec3::X);
+
+    let c = combine(a, b);
+    assert_eq!(c.dim, UVec3::new(2, 1, 1));
+    assert_eq!(c.voxel(UVec3::new(0, 0, 0)), Some(&MaterialId(1)));
+    assert_eq!(c.voxel(UVec3::new(1, 0, 0)), Some(&MaterialId(2)));
+    assert_eq!(c.data.len(), 2);
+}
+
+#[test]
+fn iter_coords() {
+    let chunk = SparseTensorChunk::nothing(UVec3::new(2, 3, 4));
+    let coords: Vec<_> = chunk.iter_coords().collect();
+
+    assert_eq!(coords.len(), 2 * 3 * 4);
+    assert_eq!(
+        coords[..3],
+        [
+            UVec3::new(0, 0, 0),
+            UVec3::new(1, 0, 0),
+            UVec3::new(0, 1, 0)
+        ]
+    );
+    assert_eq!(coords[6], UVec3::new(0, 0, 1));
+    assert_eq!(coords.last(), Some(&UVec3::new(1, 2, 3)));
+}
+
+#[test]
+fn iter_voxels() {
+    let mut chunk = SparseTensorChunk::nothing(UVec3::splat(4));
+    chunk.insert(UVec3::new(1, 2, 3), Some(MaterialId(2)));
+    chunk.insert(UVec3::new(0, 0, 0), Some(MaterialId(1)));
+
+    let mut voxels: Vec<_> = chunk.iter_voxels().collect();
+    voxels.sort_by_key(|(_, material)| material.0);
+    assert_eq!(
+        voxels,
+        [
+            (UVec3::new(0, 0, 0), MaterialId(1)),
+            (UVec3::new(1, 2, 3), MaterialId(2))
+        ]
+    );
+}
+
+#[test]
+fn batched_edits() {
+    let mut full = SparseTensorChunk::nothing(UVec3::splat(5));
+    for i in full.iter_coords().collect::<Vec<_>>() {
+        full.insert(i, Some(MaterialId(1)));
+    }
+    let carved: Vec<_> = full.iter_coords().skip(20).take(50).collect();
+
+    let mut one_by_one = full.clone();
+    for &i in &carved {
+        one_by_one.remove(i);
+        one_by_one.compress();
+    }
+
+    let mut batched = full;
+    batched.begin_edit();
+    for &i in &carved {
+        batched.remove(i);
+    }
+    let range = batched.end_edit();
+
+    assert_eq!(batched.data, one_by_one.data);
+    // The memory of the carved voxels was given back.
+    assert!(batched.data.capacity() < 125);
+    assert_eq!(range, Some((UVec3::new(0, 0, 0), UVec3::new(4, 4, 2))));
+    assert_eq!(batched.end_edit(), None);
+}
+
+#[test]
+fn bytes_round_trip() {
+    let mut row = SparseTensorChunk::nothing(UVec3::new(3, 1, 1));
+    row.insert(UVec3::new(1, 0, 0), Some(MaterialId(7)));
+    let bytes = row.to_bytes();
+    // The header holds the dimensions and the transform.
+    let header = 3 * 4 + 16 * 4;
+    assert_eq!(bytes[..4], [3, 0, 0, 0]);
+    assert_eq!(
+        bytes[header..],
+        [1, 0, 0, 0, 1, 0, 0, 0, 7, 1, 0, 0, 0, 0, 0, 0, 0]
+    );
+
+    let mut chunk = SparseTensorChunk::nothing(UVec3::new(4, 3, 2));
+    chunk.transform = Mat4::from_translation(Vec3::new(1.0, -2.0, 0.5));
+    for (x, y, z, material) in [
+        (0, 0, 0, 1),
+        (1, 0, 0, 2),
+        (3, 2, 0, 1),
+        (0, 0, 1, 3),
+        (3, 2, 1, 255),
+    ] {
+        chunk.insert(UVec3::new(x, y, z), Some(MaterialId(material)));
+    }
+
+    let bytes = chunk.to_bytes();
+    let decoded = SparseTensorChunk::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded.data, chunk.data);
+    assert_eq!(
+        (decoded.dim, decoded.transform),
+        (chunk.dim, chunk.transform)
+    );
+    assert_eq!(decoded.to_bytes(), bytes);
+
+    assert!(SparseTensorChunk::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn neighbor_faces() {
+    let mut a = SparseTensorChunk::nothing(UVec3::splat(2));
+    for i in a.iter_coords().collect::<Vec<_>>() {
+        a.insert(i, Some(MaterialId(1)));
+    }
+    let mut b = a.clone();
+    b.transform = Mat4::from_translation(Vec3::new(2.0, 0.0, 0.0));
+
+    // A lone cube shows its four faces on each of the six sides.
+    assert_eq!(a.exposed_faces(|_| false).len(), 6 * 4);
+
+    let faces = a.exposed_faces_between(&[&b]);
+    assert_eq!(faces.len(), 5 * 4);
+    assert!(faces.iter().all(|&(_, direction)| direction != IVec3::X));
+
+    let faces = b.exposed_faces_between(&[&a]);
+    assert_eq!(faces.len(), 5 * 4);
+    assert!(faces
+        .iter()
+        .all(|&(_, direction)| direction != IVec3::NEG_X));
+}
+
+#[test]
+fn ambient_occlusion() {
+    assert_eq!(vertex_occlusion(false, false, false), 1.0);
+    assert_eq!(vertex_occlusion(false, true, true), 1.0 / 3.0);
+    assert_eq!(vertex_occlusion(true, true, false), 0.0);
+
+    // The top face spans z and x. Two solid voxels on its -z and -x edges meet
+    // at the first corner and darken the corners next to it as well.
+    let mut chunk = SparseTensorChunk::nothing(UVec3::splat(3));
+    chunk.insert(UVec3::new(1, 1, 1), Some(MaterialId(1)));
+    chunk.insert(UVec3::new(1, 2, 0), Some(MaterialId(1)));
+    chunk.insert(UVec3::new(0, 2, 1), Some(MaterialId(1)));
+
+    let top = chunk.face_occlusion(UVec3::new(1, 1, 1), IVec3::Y, |_| false);
+    assert_eq!(top, [0.0, 2.0 / 3.0, 1.0, 2.0 / 3.0]);
+
+    // Voxels beyond the chunk are asked for, here a wall from the top layer up.
+    let front = chunk.face_occlusion(UVec3::new(1, 2, 0), IVec3::NEG_Z, |i| i.y >= 2);
+    assert_eq!(front, [2.0 / 3.0, 2.0 / 3.0, 0.0, 0.0]);
+}
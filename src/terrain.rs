@@ -1,12 +1,19 @@
 use std::ops::{Range, RangeBounds};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
 
 use glam::{vec3, Mat4, UVec3, Vec3};
 
-use crate::format::vox::{self, VoxModel};
-use crate::scene::Model;
+use crate::assets::AssetManager;
+use crate::format::vox::VoxModel;
+use crate::rng::Rng;
+use crate::scene::{Scene, TerrainId};
 use crate::tensor::{self, SparseTensorChunk};
 
-const FOV: usize = 6; // Must be even
+/// The amount of cells along each side of a [`MapBlock`], which is also the
+/// default of the streamer, see [`TerrainStreamer::with_fov`].
+const FOV: usize = 6;
 const CUBICAL_SIZE: u32 = 40;
 const SEED: f32 = 123.4;
 
@@ -20,13 +27,14 @@ fn random(v: Vec3, r: Range<usize>, variant: usize) -> usize {
         _ => panic!("invalid bound for random number generation"),
     } - a;
 
-    let x = (v.x * SEED).abs() as usize;
-    let y = (v.y * SEED).abs() as usize + variant;
-    let z = (v.z * SEED).abs() as usize;
+    // Every position seeds its own generator, so a cell gets the same asset
+    // no matter in which order the cells are generated.
+    let x = (v.x * SEED).abs() as u64;
+    let y = (v.y * SEED).abs() as u64;
+    let z = (v.z * SEED).abs() as u64;
+    let mut rng = Rng::new((x << 42) ^ (y << 21) ^ z ^ ((variant as u64) << 60));
 
-    let r = (x | z) & y;
-
-    r % b + a
+    rng.range(0..b as u32) as usize + a
 }
 
 macro_rules! assets {
@@ -66,12 +74,11 @@ assets!(
 );
 
 impl Asset {
-    fn chunk(&self, translation: Vec3) -> SparseTensorChunk {
-        let rotate_90 = Mat4::from_rotation_z(std::f32::consts::FRAC_PI_2);
+    fn chunk(&self, translation: Vec3, assets: &mut AssetManager) -> SparseTensorChunk {
         let transform = Mat4::from_translation(translation);
 
-        let path = self.path();
-        let mut chunk = SparseTensorChunk::from(Model::from(vox::open(path).0[0].clone()));
+        // Every cell with the same asset starts out as a copy of one cached chunk.
+        let mut chunk = SparseTensorChunk::clone(&assets.load_chunk(self.path()));
 
         chunk.transform *= transform;
         chunk
@@ -79,7 +86,13 @@ impl Asset {
 }
 
 fn blk_pos(x: usize, y: usize, center: Vec3) -> Vec3 {
-    let min = (FOV as f32 / -2.) * CUBICAL_SIZE as f32;
+    cell_pos(x, y, center, FOV)
+}
+
+/// The position of cell `x`, `y` of a block of `fov` by `fov` cells around
+/// `center`.
+fn cell_pos(x: usize, y: usize, center: Vec3, fov: usize) -> Vec3 {
+    let min = (fov as f32 / -2.) * CUBICAL_SIZE as f32;
     let min = vec3(min, 0., min);
     let p = vec3(
         x as f32 * CUBICAL_SIZE as f32,
@@ -90,6 +103,18 @@ fn blk_pos(x: usize, y: usize, center: Vec3) -> Vec3 {
     center + min + p
 }
 
+/// The positions of the cells of a block of `fov` by `fov` cells around
+/// `center`.
+fn cells(center: Vec3, fov: usize) -> impl Iterator<Item = Vec3> {
+    (0..fov).flat_map(move |y| (0..fov).map(move |x| cell_pos(x, y, center, fov)))
+}
+
+/// The asset placed in the cell at `pos`, which is the same for every block
+/// containing the cell.
+fn cell_asset(pos: Vec3) -> Asset {
+    ASSETS[random(pos, 0..ASSETS.len(), 0)]
+}
+
 pub struct TerrainMask([[bool; FOV]; FOV]);
 pub const EMPTY_MASK: TerrainMask = TerrainMask([[true; FOV]; FOV]);
 
@@ -104,8 +129,7 @@ impl MapBlock {
 
         for y in 0..FOV {
             for x in 0..FOV {
-                let blk_pos = blk_pos(x, y, pos);
-                data[y][x] = ASSETS[random(blk_pos, 0..ASSETS.len(), 0)]
+                data[y][x] = cell_asset(blk_pos(x, y, pos));
             }
         }
 
@@ -120,7 +144,7 @@ impl MapBlock {
         for y in 0..FOV {
             for x in 0..FOV {
                 let new_pos = blk_pos(x, y, self.center);
-                if old_pos.abs_diff_eq(new_pos, CUBICAL_SIZE as f32) {
+                if Self::contains(old_pos, new_pos) {
                     tmp.0[y][x] = false;
                 }
             }
@@ -129,22 +153,234 @@ impl MapBlock {
         tmp
     }
 
-    pub fn gen_terrain(&self, mask: TerrainMask) -> SparseTensorChunk {
+    /// Whether the map block centered at `center` has a cell at `pos`.
+    fn contains(center: Vec3, pos: Vec3) -> bool {
+        cells(center, FOV).any(|cell| cell.abs_diff_eq(pos, 0.5))
+    }
+
+    pub fn gen_terrain(&self, mask: TerrainMask, assets: &mut AssetManager) -> SparseTensorChunk {
         let mut ret = SparseTensorChunk::nothing(UVec3::ZERO);
 
         for y in 0..FOV {
             for x in 0..FOV {
                 if mask.0[y][x] {
                     let pos = blk_pos(x, y, self.center);
-                    ret = tensor::combine(ret, self.data[y][x].chunk(pos));
+                    ret = tensor::combine(ret, self.data[y][x].chunk(pos, assets));
                 }
             }
         }
 
+        ret.compress();
         ret
     }
 }
 
+/// Generates the chunks of map block cells on background threads, so parsing
+/// and copying them doesn't stall the main thread. Every thread takes the next
+/// request as soon as it is done with its last one and has its own asset
+/// cache, and finished chunks are handed back through a channel.
+pub struct ChunkLoader {
+    requests: Sender<(Vec3, Asset)>,
+    chunks: Receiver<(Vec3, SparseTensorChunk)>,
+    _threads: Vec<JoinHandle<()>>,
+}
+
+impl ChunkLoader {
+    /// Generates chunks on as many threads as there are cores.
+    pub fn new() -> Self {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_threads(threads)
+    }
+
+    pub fn with_threads(threads: usize) -> Self {
+        let (requests, received) = channel::<(Vec3, Asset)>();
+        let received = Arc::new(Mutex::new(received));
+        let (finished, chunks) = channel();
+
+        // The threads stop once the loader, and with it the sender, is dropped.
+        let _threads = (0..threads.max(1))
+            .map(|_| {
+                let received = Arc::clone(&received);
+                let finished = finished.clone();
+                std::thread::spawn(move || {
+                    let mut assets = AssetManager::default();
+                    loop {
+                        // The lock is only held while waiting for a request.
+                        let request = received.lock().unwrap().recv();
+                        let Ok((pos, asset)) = request else {
+                            break;
+                        };
+                        if finished.send((pos, asset.chunk(pos, &mut assets))).is_err() {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            requests,
+            chunks,
+            _threads,
+        }
+    }
+
+    /// Queues the chunk of the cell at `pos` holding `asset`. This never waits
+    /// for the chunk to be generated.
+    fn request(&self, pos: Vec3, asset: Asset) {
+        self.requests.send((pos, asset)).unwrap();
+    }
+
+    /// A chunk that finished generating together with the position of its
+    /// cell, if any finished since the last call.
+    pub fn poll(&self) -> Option<(Vec3, SparseTensorChunk)> {
+        self.chunks.try_recv().ok()
+    }
+
+    /// Waits for the next chunk to finish generating.
+    pub fn wait(&self) -> Option<(Vec3, SparseTensorChunk)> {
+        self.chunks.recv().ok()
+    }
+}
+
+impl Default for ChunkLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keeps the terrain around the camera loaded, one scene terrain chunk per
+/// cell of the block of cells around the camera.
+pub struct TerrainStreamer {
+    center: Vec3,
+    fov: usize,
+    cells: Vec<(Vec3, TerrainId)>,
+    /// The cells whose chunks are being generated by the loader.
+    pending: Vec<Vec3>,
+    loader: ChunkLoader,
+}
+
+impl TerrainStreamer {
+    /// Loads the cells around the camera, see [`TerrainStreamer::with_fov`].
+    pub fn new(scene: &mut Scene, assets: &mut AssetManager) -> Self {
+        Self::with_fov(scene, assets, FOV)
+    }
+
+    /// Loads a block of `fov` by `fov` cells around the camera. The camera is
+    /// in between the middle cells, so an odd `fov` is rounded up to the next
+    /// even number. The first block is loaded right away, so there is terrain
+    /// from the first frame on.
+    pub fn with_fov(scene: &mut Scene, assets: &mut AssetManager, fov: usize) -> Self {
+        let center = closest_block(scene.camera().position);
+        let mut streamer = Self {
+            center,
+            fov: Self::even(fov),
+            cells: Vec::new(),
+            pending: Vec::new(),
+            loader: ChunkLoader::new(),
+        };
+
+        for pos in cells(center, streamer.fov) {
+            let id = scene.add_terrain(cell_asset(pos).chunk(pos, assets));
+            streamer.cells.push((pos, id));
+        }
+        streamer
+    }
+
+    fn even(fov: usize) -> usize {
+        fov.max(1).next_multiple_of(2)
+    }
+
+    pub fn fov(&self) -> usize {
+        self.fov
+    }
+
+    /// Changes how many cells are loaded along each side, e.g. to lower the
+    /// draw distance. Odd values are rounded up like in
+    /// [`TerrainStreamer::with_fov`]. The cells are streamed in by
+    /// [`TerrainStreamer::update`].
+    pub fn set_fov(&mut self, fov: usize, scene: &mut Scene) {
+        self.fov = Self::even(fov);
+        self.move_to(self.center, scene);
+    }
+
+    /// Moves the block of cells along with the camera. Cells that left the
+    /// block are removed from the scene and only the newly exposed cells are
+    /// requested from the background loader.
+    ///
+    /// Returns the number of cells whose chunks arrived and were added to the
+    /// scene.
+    pub fn update(&mut self, scene: &mut Scene) -> usize {
+        let center = closest_block(scene.camera().position);
+        if center != self.center {
+            self.move_to(center, scene);
+        }
+
+        let mut loaded = 0;
+        while let Some((pos, chunk)) = self.loader.poll() {
+            loaded += self.receive(pos, chunk, scene) as usize;
+        }
+
+        loaded
+    }
+
+    /// Waits until every requested chunk is generated and adds them to the
+    /// scene, so the terrain doesn't depend on how long generating takes.
+    /// Returns how many chunks were added.
+    pub fn finish(&mut self, scene: &mut Scene) -> usize {
+        let mut loaded = 0;
+        while !self.pending.is_empty() {
+            let Some((pos, chunk)) = self.loader.wait() else {
+                break;
+            };
+            loaded += self.receive(pos, chunk, scene) as usize;
+        }
+
+        loaded
+    }
+
+    /// Adds the generated chunk of the cell at `pos` to the scene, unless the
+    /// camera has moved on while it was generated.
+    fn receive(&mut self, pos: Vec3, chunk: SparseTensorChunk, scene: &mut Scene) -> bool {
+        self.pending.retain(|pending| *pending != pos);
+        if !self.contains(pos) {
+            return false;
+        }
+
+        self.cells.push((pos, scene.add_terrain(chunk)));
+        true
+    }
+
+    fn contains(&self, pos: Vec3) -> bool {
+        cells(self.center, self.fov).any(|cell| cell.abs_diff_eq(pos, 0.5))
+    }
+
+    fn move_to(&mut self, center: Vec3, scene: &mut Scene) {
+        self.center = center;
+
+        let mut cells = std::mem::take(&mut self.cells);
+        cells.retain(|(pos, id)| {
+            let keep = self.contains(*pos);
+            if !keep {
+                scene.remove_terrain(id);
+            }
+            keep
+        });
+        self.cells = cells;
+
+        for pos in self::cells(center, self.fov) {
+            let loaded = self
+                .cells
+                .iter()
+                .any(|(cell, _)| cell.abs_diff_eq(pos, 0.5));
+            if !loaded && !self.pending.contains(&pos) {
+                self.loader.request(pos, cell_asset(pos));
+                self.pending.push(pos);
+            }
+        }
+    }
+}
+
 impl std::fmt::Debug for MapBlock {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let len = ASSETS.iter().map(|a| format!("{a:?}").len()).max().unwrap() + 3;
@@ -199,3 +435,142 @@ fn block_coordinates() {
         vec3(2. * CUBICAL_SIZE as f32, 1., 2. * CUBICAL_SIZE as f32)
     );
 }
+
+#[test]
+fn streaming() {
+    use crate::scene::Camera;
+
+    let mut scene = Scene::new(Camera::new(vec3(1., 2., 3.), 1.0));
+    let mut assets = AssetManager::default();
+
+    let mut streamer = TerrainStreamer::new(&mut scene, &mut assets);
+    assert_eq!(scene.terrain().count(), FOV * FOV);
+    assert_eq!(streamer.update(&mut scene), 0);
+
+    scene
+        .camera_mut()
+        .translate(vec3(CUBICAL_SIZE as f32, 0., 0.));
+    let mut loaded = streamer.update(&mut scene);
+    let start = std::time::Instant::now();
+    while loaded < FOV {
+        assert!(start.elapsed().as_secs() < 10, "the cells never arrived");
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        loaded += streamer.update(&mut scene);
+    }
+    assert_eq!(loaded, FOV);
+    assert_eq!(scene.terrain().count(), FOV * FOV);
+    assert_eq!(streamer.update(&mut scene), 0);
+}
+
+#[test]
+fn streaming_fov() {
+    use crate::scene::Camera;
+
+    let mut scene = Scene::new(Camera::new(vec3(1., 2., 3.), 1.0));
+    let mut assets = AssetManager::default();
+
+    let mut streamer = TerrainStreamer::with_fov(&mut scene, &mut assets, 3);
+    assert_eq!(streamer.fov(), 4);
+    assert_eq!(scene.terrain().count(), 4 * 4);
+
+    let stream_in = |streamer: &mut TerrainStreamer, scene: &mut Scene, count: usize| {
+        let start = std::time::Instant::now();
+        while scene.terrain().count() < count {
+            assert!(start.elapsed().as_secs() < 10, "the cells never arrived");
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            streamer.update(scene);
+        }
+        scene.terrain().count()
+    };
+
+    streamer.set_fov(6, &mut scene);
+    assert_eq!(stream_in(&mut streamer, &mut scene, 6 * 6), 6 * 6);
+    streamer.set_fov(2, &mut scene);
+    assert_eq!(stream_in(&mut streamer, &mut scene, 2 * 2), 2 * 2);
+}
+
+#[test]
+fn parallel_loading() {
+    let loader = ChunkLoader::with_threads(4);
+    let positions: Vec<_> = (0..ASSETS.len())
+        .map(|i| vec3(i as f32 * CUBICAL_SIZE as f32, 0., 0.))
+        .collect();
+    for (&pos, &asset) in positions.iter().zip(ASSETS) {
+        loader.request(pos, asset);
+    }
+
+    // Every chunk arrives exactly once, in whatever order the threads finish.
+    let mut loaded: Vec<_> = (0..ASSETS.len())
+        .map(|_| loader.wait().unwrap().0)
+        .collect();
+    loaded.sort_by(|a, b| a.x.total_cmp(&b.x));
+    assert_eq!(loaded, positions);
+    assert!(loader.poll().is_none());
+}
+
+#[test]
+fn background_loading() {
+    let loader = ChunkLoader::new();
+    let pos = vec3(40., 0., 80.);
+    loader.request(pos, Asset::floor);
+
+    let start = std::time::Instant::now();
+    let (loaded_pos, chunk) = loop {
+        if let Some(loaded) = loader.poll() {
+            break loaded;
+        }
+        assert!(start.elapsed().as_secs() < 10, "the chunk never arrived");
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    };
+
+    assert_eq!(loaded_pos, pos);
+    assert!(!chunk.data.is_empty());
+    assert!(loader.poll().is_none());
+}
+
+#[cfg(test)]
+extern crate test;
+
+#[bench]
+fn generate_map_block(b: &mut test::Bencher) {
+    let map_block = MapBlock::from_scratch(closest_block(vec3(1., 2., 3.)));
+    let mut assets = AssetManager::default();
+
+    let distinct: std::collections::HashSet<String> = map_block
+        .data
+        .iter()
+        .flatten()
+        .map(|asset| asset.path())
+        .collect();
+
+    map_block.gen_terrain(EMPTY_MASK, &mut assets);
+    assert_eq!(assets.parsed_vox_files(), distinct.len());
+
+    b.iter(|| test::black_box(map_block.gen_terrain(EMPTY_MASK, &mut assets)));
+    assert_eq!(assets.parsed_vox_files(), distinct.len());
+}
+
+/// Generates every cell of a map block with `loader` and waits for all of
+/// them, like the streamer does when it is created.
+#[cfg(test)]
+fn load_cells(loader: &ChunkLoader) -> Vec<(Vec3, SparseTensorChunk)> {
+    let positions: Vec<_> = cells(closest_block(vec3(1., 2., 3.)), FOV).collect();
+    for &pos in &positions {
+        loader.request(pos, cell_asset(pos));
+    }
+    positions.iter().map(|_| loader.wait().unwrap()).collect()
+}
+
+// Together with `load_cells_parallel` this shows the speedup of generating the
+// cells on every core over generating them one after the other.
+#[bench]
+fn load_cells_single_thread(b: &mut test::Bencher) {
+    let loader = ChunkLoader::with_threads(1);
+    b.iter(|| test::black_box(load_cells(&loader)));
+}
+
+#[bench]
+fn load_cells_parallel(b: &mut test::Bencher) {
+    let loader = ChunkLoader::new();
+    b.iter(|| test::black_box(load_cells(&loader)));
+}